@@ -0,0 +1,1003 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use disassemble::{Condition, Instruction, Loc};
+use ihex;
+use image;
+use machine::{Reg, RegPair};
+use symbols::Symbols;
+
+/// How deep INCLUDEs can nest, which stops
+/// a file that includes itself looping forever.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Bytes shown on each line of the listing.
+const LISTING_BYTES: usize = 4;
+
+const MNEMONICS: [&'static str; 78] = [
+    "NOP", "LXI", "STAX", "INX", "INR", "DCR", "MVI", "RLC", "RRC", "RAL", "RAR",
+    "DAD", "LDAX", "DCX", "SHLD", "LHLD", "DAA", "CMA", "STA", "LDA", "STC", "CMC",
+    "MOV", "HLT", "ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP",
+    "RNZ", "RZ", "RNC", "RC", "RPO", "RPE", "RP", "RM", "RET", "POP", "PUSH",
+    "JNZ", "JZ", "JNC", "JC", "JPO", "JPE", "JP", "JM", "JMP",
+    "CNZ", "CZ", "CNC", "CC", "CPO", "CPE", "CP", "CM", "CALL",
+    "ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI", "RST",
+    "OUT", "IN", "XTHL", "PCHL", "XCHG", "SPHL", "DI", "EI",
+];
+
+const DIRECTIVES: [&'static str; 7] = ["ORG", "EQU", "DB", "DW", "DS", "INCLUDE", "END"];
+
+/// A problem with the source, and where it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+/// The output of the assembler.
+pub struct Assembly {
+    /// Runs of assembled bytes, each with
+    /// the address it starts at, in the
+    /// order they appear in the source.
+    pub chunks: Vec<(u16, Vec<u8>)>,
+    /// Labels and EQUs.
+    pub symbols: Symbols,
+    /// The source with the address and
+    /// bytes produced by each line.
+    pub listing: String,
+}
+
+impl Assembly {
+    /// Everything assembled as one flat binary, with any
+    /// gaps zero filled, and the address it starts at.
+    pub fn binary(&self) -> (u16, Vec<u8>) {
+        image::flatten(&self.chunks)
+    }
+
+    /// Everything assembled in Intel HEX format.
+    pub fn hex(&self) -> String {
+        ihex::write(&self.chunks)
+    }
+}
+
+/// A line of source split into its parts, with
+/// the mnemonic or directive in upper case.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Statement {
+    label: Option<String>,
+    op: Option<String>,
+    operands: Vec<String>,
+}
+
+/// A line of source, after INCLUDEs are expanded.
+struct Line {
+    file: String,
+    number: usize,
+    text: String,
+    statement: Statement,
+}
+
+fn is_keyword(word: &str) -> bool {
+    let word = word.to_uppercase();
+    MNEMONICS.contains(&word.as_str()) || DIRECTIVES.contains(&word.as_str())
+}
+
+/// `text` up to any comment, ignoring
+/// semicolons in quoted strings.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ';' => return &text[..i],
+            None => {},
+        }
+    }
+    text
+}
+
+/// Split operands at commas that aren't quoted.
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    if text.trim().is_empty() {
+        return operands;
+    }
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ',' => {
+                operands.push(String::from(text[start..i].trim()));
+                start = i + 1;
+            },
+            None => {},
+        }
+    }
+    operands.push(String::from(text[start..].trim()));
+    operands
+}
+
+/// Split the first whitespace separated word off `text`.
+fn first_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Parse a line into its label, mnemonic and operands. A
+/// label either ends with a colon or starts the line, and
+/// names given to EQU don't need either.
+fn parse_statement(text: &str) -> Statement {
+    let code = strip_comment(text);
+    if code.trim().is_empty() {
+        return Statement::default();
+    }
+    let indented = code.starts_with(char::is_whitespace);
+    let (first, after) = first_word(code);
+    let (label, rest) = if let Some(i) = first.find(':') {
+        let rest = code.trim();
+        (Some(&first[..i]), rest[i + 1..].trim())
+    } else if (!indented && !is_keyword(first)) ||
+        first_word(after).0.to_uppercase() == "EQU" {
+        (Some(first), after)
+    } else {
+        (None, code.trim())
+    };
+    let (op, operands) = first_word(rest);
+    Statement {
+        label: label.map(String::from),
+        op: if op.is_empty() { None } else { Some(op.to_uppercase()) },
+        operands: split_operands(operands),
+    }
+}
+
+/// Read the text of a source file.
+fn read_text(path: &Path) -> Result<String, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    Ok(text)
+}
+
+/// Split source into lines, reading INCLUDEd
+/// files relative to `dir`.
+fn split_source(file: &str, text: &str, dir: Option<&Path>, depth: usize,
+                lines: &mut Vec<Line>) -> Result<(), Error> {
+    for (i, text) in text.lines().enumerate() {
+        let statement = parse_statement(text);
+        if statement.op.as_ref().map(|op| op.as_str()) == Some("INCLUDE") {
+            let error = |message: String| Error { file: String::from(file), line: i + 1, message: message };
+            if statement.operands.len() != 1 {
+                return Err(error(String::from("INCLUDE needs a file name")));
+            }
+            let name = statement.operands[0].trim_matches(|c| c == '"' || c == '\'');
+            let path = match dir {
+                Some(dir) => dir.join(name),
+                None => PathBuf::from(name),
+            };
+            if depth == MAX_INCLUDE_DEPTH {
+                return Err(error(String::from("INCLUDEs nested too deeply")));
+            }
+            let text = read_text(&path).map_err(&error)?;
+            split_source(&path.display().to_string(), &text, path.parent(), depth + 1, lines)?;
+            continue;
+        }
+        lines.push(Line {
+            file: String::from(file),
+            number: i + 1,
+            text: String::from(text),
+            statement: statement,
+        });
+    }
+    Ok(())
+}
+
+/// Tokens of an expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Num(i32),
+    Name(String),
+    Op(String),
+    Open,
+    Close,
+}
+
+/// Parse a number with an optional Intel style
+/// radix suffix, or a 0x or $ hex prefix.
+fn parse_number(text: &str) -> Result<i32, String> {
+    let upper = text.to_uppercase();
+    let (digits, radix) = if upper.starts_with("0X") {
+        (&upper[2..], 16)
+    } else if upper.starts_with('$') {
+        (&upper[1..], 16)
+    } else if upper.ends_with('H') {
+        (&upper[..upper.len() - 1], 16)
+    } else if upper.ends_with('B') {
+        (&upper[..upper.len() - 1], 2)
+    } else if upper.ends_with('O') || upper.ends_with('Q') {
+        (&upper[..upper.len() - 1], 8)
+    } else if upper.ends_with('D') {
+        (&upper[..upper.len() - 1], 10)
+    } else {
+        (&upper[..], 10)
+    };
+    i32::from_str_radix(digits, radix).map_err(|_| format!("invalid number {}", text))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_digit(10) || (c == '$' && i + 1 < chars.len() && chars[i + 1].is_digit(16)) {
+            i += 1;
+            while i < chars.len() && chars[i].is_alphanumeric() {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().cloned().collect();
+            tokens.push(Token::Num(parse_number(&number)?));
+        } else if c == '$' {
+            tokens.push(Token::Name(String::from("$")));
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' || c == '?' || c == '@' || c == '.' {
+            while i < chars.len() &&
+                (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '?' ||
+                 chars[i] == '@' || chars[i] == '.') {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().cloned().collect();
+            match name.to_uppercase().as_str() {
+                "MOD" | "SHL" | "SHR" | "AND" | "OR" | "XOR" | "NOT" | "HIGH" | "LOW" => {
+                    tokens.push(Token::Op(name.to_uppercase()));
+                },
+                _ => tokens.push(Token::Name(name)),
+            }
+        } else if c == '\'' || c == '"' {
+            // A quoted character is its ASCII value
+            if i + 2 < chars.len() && chars[i + 2] == c {
+                tokens.push(Token::Num(chars[i + 1] as i32));
+                i += 3;
+            } else {
+                return Err(format!("invalid character constant in {}", text));
+            }
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            i += 1;
+        } else if (c == '<' || c == '>') && i + 1 < chars.len() && chars[i + 1] == c {
+            tokens.push(Token::Op(if c == '<' { String::from("SHL") } else { String::from("SHR") }));
+            i += 2;
+        } else if "+-*/%&|^~".contains(c) {
+            let op = match c {
+                '%' => "MOD",
+                '&' => "AND",
+                '|' => "OR",
+                '^' => "XOR",
+                '~' => "NOT",
+                _ => "",
+            };
+            tokens.push(Token::Op(if op.is_empty() { c.to_string() } else { String::from(op) }));
+            i += 1;
+        } else {
+            return Err(format!("unexpected {} in {}", c, text));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Evaluates expressions by recursive descent, looking
+/// names up in `symbols` and `$` as the value of `pc`.
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: &'a HashMap<String, i32>,
+    pc: u16,
+}
+
+impl<'a> Evaluator<'a> {
+    fn next_op(&self, ops: &[&str]) -> Option<String> {
+        match self.tokens.get(self.pos) {
+            Some(&Token::Op(ref op)) if ops.contains(&op.as_str()) => Some(op.clone()),
+            _ => None,
+        }
+    }
+
+    /// Parse a left associative chain of binary
+    /// operators in `ops`, with operands parsed by
+    /// `operand`.
+    fn binary<F>(&mut self, ops: &[&str], operand: F) -> Result<i32, String>
+        where F: Fn(&mut Self) -> Result<i32, String>
+    {
+        let mut x = operand(self)?;
+        while let Some(op) = self.next_op(ops) {
+            self.pos += 1;
+            let y = operand(self)?;
+            x = match op.as_str() {
+                "OR" => x | y,
+                "XOR" => x ^ y,
+                "AND" => x & y,
+                "+" => x.wrapping_add(y),
+                "-" => x.wrapping_sub(y),
+                "*" => x.wrapping_mul(y),
+                "/" | "MOD" if y == 0 => return Err(String::from("division by zero")),
+                "/" => x.wrapping_div(y),
+                "MOD" => x.wrapping_rem(y),
+                "SHL" => x.wrapping_shl(y as u32),
+                _ => x.wrapping_shr(y as u32),
+            };
+        }
+        Ok(x)
+    }
+
+    fn or(&mut self) -> Result<i32, String> {
+        self.binary(&["OR", "XOR"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<i32, String> {
+        self.binary(&["AND"], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<i32, String> {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<i32, String> {
+        self.binary(&["*", "/", "MOD", "SHL", "SHR"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<i32, String> {
+        if let Some(op) = self.next_op(&["+", "-", "NOT", "HIGH", "LOW"]) {
+            self.pos += 1;
+            let x = self.unary()?;
+            return Ok(match op.as_str() {
+                "+" => x,
+                "-" => x.wrapping_neg(),
+                "NOT" => !x,
+                "HIGH" => (x >> 8) & 0xff,
+                _ => x & 0xff,
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<i32, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Num(x)) => Ok(x),
+            Some(Token::Name(ref name)) if name == "$" => Ok(self.pc as i32),
+            Some(Token::Name(name)) => {
+                self.symbols.get(&name).cloned().ok_or(format!("undefined symbol {}", name))
+            },
+            Some(Token::Open) => {
+                let x = self.or()?;
+                match self.tokens.get(self.pos) {
+                    Some(&Token::Close) => {
+                        self.pos += 1;
+                        Ok(x)
+                    },
+                    _ => Err(String::from("missing )")),
+                }
+            },
+            _ => Err(String::from("expected a value")),
+        }
+    }
+}
+
+/// Evaluate `expr` with `$` being `pc`.
+fn evaluate(expr: &str, symbols: &HashMap<String, i32>, pc: u16) -> Result<i32, String> {
+    let mut evaluator = Evaluator {
+        tokens: tokenize(expr)?,
+        pos: 0,
+        symbols: symbols,
+        pc: pc,
+    };
+    if evaluator.tokens.is_empty() {
+        return Err(String::from("expected a value"));
+    }
+    let x = evaluator.or()?;
+    if evaluator.pos < evaluator.tokens.len() {
+        return Err(format!("unexpected {:?} in {}", evaluator.tokens[evaluator.pos], expr));
+    }
+    Ok(x)
+}
+
+/// A string in quotes, as allowed by DB.
+fn quoted(operand: &str) -> Option<&str> {
+    for quote in ['\'', '"'].iter() {
+        if operand.len() >= 2 && operand.starts_with(*quote) && operand.ends_with(*quote) &&
+            !operand[1..operand.len() - 1].contains(*quote) {
+            return Some(&operand[1..operand.len() - 1]);
+        }
+    }
+    None
+}
+
+fn loc(operand: &str) -> Result<Loc, String> {
+    match operand.to_uppercase().as_str() {
+        "A" => Ok(Loc::Reg(Reg::A)),
+        "B" => Ok(Loc::Reg(Reg::B)),
+        "C" => Ok(Loc::Reg(Reg::C)),
+        "D" => Ok(Loc::Reg(Reg::D)),
+        "E" => Ok(Loc::Reg(Reg::E)),
+        "H" => Ok(Loc::Reg(Reg::H)),
+        "L" => Ok(Loc::Reg(Reg::L)),
+        "M" => Ok(Loc::Mem),
+        _ => Err(format!("expected a register, found {}", operand)),
+    }
+}
+
+/// The register pairs allowed are `pairs`.
+fn pair(operand: &str, pairs: &[RegPair]) -> Result<RegPair, String> {
+    let rp = match operand.to_uppercase().as_str() {
+        "B" | "BC" => Some(RegPair::BC),
+        "D" | "DE" => Some(RegPair::DE),
+        "H" | "HL" => Some(RegPair::HL),
+        "SP" => Some(RegPair::SP),
+        "PSW" => Some(RegPair::PSW),
+        _ => None,
+    };
+    match rp {
+        Some(rp) if pairs.contains(&rp) => Ok(rp),
+        _ => Err(format!("expected a register pair, found {}", operand)),
+    }
+}
+
+fn condition(cc: &str) -> Option<Condition> {
+    match cc {
+        "NZ" => Some(Condition::NotZero),
+        "Z" => Some(Condition::Zero),
+        "NC" => Some(Condition::NoCarry),
+        "C" => Some(Condition::Carry),
+        "PO" => Some(Condition::ParityOdd),
+        "PE" => Some(Condition::ParityEven),
+        "P" => Some(Condition::Plus),
+        "M" => Some(Condition::Minus),
+        _ => None,
+    }
+}
+
+/// Build the instruction for mnemonic `op`, with
+/// values of operands given by `value`.
+fn instruction<F>(op: &str, operands: &[String], value: F) -> Result<Instruction, String>
+    where F: Fn(&str) -> Result<i32, String>
+{
+    use disassemble::Instruction::*;
+
+    if !MNEMONICS.contains(&op) {
+        return Err(format!("unknown instruction {}", op));
+    }
+    let count = match op {
+        "LXI" | "MVI" | "MOV" => 2,
+        "NOP" | "RLC" | "RRC" | "RAL" | "RAR" | "DAA" | "CMA" | "STC" | "CMC" | "HLT" |
+        "RNZ" | "RZ" | "RNC" | "RC" | "RPO" | "RPE" | "RP" | "RM" | "RET" |
+        "XTHL" | "PCHL" | "XCHG" | "SPHL" | "DI" | "EI" => 0,
+        _ => 1,
+    };
+    if operands.len() != count {
+        return Err(format!("{} takes {} operand{}", op, count, if count == 1 { "" } else { "s" }));
+    }
+    let byte = |operand: &str| {
+        let x = value(operand)?;
+        if x < -128 || x > 255 {
+            return Err(format!("{} doesn't fit in a byte", operand));
+        }
+        Ok(x as u8)
+    };
+    let word = |operand: &str| {
+        let x = value(operand)?;
+        if x < -32768 || x > 65535 {
+            return Err(format!("{} doesn't fit in a word", operand));
+        }
+        Ok(x as u16)
+    };
+    let all = [RegPair::BC, RegPair::DE, RegPair::HL, RegPair::SP];
+    let instr = match op {
+        "NOP" => Nop,
+        "LXI" => Lxi(pair(&operands[0], &all)?, word(&operands[1])?),
+        "STAX" => Stax(pair(&operands[0], &[RegPair::BC, RegPair::DE])?),
+        "INX" => Inx(pair(&operands[0], &all)?),
+        "INR" => Inr(loc(&operands[0])?),
+        "DCR" => Dcr(loc(&operands[0])?),
+        "MVI" => Mvi(loc(&operands[0])?, byte(&operands[1])?),
+        "RLC" => Rlc,
+        "RRC" => Rrc,
+        "RAL" => Ral,
+        "RAR" => Rar,
+        "DAD" => Dad(pair(&operands[0], &all)?),
+        "LDAX" => Ldax(pair(&operands[0], &[RegPair::BC, RegPair::DE])?),
+        "DCX" => Dcx(pair(&operands[0], &all)?),
+        "SHLD" => Shld(word(&operands[0])?),
+        "LHLD" => Lhld(word(&operands[0])?),
+        "DAA" => Daa,
+        "CMA" => Cma,
+        "STA" => Sta(word(&operands[0])?),
+        "LDA" => Lda(word(&operands[0])?),
+        "STC" => Stc,
+        "CMC" => Cmc,
+        "MOV" => {
+            let (dst, src) = (loc(&operands[0])?, loc(&operands[1])?);
+            if dst == Loc::Mem && src == Loc::Mem {
+                return Err(String::from("MOV M,M is not an instruction"));
+            }
+            Mov(dst, src)
+        },
+        "HLT" => Hlt,
+        "ADD" => Add(loc(&operands[0])?),
+        "ADC" => Adc(loc(&operands[0])?),
+        "SUB" => Sub(loc(&operands[0])?),
+        "SBB" => Sbb(loc(&operands[0])?),
+        "ANA" => Ana(loc(&operands[0])?),
+        "XRA" => Xra(loc(&operands[0])?),
+        "ORA" => Ora(loc(&operands[0])?),
+        "CMP" => Cmp(loc(&operands[0])?),
+        "RET" => Ret,
+        "POP" => Pop(pair(&operands[0], &[RegPair::BC, RegPair::DE, RegPair::HL, RegPair::PSW])?),
+        "PUSH" => Push(pair(&operands[0], &[RegPair::BC, RegPair::DE, RegPair::HL, RegPair::PSW])?),
+        "JMP" => Jmp(word(&operands[0])?),
+        "CALL" => Call(word(&operands[0])?),
+        "ADI" => Adi(byte(&operands[0])?),
+        "ACI" => Aci(byte(&operands[0])?),
+        "SUI" => Sui(byte(&operands[0])?),
+        "SBI" => Sbi(byte(&operands[0])?),
+        "ANI" => Ani(byte(&operands[0])?),
+        "XRI" => Xri(byte(&operands[0])?),
+        "ORI" => Ori(byte(&operands[0])?),
+        "CPI" => Cpi(byte(&operands[0])?),
+        "RST" => {
+            let n = value(&operands[0])?;
+            if n < 0 || n > 7 {
+                return Err(format!("RST {} is not 0 to 7", n));
+            }
+            Rst(n as u8)
+        },
+        "OUT" => Out(byte(&operands[0])?),
+        "IN" => In(byte(&operands[0])?),
+        "XTHL" => Xthl,
+        "PCHL" => Pchl,
+        "XCHG" => Xchg,
+        "SPHL" => Sphl,
+        "DI" => Di,
+        "EI" => Ei,
+        _ => {
+            // Conditional returns, jumps and calls
+            let cc = condition(&op[1..]).ok_or(format!("unknown instruction {}", op))?;
+            match &op[..1] {
+                "R" => Rcc(cc),
+                "J" => Jcc(cc, word(&operands[0])?),
+                "C" => Ccc(cc, word(&operands[0])?),
+                _ => return Err(format!("unknown instruction {}", op)),
+            }
+        },
+    };
+    Ok(instr)
+}
+
+/// Assembler state carried between the passes.
+struct Assembler {
+    symbols: HashMap<String, i32>,
+    /// Symbol names in the order defined.
+    names: Vec<String>,
+    chunks: Vec<(u16, Vec<u8>)>,
+    listing: String,
+}
+
+impl Assembler {
+    fn define(&mut self, name: &str, x: i32) -> Result<(), String> {
+        if self.symbols.contains_key(name) {
+            return Err(format!("{} is already defined", name));
+        }
+        self.symbols.insert(String::from(name), x);
+        self.names.push(String::from(name));
+        Ok(())
+    }
+
+    /// Evaluate an expression that must only use symbols
+    /// already defined, as it affects addresses.
+    fn address(&self, expr: &str, pc: u16) -> Result<u16, String> {
+        evaluate(expr, &self.symbols, pc)
+            .map_err(|e| format!("{}, it must be defined before use here", e))
+            .map(|x| x as u16)
+    }
+
+    /// Work out the address of every label and the
+    /// value of every EQU. EQUs can refer to symbols
+    /// defined later, those are left to the end.
+    fn first_pass(&mut self, lines: &[Line]) -> Result<(), Error> {
+        let mut pc: u16 = 0;
+        let mut equs = Vec::new();
+        for line in lines.iter() {
+            let error = |message: String| Error { file: line.file.clone(), line: line.number, message: message };
+            let statement = &line.statement;
+            let op = statement.op.as_ref().map(|op| op.as_str());
+            if op == Some("EQU") {
+                let name = statement.label.as_ref().ok_or(error(String::from("EQU needs a name")))?;
+                if statement.operands.len() != 1 {
+                    return Err(error(String::from("EQU takes 1 operand")));
+                }
+                // Defined now if it can be, so ORG and DS can use
+                // it, otherwise once what it refers to is known
+                match evaluate(&statement.operands[0], &self.symbols, pc) {
+                    Ok(x) => self.define(name, x).map_err(&error)?,
+                    Err(_) => equs.push((line, name.clone(), pc)),
+                }
+                continue;
+            }
+            if let Some(ref label) = statement.label {
+                self.define(label, pc as i32).map_err(&error)?;
+            }
+            pc = match op {
+                None => pc,
+                Some("END") => break,
+                Some("ORG") => self.address(statement.operands.get(0).map(|x| x.as_str()).unwrap_or(""), pc).map_err(&error)?,
+                Some("DS") => {
+                    let size = self.address(statement.operands.get(0).map(|x| x.as_str()).unwrap_or(""), pc).map_err(&error)?;
+                    pc.wrapping_add(size)
+                },
+                Some("DB") => pc.wrapping_add(self.db(&statement.operands, pc, false).map_err(&error)?.len() as u16),
+                Some("DW") => pc.wrapping_add(statement.operands.len() as u16 * 2),
+                Some(op) => {
+                    let instr = instruction(op, &statement.operands, |_| Ok(0)).map_err(&error)?;
+                    pc.wrapping_add(instr.size() as u16)
+                },
+            };
+        }
+        // Evaluate EQUs until no more can be
+        while !equs.is_empty() {
+            let before = equs.len();
+            let mut failed = Vec::new();
+            let mut last_error = None;
+            for (line, name, pc) in equs {
+                match evaluate(&line.statement.operands[0], &self.symbols, pc) {
+                    Ok(x) => {
+                        let error = |message: String| Error { file: line.file.clone(), line: line.number, message: message };
+                        self.define(&name, x).map_err(&error)?;
+                    },
+                    Err(e) => {
+                        last_error = Some(Error { file: line.file.clone(), line: line.number, message: e });
+                        failed.push((line, name, pc));
+                    },
+                }
+            }
+            if failed.len() == before {
+                return Err(last_error.unwrap());
+            }
+            equs = failed;
+        }
+        Ok(())
+    }
+
+    /// The bytes of a DB, with `strict` requiring
+    /// every symbol to be defined.
+    fn db(&self, operands: &[String], pc: u16, strict: bool) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        for operand in operands.iter() {
+            if let Some(text) = quoted(operand) {
+                if text.chars().count() != 1 {
+                    bytes.extend(text.bytes());
+                    continue;
+                }
+            }
+            let x = match evaluate(operand, &self.symbols, pc) {
+                Ok(x) => x,
+                Err(e) => if strict { return Err(e) } else { 0 },
+            };
+            if x < -128 || x > 255 {
+                return Err(format!("{} doesn't fit in a byte", operand));
+            }
+            bytes.push(x as u8);
+        }
+        Ok(bytes)
+    }
+
+    /// Add `bytes` at `addr` to the output.
+    fn emit(&mut self, addr: u16, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let contiguous = match self.chunks.last() {
+            Some(&(start, ref chunk)) => start as usize + chunk.len() == addr as usize,
+            None => false,
+        };
+        if contiguous {
+            self.chunks.last_mut().unwrap().1.extend_from_slice(bytes);
+        } else {
+            self.chunks.push((addr, bytes.to_vec()));
+        }
+    }
+
+    /// Add a source line to the listing, with
+    /// the bytes it produced.
+    fn list(&mut self, addr: Option<u16>, bytes: &[u8], text: &str) {
+        let hex: Vec<String> = bytes.iter().take(LISTING_BYTES).map(|x| format!("{:02X}", x)).collect();
+        let mut row = match addr {
+            Some(addr) => format!("{:04X}  {:<12}{}", addr, hex.join(" "), text),
+            None => format!("      {:<12}{}", hex.join(" "), text),
+        };
+        while row.ends_with(' ') {
+            row.pop();
+        }
+        self.listing.push_str(&row);
+        self.listing.push('\n');
+        let start = addr.unwrap_or(0);
+        for (i, chunk) in bytes.chunks(LISTING_BYTES).enumerate().skip(1) {
+            let hex: Vec<String> = chunk.iter().map(|x| format!("{:02X}", x)).collect();
+            let addr = start.wrapping_add((i * LISTING_BYTES) as u16);
+            self.listing.push_str(&format!("{:04X}  {}\n", addr, hex.join(" ")));
+        }
+    }
+
+    /// Assemble every line now all symbols are known.
+    fn second_pass(&mut self, lines: &[Line]) -> Result<(), Error> {
+        let mut pc: u16 = 0;
+        for line in lines.iter() {
+            let error = |message: String| Error { file: line.file.clone(), line: line.number, message: message };
+            let statement = &line.statement;
+            let op = statement.op.as_ref().map(|op| op.as_str());
+            let bytes = match op {
+                None => {
+                    let addr = statement.label.as_ref().map(|_| pc);
+                    self.list(addr, &[], &line.text);
+                    continue;
+                },
+                Some("END") => {
+                    self.list(None, &[], &line.text);
+                    break;
+                },
+                Some("EQU") => {
+                    let x = self.symbols[statement.label.as_ref().unwrap()];
+                    let text = format!("= {:04X}     {}", x as u16, line.text);
+                    self.listing.push_str(&format!("      {}\n", text));
+                    continue;
+                },
+                Some("ORG") => {
+                    pc = self.address(&statement.operands[0], pc).map_err(&error)?;
+                    self.list(Some(pc), &[], &line.text);
+                    continue;
+                },
+                Some("DS") => {
+                    self.list(Some(pc), &[], &line.text);
+                    pc = pc.wrapping_add(self.address(&statement.operands[0], pc).map_err(&error)?);
+                    continue;
+                },
+                Some("DB") => self.db(&statement.operands, pc, true).map_err(&error)?,
+                Some("DW") => {
+                    let mut bytes = Vec::new();
+                    for operand in statement.operands.iter() {
+                        let x = evaluate(operand, &self.symbols, pc).map_err(&error)?;
+                        if x < -32768 || x > 65535 {
+                            return Err(error(format!("{} doesn't fit in a word", operand)));
+                        }
+                        bytes.push((x & 0xff) as u8);
+                        bytes.push(((x >> 8) & 0xff) as u8);
+                    }
+                    bytes
+                },
+                Some(op) => {
+                    let symbols = &self.symbols;
+                    instruction(op, &statement.operands, |expr| evaluate(expr, symbols, pc))
+                        .map_err(&error)?
+                        .encode()
+                },
+            };
+            self.emit(pc, &bytes);
+            self.list(Some(pc), &bytes, &line.text);
+            pc = pc.wrapping_add(bytes.len() as u16);
+        }
+        Ok(())
+    }
+
+    fn assemble(lines: &[Line]) -> Result<Assembly, Error> {
+        let mut assembler = Assembler {
+            symbols: HashMap::new(),
+            names: Vec::new(),
+            chunks: Vec::new(),
+            listing: String::new(),
+        };
+        assembler.first_pass(lines)?;
+        assembler.second_pass(lines)?;
+        let mut symbols = Symbols::new();
+        for name in assembler.names.iter() {
+            symbols.insert(assembler.symbols[name] as u16, name);
+        }
+        Ok(Assembly {
+            chunks: assembler.chunks,
+            symbols: symbols,
+            listing: assembler.listing,
+        })
+    }
+}
+
+/// Assemble 8080 source. INCLUDEd files are
+/// found relative to the current directory.
+pub fn assemble(source: &str) -> Result<Assembly, Error> {
+    let mut lines = Vec::new();
+    split_source("<source>", source, None, 0, &mut lines)?;
+    Assembler::assemble(&lines)
+}
+
+/// Assemble the 8080 source file at `path`. INCLUDEd
+/// files are found relative to the file including them.
+pub fn assemble_file(path: &Path) -> Result<Assembly, Error> {
+    let text = read_text(path)
+        .map_err(|message| Error { file: path.display().to_string(), line: 0, message: message })?;
+    let mut lines = Vec::new();
+    split_source(&path.display().to_string(), &text, path.parent(), 0, &mut lines)?;
+    Assembler::assemble(&lines)
+}
+
+#[cfg(test)]
+fn assemble_binary(source: &str) -> Vec<u8> {
+    match assemble(source) {
+        Ok(assembly) => assembly.binary().1,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn test_parse_statement() {
+    let statement = parse_statement("loop:   MVI A, ';' ; comment");
+    assert_eq!(statement.label, Some(String::from("loop")));
+    assert_eq!(statement.op, Some(String::from("MVI")));
+    assert_eq!(statement.operands, vec![String::from("A"), String::from("';'")]);
+
+    let statement = parse_statement("START   lxi sp,stack");
+    assert_eq!(statement.label, Some(String::from("START")));
+    assert_eq!(statement.op, Some(String::from("LXI")));
+
+    let statement = parse_statement("    ret");
+    assert_eq!(statement.label, None);
+    assert_eq!(statement.op, Some(String::from("RET")));
+
+    let statement = parse_statement("  BDOS EQU 5");
+    assert_eq!(statement.label, Some(String::from("BDOS")));
+    assert_eq!(statement.op, Some(String::from("EQU")));
+
+    assert_eq!(parse_statement("; just a comment"), Statement::default());
+}
+
+#[test]
+fn test_expressions() {
+    let mut symbols = HashMap::new();
+    symbols.insert(String::from("BASE"), 0x2000);
+    assert_eq!(evaluate("BASE + 10H * 2", &symbols, 0), Ok(0x2020));
+    assert_eq!(evaluate("(BASE + 1) AND 0FFH", &symbols, 0), Ok(0x01));
+    assert_eq!(evaluate("HIGH BASE", &symbols, 0), Ok(0x20));
+    assert_eq!(evaluate("$ + 3", &symbols, 0x100), Ok(0x103));
+    assert_eq!(evaluate("'A' - 1", &symbols, 0), Ok(0x40));
+    assert_eq!(evaluate("1 SHL 4 OR 101B", &symbols, 0), Ok(0x15));
+    assert_eq!(evaluate("-1", &symbols, 0), Ok(-1));
+    assert_eq!(evaluate("0x1f + $10 + 17Q", &symbols, 0), Ok(0x3e));
+    assert!(evaluate("MISSING", &symbols, 0).is_err());
+    assert!(evaluate("(1", &symbols, 0).is_err());
+}
+
+#[test]
+fn test_assemble() {
+    let source = "
+BDOS    EQU     0005H
+        ORG     0100H
+START:  LXI     D,MSG
+        MVI     C,9
+        CALL    BDOS
+        JMP     DONE        ; forward reference
+MSG:    DB      'Hi$',0DH,LEN
+LEN     EQU     $-MSG
+DONE:   RET
+        DW      START,-1
+";
+    let assembly = assemble(source).unwrap();
+    assert_eq!(assembly.binary(), (0x100, vec![
+        0x11, 0x0b, 0x01,
+        0x0e, 0x09,
+        0xcd, 0x05, 0x00,
+        0xc3, 0x10, 0x01,
+        b'H', b'i', b'$', 0x0d, 0x05,
+        0xc9,
+        0x00, 0x01, 0xff, 0xff,
+    ]));
+    assert_eq!(assembly.symbols.addr("DONE"), Some(0x110));
+    assert_eq!(assembly.symbols.addr("LEN"), Some(5));
+}
+
+#[test]
+fn test_assemble_org_and_ds() {
+    let assembly = assemble("
+        ORG     10H
+        DB      1
+        DS      2
+        DB      2
+        ORG     0
+        DB      3
+").unwrap();
+    assert_eq!(assembly.chunks, vec![(0x10, vec![1]), (0x13, vec![2]), (0x00, vec![3])]);
+    let (start, binary) = assembly.binary();
+    assert_eq!(start, 0);
+    assert_eq!(binary.len(), 0x14);
+    assert_eq!(&binary[0x10..], &[1, 0, 0, 2]);
+}
+
+#[test]
+fn test_assemble_errors() {
+    let error = assemble("  NOP\n  MVI A,100H\n").err().unwrap();
+    assert_eq!(error.line, 2);
+    assert!(assemble("  JMP NOWHERE").is_err());
+    assert!(assemble("  MOV M,M").is_err());
+    assert!(assemble("  POP SP").is_err());
+    assert!(assemble("  FOO A").is_err());
+    assert!(assemble("X: NOP\nX: NOP").is_err());
+    // Only a forward reference can't set an address
+    let error = assemble("  ORG LATER\nLATER: NOP").err().unwrap();
+    assert_eq!(error.message, "undefined symbol LATER, it must be defined before use here");
+    assert!(assemble("  DS SIZE\nSIZE EQU 4").is_err());
+    assert!(assemble("X EQU 1\nX EQU 2").is_err());
+    assert!(assemble("  DW 1/0").is_err());
+    // The one overflowing division wraps rather than panicking
+    assert!(assemble("  DW (1 SHL 31)/-1").is_err());
+    assert_eq!(assemble("  DW (1 SHL 31) MOD -1").unwrap().chunks, vec![(0, vec![0, 0])]);
+}
+
+#[test]
+fn test_assemble_equ_before_use() {
+    let assembly = assemble("
+TPA     EQU     100H
+SIZE    EQU     TPA/80H
+        ORG     TPA
+        DB      1
+        DS      SIZE
+LATE    EQU     LATER+1
+LATER:  DB      LATE-100H
+").unwrap();
+    assert_eq!(assembly.chunks, vec![(0x100, vec![1]), (0x103, vec![0x04])]);
+}
+
+#[test]
+fn test_listing() {
+    let assembly = assemble("        ORG 100H\nSTART:  LXI H,1234H\n        DB 1,2,3,4,5\n").unwrap();
+    let listing: Vec<&str> = assembly.listing.lines().collect();
+    assert_eq!(listing, vec![
+        "0100                      ORG 100H",
+        "0100  21 34 12    START:  LXI H,1234H",
+        "0103  01 02 03 04         DB 1,2,3,4,5",
+        "0107  05",
+    ]);
+}
+
+#[test]
+fn test_disassembly_round_trip() {
+    use disassemble::Listing;
+
+    // Every opcode, undocumented ones included
+    let mut bytes = Vec::new();
+    for op in 0..256 {
+        bytes.push(op as u8);
+        bytes.push(0x34);
+        bytes.push(0x12);
+    }
+    let asm = Listing::linear(&bytes, 0x100).to_asm();
+    assert_eq!(assemble_binary(&asm), bytes);
+}
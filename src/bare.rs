@@ -0,0 +1,164 @@
+use cpu::{ConditionCodes, Cpu, Registers};
+use machine::Machine;
+use memory::{Access, Memory};
+
+/// An 8080 with 64K of RAM and nothing else, for
+/// monitor ROMs and test programs that bring their
+/// own everything. `OUT` goes nowhere and `IN`
+/// reads 0xff, as from an empty bus.
+pub struct Bare {
+    pub cpu: Cpu,
+}
+
+impl Bare {
+    /// Load `data` at `addr` and start running there.
+    pub fn new(data: &[u8], addr: u16) -> Self {
+        let mut cpu = Cpu::new(Memory::with_data_and_offset(data, addr as usize));
+        cpu.pc = addr;
+        Bare {
+            cpu: cpu,
+        }
+    }
+
+    /// Copy `data` into memory at `addr`.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        self.cpu.mem.load(addr, data);
+    }
+}
+
+impl Machine for Bare {
+    /// Step the Machine by a single instruction.
+    #[inline(always)]
+    fn step(&mut self) -> u32 {
+        if self.cpu.halted {
+            return self.cpu.step();
+        }
+        match self.cpu.mem.read(self.cpu.pc) {
+            0xd3 => { // OUT D8
+                self.cpu.pc = self.cpu.pc.wrapping_add(2);
+                10
+            },
+            0xdb => { // IN D8
+                self.cpu.a = 0xff;
+                self.cpu.pc = self.cpu.pc.wrapping_add(2);
+                10
+            },
+            _ => self.cpu.step(),
+        }
+    }
+
+    #[inline(always)]
+    fn interrupt(&mut self, int: usize) {
+        self.cpu.interrupt(int as u8);
+    }
+
+    #[inline(always)]
+    fn get_pc(&self) -> u16 { self.cpu.pc }
+
+    #[inline(always)]
+    fn set_pc(&mut self, value: u16) { self.cpu.pc = value; }
+
+    #[inline(always)]
+    fn get_sp(&self) -> u16 { self.cpu.sp }
+    #[inline(always)]
+    fn set_sp(&mut self, value: u16) { self.cpu.sp = value; }
+
+    #[inline(always)]
+    fn get_flags(&self) -> u8 { self.cpu.cc.to_byte() }
+    #[inline(always)]
+    fn set_flags(&mut self, value: u8) { self.cpu.cc = ConditionCodes::from_byte(value); }
+
+    #[inline(always)]
+    fn get_interrupt_enabled(&self) -> bool { self.cpu.interrupt_enabled }
+
+    #[inline(always)]
+    fn get_a(&self) -> u8 { self.cpu.a }
+    #[inline(always)]
+    fn set_a(&mut self, value: u8) { self.cpu.a = value; }
+
+    #[inline(always)]
+    fn get_b(&self) -> u8 { self.cpu.b }
+    #[inline(always)]
+    fn set_b(&mut self, value: u8) { self.cpu.b = value; }
+
+    #[inline(always)]
+    fn get_c(&self) -> u8 { self.cpu.c }
+    #[inline(always)]
+    fn set_c(&mut self, value: u8) { self.cpu.c = value; }
+
+    #[inline(always)]
+    fn get_d(&self) -> u8 { self.cpu.d }
+    #[inline(always)]
+    fn set_d(&mut self, value: u8) { self.cpu.d = value; }
+
+    #[inline(always)]
+    fn get_e(&self) -> u8 { self.cpu.e }
+    #[inline(always)]
+    fn set_e(&mut self, value: u8) { self.cpu.e = value; }
+
+    #[inline(always)]
+    fn get_h(&self) -> u8 { self.cpu.h }
+    #[inline(always)]
+    fn set_h(&mut self, value: u8) { self.cpu.h = value; }
+
+    #[inline(always)]
+    fn get_l(&self) -> u8 { self.cpu.l }
+    #[inline(always)]
+    fn set_l(&mut self, value: u8) { self.cpu.l = value; }
+
+    #[inline(always)]
+    fn read(&self, addr: u16) -> u8 {
+        self.cpu.mem.read(addr)
+    }
+
+    #[inline(always)]
+    fn write(&mut self, addr: u16, value: u8) {
+        self.cpu.mem.write(addr, value);
+    }
+
+    fn set_watches(&mut self, addrs: &[u16]) {
+        self.cpu.mem.set_watches(addrs);
+    }
+
+    fn take_accesses(&mut self) -> Vec<(u16, Access)> {
+        self.cpu.mem.take_accesses()
+    }
+
+    fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    fn set_registers(&mut self, registers: &Registers) {
+        self.cpu.set_registers(registers);
+    }
+
+    type Snapshot = Registers;
+
+    fn snapshot(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    fn restore(&mut self, snapshot: &Registers) {
+        self.cpu.set_registers(snapshot);
+    }
+
+    fn set_journal(&mut self, on: bool) {
+        self.cpu.mem.set_journal(on);
+    }
+
+    fn take_journal(&mut self) -> Vec<(u16, u8)> {
+        self.cpu.mem.take_journal()
+    }
+}
+
+#[test]
+fn test_bare_io() {
+    // IN 10h; OUT 20h; HLT
+    let mut machine = Bare::new(&[0xdb, 0x10, 0xd3, 0x20, 0x76], 0xf000);
+    assert_eq!(machine.get_pc(), 0xf000);
+    machine.step();
+    assert_eq!(machine.get_a(), 0xff);
+    machine.step();
+    machine.step();
+    assert!(machine.cpu.halted);
+}
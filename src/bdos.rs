@@ -0,0 +1,776 @@
+use std::cmp;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use console::{Console, CR, EOF, LF};
+use cpu::Cpu;
+
+/// Size of a CP/M record in bytes.
+pub const RECORD_SIZE: usize = 128;
+
+/// Records in a logical extent.
+const EXTENT_RECORDS: u32 = 128;
+
+/// Default DMA buffer address.
+pub const DEFAULT_DMA: u16 = 0x0080;
+
+// Offsets of fields in a file control block.
+const FCB_DR: u16 = 0;
+const FCB_NAME: u16 = 1;
+const FCB_EX: u16 = 12;
+const FCB_S2: u16 = 14;
+const FCB_RC: u16 = 15;
+const FCB_CR: u16 = 32;
+const FCB_R0: u16 = 33;
+
+/// A file name as stored in an FCB, 8 bytes of
+/// name and 3 of type padded with spaces.
+type Name = [u8; 11];
+
+/// Read the name out of the FCB at `fcb` with the
+/// attribute bits in the high bit of each byte removed.
+fn fcb_name(cpu: &Cpu, fcb: u16) -> Name {
+    let mut name = [b' '; 11];
+    for i in 0..11 {
+        name[i] = cpu.mem.read(fcb + FCB_NAME + i as u16) & 0x7f;
+    }
+    name
+}
+
+/// Convert a host file name into an FCB name. Returns
+/// `None` if the name can't be represented in 8.3 form.
+fn host_to_name(host: &str) -> Option<Name> {
+    let (base, ext) = match host.rfind('.') {
+        Some(i) => (&host[..i], &host[i + 1..]),
+        None => (host, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+    let valid = |c: u8| c > b' ' && c <= b'~' && !b"<>.,;:=?*[]".contains(&c);
+    if !base.bytes().chain(ext.bytes()).all(valid) {
+        return None;
+    }
+    let mut name = [b' '; 11];
+    for (i, c) in base.bytes().enumerate() {
+        name[i] = c.to_ascii_uppercase();
+    }
+    for (i, c) in ext.bytes().enumerate() {
+        name[8 + i] = c.to_ascii_uppercase();
+    }
+    Some(name)
+}
+
+/// Convert an FCB name into the name
+/// used for the file on the host.
+fn name_to_host(name: &Name) -> String {
+    let base = String::from_utf8_lossy(&name[..8]).trim_end_matches(' ').to_string();
+    let ext = String::from_utf8_lossy(&name[8..]).trim_end_matches(' ').to_string();
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+/// Does `name` match `pattern`? A '?' in
+/// the pattern matches any character.
+fn matches(pattern: &Name, name: &Name) -> bool {
+    pattern.iter().zip(name.iter()).all(|(&p, &n)| p == b'?' || p == n)
+}
+
+/// Number of records needed to hold `len` bytes.
+fn records(len: u64) -> u32 {
+    ((len + RECORD_SIZE as u64 - 1) / RECORD_SIZE as u64) as u32
+}
+
+/// Echo a character read from the console
+/// unless the terminal has already done so.
+fn echo<C: Console>(console: &mut C, c: u8) {
+    if !console.echoes_input() && c >= b' ' {
+        console.write(c);
+    }
+}
+
+/// Read a line of input in to the buffer at `addr`.
+/// The first byte holds its size, the second
+/// receives the number of characters read.
+/// Returns false if ^C was typed at the start
+/// of the line, which reboots the system.
+fn read_buffer<C: Console>(cpu: &mut Cpu, console: &mut C, addr: u16) -> bool {
+    let max = cpu.mem.read(addr) as usize;
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        let c = console.read();
+        match c {
+            CR | LF | EOF => break,
+            0x03 if line.is_empty() => return false,
+            0x08 | 0x7f => {
+                if line.pop().is_some() && !console.echoes_input() {
+                    for &x in b"\x08 \x08" {
+                        console.write(x);
+                    }
+                }
+            },
+            c => {
+                if line.len() < max {
+                    line.push(c);
+                    echo(console, c);
+                }
+                if line.len() == max {
+                    break;
+                }
+            },
+        }
+    }
+    if !console.echoes_input() {
+        console.write(CR);
+        console.write(LF);
+    }
+    cpu.mem.write(addr + 1, line.len() as u8);
+    for (i, c) in line.iter().enumerate() {
+        cpu.mem.write(addr + 2 + i as u16, *c);
+    }
+    true
+}
+
+/// Emulation of the CP/M 2.2 Basic Disk Operating System.
+///
+/// Drives are mapped on to directories on the host,
+/// drive A is the root directory given and drives B
+/// to P are the subdirectories `B` to `P` within it.
+/// File names are matched without regard to case and
+/// host files that don't have 8.3 names are ignored.
+///
+/// No state is kept for open files, every access goes
+/// to the host file named in the FCB at the position
+/// given by its extent and record fields.
+pub struct Bdos {
+    root: PathBuf,
+    dma: u16,
+    drive: u8,
+    user: u8,
+    iobyte: u8,
+    /// Files still to be returned by Search Next.
+    search: Vec<(Name, u32)>,
+    /// Addresses of the BIOS disk parameter block
+    /// and allocation vector given to programs.
+    dpb: u16,
+    alv: u16,
+}
+
+impl Bdos {
+    pub fn new(root: &Path) -> Self {
+        Bdos {
+            root: root.to_path_buf(),
+            dma: DEFAULT_DMA,
+            drive: 0,
+            user: 0,
+            iobyte: 0,
+            search: Vec::new(),
+            dpb: 0,
+            alv: 0,
+        }
+    }
+
+    /// Use the disk parameter block and allocation
+    /// vector the BIOS installed at `dpb` and `alv`
+    /// for Get DPB and Get Allocation Vector.
+    pub fn set_disk_tables(&mut self, dpb: u16, alv: u16) {
+        self.dpb = dpb;
+        self.alv = alv;
+    }
+
+    /// Currently selected drive, 0 is A.
+    pub fn drive(&self) -> u8 {
+        self.drive
+    }
+
+    /// Currently selected user number.
+    pub fn user(&self) -> u8 {
+        self.user
+    }
+
+    /// Perform the BDOS function in register C with
+    /// the parameter in DE, leaving the result in A
+    /// and HL. Returns false if the program asked to
+    /// be terminated.
+    pub fn call<C: Console>(&mut self, cpu: &mut Cpu, console: &mut C) -> bool {
+        let de = cpu.de();
+        let e = cpu.e;
+        let result: u16 = match cpu.c {
+            0 => return false, // System reset
+            1 => { // Console input
+                let c = console.read();
+                echo(console, c);
+                c as u16
+            },
+            2 => { // Console output
+                console.write(e);
+                0
+            },
+            3 => EOF as u16, // Reader input
+            4 | 5 => 0, // Punch and list output are discarded
+            6 => { // Direct console I/O
+                match e {
+                    0xff => if console.status() { console.read() as u16 } else { 0 },
+                    0xfe => if console.status() { 0xff } else { 0 },
+                    c => { console.write(c); 0 },
+                }
+            },
+            7 => self.iobyte as u16, // Get IOBYTE
+            8 => { // Set IOBYTE
+                self.iobyte = e;
+                0
+            },
+            9 => { // Print string terminated by '$'
+                let mut addr = de;
+                loop {
+                    let c = cpu.mem.read(addr);
+                    if c == b'$' {
+                        break;
+                    }
+                    console.write(c);
+                    addr = addr.wrapping_add(1);
+                }
+                0
+            },
+            10 => { // Read console buffer
+                if !read_buffer(cpu, console, de) {
+                    return false;
+                }
+                0
+            },
+            11 => if console.status() { 0xff } else { 0 }, // Console status
+            12 => 0x0022, // Return version number, CP/M 2.2
+            13 => { // Reset disk system
+                self.dma = DEFAULT_DMA;
+                self.drive = 0;
+                0
+            },
+            14 => { // Select disk
+                if self.drive_dir(e).is_dir() {
+                    self.drive = e;
+                    0
+                } else {
+                    0xff
+                }
+            },
+            15 => self.open(cpu, de), // Open file
+            16 => self.close(cpu, de), // Close file
+            17 => self.search_first(cpu, de), // Search for first
+            18 => self.search_next(cpu), // Search for next
+            19 => self.delete(cpu, de), // Delete file
+            20 => self.read_sequential(cpu, de), // Read sequential
+            21 => self.write_sequential(cpu, de), // Write sequential
+            22 => self.make(cpu, de), // Make file
+            23 => self.rename(cpu, de), // Rename file
+            24 => { // Return login vector
+                (0..16).filter(|&d| self.drive_dir(d).is_dir())
+                    .fold(0, |acc, d| acc | (1 << d))
+            },
+            25 => self.drive as u16, // Return current disk
+            26 => { // Set DMA address
+                self.dma = de;
+                0
+            },
+            27 => self.alv, // Get allocation vector address
+            28 => 0, // Write protect disk
+            29 => 0, // Get read only vector
+            30 => { // Set file attributes
+                if self.find(cpu, de).is_some() { 0 } else { 0xff }
+            },
+            31 => self.dpb, // Get disk parameter block address
+            32 => { // Get or set user code
+                if e == 0xff {
+                    self.user as u16
+                } else {
+                    self.user = e & 0x0f;
+                    0
+                }
+            },
+            33 => self.read_random(cpu, de), // Read random
+            34 | 40 => self.write_random(cpu, de), // Write random (with zero fill)
+            35 => self.compute_size(cpu, de), // Compute file size
+            36 => { // Set random record
+                let record = self.fcb_record(cpu, de);
+                self.set_random_record(cpu, de, record);
+                0
+            },
+            37 => 0, // Reset drive
+            // Programs probe for later versions' functions,
+            // CP/M 2.2 returns 0xff for any it doesn't have
+            _ => 0xff,
+        };
+        // Results are returned in both A and
+        // L, and B and H, for compatibility.
+        cpu.set_hl(result);
+        cpu.a = cpu.l;
+        cpu.b = cpu.h;
+        true
+    }
+
+    /// Directory on the host holding the files for `drive`.
+    fn drive_dir(&self, drive: u8) -> PathBuf {
+        if drive == 0 {
+            self.root.clone()
+        } else {
+            self.root.join(((b'A' + drive) as char).to_string())
+        }
+    }
+
+    /// Directory on the host for the drive named in the FCB.
+    fn fcb_dir(&self, cpu: &Cpu, fcb: u16) -> PathBuf {
+        match cpu.mem.read(fcb + FCB_DR) {
+            dr if dr >= 1 && dr <= 16 => self.drive_dir(dr - 1),
+            _ => self.drive_dir(self.drive),
+        }
+    }
+
+    /// List the files in `dir` whose names match `pattern`,
+    /// sorted by name, along with their size in records.
+    fn list(&self, dir: &Path, pattern: &Name) -> Vec<(Name, PathBuf, u32)> {
+        let mut files = Vec::new();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return files,
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let name = match entry.file_name().to_str().and_then(host_to_name) {
+                Some(name) => name,
+                None => continue,
+            };
+            if matches(pattern, &name) {
+                files.push((name, entry.path(), records(metadata.len())));
+            }
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        files
+    }
+
+    /// Find the host file for the FCB at `fcb`, taking
+    /// the first match if the name has wildcards.
+    fn find(&self, cpu: &Cpu, fcb: u16) -> Option<(Name, PathBuf, u32)> {
+        let dir = self.fcb_dir(cpu, fcb);
+        let name = fcb_name(cpu, fcb);
+        self.list(&dir, &name).into_iter().next()
+    }
+
+    /// Path on the host for the exact name in the FCB.
+    fn fcb_path(&self, cpu: &Cpu, fcb: u16) -> PathBuf {
+        match self.find(cpu, fcb) {
+            Some((_, path, _)) => path,
+            None => self.fcb_dir(cpu, fcb).join(name_to_host(&fcb_name(cpu, fcb))),
+        }
+    }
+
+    /// The record the FCB's sequential position points at.
+    fn fcb_record(&self, cpu: &Cpu, fcb: u16) -> u32 {
+        let ex = (cpu.mem.read(fcb + FCB_EX) & 0x1f) as u32;
+        let s2 = (cpu.mem.read(fcb + FCB_S2) & 0x3f) as u32;
+        let cr = cpu.mem.read(fcb + FCB_CR) as u32;
+        (s2 << 12 | ex << 7) + cr
+    }
+
+    /// Point the FCB's sequential position at `record`, updating
+    /// the record count for the extent that contains it.
+    fn set_fcb_record(&self, cpu: &mut Cpu, fcb: u16, record: u32, size: u32) {
+        let extent_start = record & !(EXTENT_RECORDS - 1);
+        let rc = cmp::min(size.saturating_sub(extent_start), EXTENT_RECORDS);
+        cpu.mem.write(fcb + FCB_EX, ((record >> 7) & 0x1f) as u8);
+        cpu.mem.write(fcb + FCB_S2, ((record >> 12) & 0x3f) as u8);
+        cpu.mem.write(fcb + FCB_RC, rc as u8);
+        cpu.mem.write(fcb + FCB_CR, (record & 0x7f) as u8);
+    }
+
+    fn random_record(&self, cpu: &Cpu, fcb: u16) -> u32 {
+        let r0 = cpu.mem.read(fcb + FCB_R0) as u32;
+        let r1 = cpu.mem.read(fcb + FCB_R0 + 1) as u32;
+        let r2 = cpu.mem.read(fcb + FCB_R0 + 2) as u32;
+        r2 << 16 | r1 << 8 | r0
+    }
+
+    fn set_random_record(&self, cpu: &mut Cpu, fcb: u16, record: u32) {
+        cpu.mem.write(fcb + FCB_R0, (record & 0xff) as u8);
+        cpu.mem.write(fcb + FCB_R0 + 1, ((record >> 8) & 0xff) as u8);
+        cpu.mem.write(fcb + FCB_R0 + 2, ((record >> 16) & 0xff) as u8);
+    }
+
+    fn open(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        match self.find(cpu, fcb) {
+            Some((name, _, size)) => {
+                for (i, c) in name.iter().enumerate() {
+                    cpu.mem.write(fcb + FCB_NAME + i as u16, *c);
+                }
+                // Opening selects the extent given in the FCB.
+                let ex = (cpu.mem.read(fcb + FCB_EX) & 0x1f) as u32;
+                let s2 = (cpu.mem.read(fcb + FCB_S2) & 0x3f) as u32;
+                let cr = cpu.mem.read(fcb + FCB_CR);
+                self.set_fcb_record(cpu, fcb, s2 << 12 | ex << 7, size);
+                cpu.mem.write(fcb + FCB_CR, cr);
+                0
+            },
+            None => 0xff,
+        }
+    }
+
+    fn close(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        if self.find(cpu, fcb).is_some() { 0 } else { 0xff }
+    }
+
+    fn make(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        let path = self.fcb_dir(cpu, fcb).join(name_to_host(&fcb_name(cpu, fcb)));
+        match File::create(path) {
+            Ok(_) => {
+                let ex = (cpu.mem.read(fcb + FCB_EX) & 0x1f) as u32;
+                self.set_fcb_record(cpu, fcb, ex << 7, 0);
+                cpu.mem.write(fcb + FCB_CR, 0);
+                0
+            },
+            Err(_) => 0xff,
+        }
+    }
+
+    fn delete(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        let dir = self.fcb_dir(cpu, fcb);
+        let files = self.list(&dir, &fcb_name(cpu, fcb));
+        if files.is_empty() {
+            return 0xff;
+        }
+        for (_, path, _) in files {
+            if fs::remove_file(path).is_err() {
+                return 0xff;
+            }
+        }
+        0
+    }
+
+    fn rename(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        // The new name is in the second half of the FCB.
+        let new = fcb_name(cpu, fcb + 16);
+        let dir = self.fcb_dir(cpu, fcb);
+        match self.find(cpu, fcb) {
+            Some((_, path, _)) => {
+                match fs::rename(path, dir.join(name_to_host(&new))) {
+                    Ok(_) => 0,
+                    Err(_) => 0xff,
+                }
+            },
+            None => 0xff,
+        }
+    }
+
+    fn search_first(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        // A '?' drive matches every file
+        // on the current drive.
+        let pattern = if cpu.mem.read(fcb + FCB_DR) == b'?' {
+            [b'?'; 11]
+        } else {
+            fcb_name(cpu, fcb)
+        };
+        let dir = self.fcb_dir(cpu, fcb);
+        let mut files: Vec<(Name, u32)> = self.list(&dir, &pattern).into_iter()
+            .map(|(name, _, size)| (name, size))
+            .collect();
+        files.reverse();
+        self.search = files;
+        self.search_next(cpu)
+    }
+
+    /// Write the next search result to the DMA buffer
+    /// as the first of its four directory entries.
+    fn search_next(&mut self, cpu: &mut Cpu) -> u16 {
+        let (name, size) = match self.search.pop() {
+            Some(file) => file,
+            None => return 0xff,
+        };
+        // Describe the file by its last extent.
+        let last = if size == 0 { 0 } else { (size - 1) & !(EXTENT_RECORDS - 1) };
+        let dma = self.dma;
+        cpu.mem.write(dma, self.user);
+        for (i, c) in name.iter().enumerate() {
+            cpu.mem.write(dma + 1 + i as u16, *c);
+        }
+        cpu.mem.write(dma + 12, ((last >> 7) & 0x1f) as u8);
+        cpu.mem.write(dma + 13, 0);
+        cpu.mem.write(dma + 14, ((last >> 12) & 0x3f) as u8);
+        cpu.mem.write(dma + 15, (size - last) as u8);
+        for i in 16..32 {
+            // Mark blocks as in use for a non-empty file.
+            cpu.mem.write(dma + i, if size > 0 { 1 } else { 0 });
+        }
+        for i in 32..RECORD_SIZE as u16 {
+            cpu.mem.write(dma + i, 0xe5);
+        }
+        0
+    }
+
+    /// Read `record` of the FCB's file in to the DMA buffer,
+    /// padding a short record with ^Z. Returns false if the
+    /// record is past the end of the file.
+    fn read_record(&mut self, cpu: &mut Cpu, fcb: u16, record: u32) -> bool {
+        let mut buf = [EOF; RECORD_SIZE];
+        let path = self.fcb_path(cpu, fcb);
+        let n = match File::open(path) {
+            Ok(mut file) => {
+                match file.seek(SeekFrom::Start(record as u64 * RECORD_SIZE as u64)) {
+                    Ok(_) => {
+                        let mut n = 0;
+                        while n < RECORD_SIZE {
+                            match file.read(&mut buf[n..]) {
+                                Ok(0) | Err(_) => break,
+                                Ok(read) => n += read,
+                            }
+                        }
+                        n
+                    },
+                    Err(_) => 0,
+                }
+            },
+            Err(_) => 0,
+        };
+        if n == 0 {
+            return false;
+        }
+        let dma = self.dma;
+        for (i, x) in buf.iter().enumerate() {
+            cpu.mem.write(dma.wrapping_add(i as u16), *x);
+        }
+        true
+    }
+
+    /// Write the DMA buffer to `record` of the FCB's file.
+    /// Returns the size of the file in records afterwards.
+    fn write_record(&mut self, cpu: &mut Cpu, fcb: u16, record: u32) -> Option<u32> {
+        let mut buf = [0; RECORD_SIZE];
+        let dma = self.dma;
+        for (i, x) in buf.iter_mut().enumerate() {
+            *x = cpu.mem.read(dma.wrapping_add(i as u16));
+        }
+        let path = self.fcb_path(cpu, fcb);
+        let mut file = match OpenOptions::new().write(true).create(true).open(path) {
+            Ok(file) => file,
+            Err(_) => return None,
+        };
+        if file.seek(SeekFrom::Start(record as u64 * RECORD_SIZE as u64)).is_err() {
+            return None;
+        }
+        if file.write_all(&buf).is_err() {
+            return None;
+        }
+        file.metadata().ok().map(|m| records(m.len()))
+    }
+
+    /// Size of the FCB's file in records.
+    fn file_size(&self, cpu: &Cpu, fcb: u16) -> u32 {
+        self.find(cpu, fcb).map(|(_, _, size)| size).unwrap_or(0)
+    }
+
+    fn read_sequential(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        let record = self.fcb_record(cpu, fcb);
+        if !self.read_record(cpu, fcb, record) {
+            return 1;
+        }
+        let size = self.file_size(cpu, fcb);
+        self.set_fcb_record(cpu, fcb, record + 1, size);
+        0
+    }
+
+    fn write_sequential(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        let record = self.fcb_record(cpu, fcb);
+        match self.write_record(cpu, fcb, record) {
+            Some(size) => {
+                self.set_fcb_record(cpu, fcb, record + 1, size);
+                0
+            },
+            None => 2,
+        }
+    }
+
+    fn read_random(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        let record = self.random_record(cpu, fcb);
+        if record > 0xffff {
+            return 6;
+        }
+        // The sequential position is left at the record
+        // read so that reading can continue from there.
+        let size = self.file_size(cpu, fcb);
+        self.set_fcb_record(cpu, fcb, record, size);
+        if !self.read_record(cpu, fcb, record) {
+            return 1;
+        }
+        0
+    }
+
+    fn write_random(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        let record = self.random_record(cpu, fcb);
+        if record > 0xffff {
+            return 6;
+        }
+        match self.write_record(cpu, fcb, record) {
+            Some(size) => {
+                self.set_fcb_record(cpu, fcb, record, size);
+                0
+            },
+            None => 2,
+        }
+    }
+
+    fn compute_size(&mut self, cpu: &mut Cpu, fcb: u16) -> u16 {
+        match self.find(cpu, fcb) {
+            Some((_, _, size)) => {
+                self.set_random_record(cpu, fcb, size);
+                0
+            },
+            None => 0xff,
+        }
+    }
+}
+
+#[test]
+fn test_host_to_name() {
+    assert_eq!(&host_to_name("zork1.dat").unwrap(), b"ZORK1   DAT");
+    assert_eq!(&host_to_name("MBASIC").unwrap(), b"MBASIC     ");
+    assert_eq!(host_to_name("toolongname.com"), None);
+    assert_eq!(host_to_name("a.long"), None);
+    assert_eq!(host_to_name(".hidden"), None);
+}
+
+#[test]
+fn test_name_matches() {
+    let pattern = *b"????????COM";
+    assert!(matches(&pattern, b"MBASIC  COM"));
+    assert!(!matches(&pattern, b"MBASIC  BAS"));
+    assert_eq!(name_to_host(b"MBASIC  COM"), "MBASIC.COM");
+    assert_eq!(name_to_host(b"README     "), "README");
+    // Only the padding after the name and type goes
+    assert_eq!(name_to_host(b" A      TXT"), " A.TXT");
+    assert_eq!(name_to_host(b"FILE    A  "), "FILE.A");
+}
+
+#[cfg(test)]
+use console::BufferConsole;
+#[cfg(test)]
+use memory::Memory;
+
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    let dir = ::std::env::temp_dir().join(format!("emu8080-{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+fn bdos_call(bdos: &mut Bdos, cpu: &mut Cpu, c: u8, de: u16) -> u8 {
+    let mut console = BufferConsole::new(b"");
+    cpu.c = c;
+    cpu.set_de(de);
+    assert!(bdos.call(cpu, &mut console));
+    cpu.a
+}
+
+#[cfg(test)]
+fn set_fcb(cpu: &mut Cpu, fcb: u16, name: &Name) {
+    for i in 0..36 {
+        cpu.mem.write(fcb + i, 0);
+    }
+    for (i, c) in name.iter().enumerate() {
+        cpu.mem.write(fcb + FCB_NAME + i as u16, *c);
+    }
+}
+
+#[test]
+fn test_file_io() {
+    let dir = test_dir("file-io");
+    let mut bdos = Bdos::new(&dir);
+    let mut cpu = Cpu::new(Memory::with_data(&[]));
+    let fcb = 0x5c;
+
+    // Make a file and write two records to it.
+    set_fcb(&mut cpu, fcb, b"TEST    DAT");
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 22, fcb), 0);
+    for record in 0..2 {
+        for i in 0..RECORD_SIZE as u16 {
+            cpu.mem.write(DEFAULT_DMA + i, record);
+        }
+        assert_eq!(bdos_call(&mut bdos, &mut cpu, 21, fcb), 0);
+    }
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 16, fcb), 0);
+    assert_eq!(fs::metadata(dir.join("TEST.DAT")).unwrap().len(), 256);
+
+    // Read it back sequentially.
+    set_fcb(&mut cpu, fcb, b"TEST    DAT");
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 15, fcb), 0);
+    assert_eq!(cpu.mem.read(fcb + FCB_RC), 2);
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 20, fcb), 0);
+    assert_eq!(cpu.mem.read(DEFAULT_DMA), 0);
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 20, fcb), 0);
+    assert_eq!(cpu.mem.read(DEFAULT_DMA), 1);
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 20, fcb), 1);
+
+    // Random access and file size.
+    cpu.mem.write(fcb + FCB_R0, 0);
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 33, fcb), 0);
+    assert_eq!(cpu.mem.read(DEFAULT_DMA), 0);
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 35, fcb), 0);
+    assert_eq!(cpu.mem.read(fcb + FCB_R0), 2);
+
+    // Search with wildcards, rename and delete.
+    set_fcb(&mut cpu, fcb, b"????????DAT");
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 17, fcb), 0);
+    assert_eq!(cpu.mem.read(DEFAULT_DMA + 1), b'T');
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 18, fcb), 0xff);
+    set_fcb(&mut cpu, fcb, b"TEST    DAT");
+    for (i, c) in b"NEW     DAT".iter().enumerate() {
+        cpu.mem.write(fcb + 17 + i as u16, *c);
+    }
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 23, fcb), 0);
+    assert!(dir.join("NEW.DAT").exists());
+    set_fcb(&mut cpu, fcb, b"NEW     DAT");
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 19, fcb), 0);
+    assert_eq!(bdos_call(&mut bdos, &mut cpu, 15, fcb), 0xff);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unknown_function() {
+    let mut bdos = Bdos::new(Path::new("."));
+    let mut cpu = Cpu::new(Memory::with_data(&[]));
+    for &c in &[38, 39, 41, 98, 255] {
+        cpu.set_hl(0);
+        assert_eq!(bdos_call(&mut bdos, &mut cpu, c, 0), 0xff);
+        assert_eq!(cpu.l, 0xff);
+        assert_eq!(cpu.h, 0);
+    }
+}
+
+#[test]
+fn test_read_console_buffer() {
+    let dir = test_dir("console");
+    let mut bdos = Bdos::new(&dir);
+    let mut console = BufferConsole::new(b"DIR\x08X\r");
+    let mut cpu = Cpu::new(Memory::with_data(&[]));
+    cpu.mem.write(0x200, 16);
+    cpu.c = 10;
+    cpu.set_de(0x200);
+    assert!(bdos.call(&mut cpu, &mut console));
+    assert_eq!(cpu.mem.read(0x201), 3);
+    assert_eq!(cpu.mem.read(0x204), b'X');
+    assert_eq!(&console.output[..3], b"DIR");
+    fs::remove_dir_all(&dir).unwrap();
+}
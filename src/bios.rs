@@ -0,0 +1,322 @@
+use console::{Console, EOF};
+use cpu::Cpu;
+use disk::{Disk, SECTORS, SECTOR_SIZE};
+
+/// Number of entries in the BIOS jump table.
+const ENTRIES: u16 = 17;
+
+/// Number of drives the BIOS supports.
+pub const DRIVES: usize = 4;
+
+/// Size of the CCP and BDOS together, this is
+/// what is loaded from the system tracks on boot.
+const SYSTEM_SIZE: u16 = 0x1600;
+
+/// The BDOS is loaded this far above the CCP.
+pub const BDOS_OFFSET: u16 = 0x0800;
+
+// Offsets of the disk tables from the BIOS base,
+// following on from the jump table.
+const DPH_OFFSET: u16 = 0x40;
+const XLT_OFFSET: u16 = 0x80;
+const DPB_OFFSET: u16 = 0xa0;
+const DIRBUF_OFFSET: u16 = 0x100;
+const CSV_OFFSET: u16 = 0x180;
+const ALV_OFFSET: u16 = 0x1c0;
+
+/// Sector translation table giving the standard
+/// skew of 6 for 8" single density disks.
+const XLT: [u8; 26] = [
+    1, 7, 13, 19, 25, 5, 11, 17, 23, 3, 9, 15, 21,
+    2, 8, 14, 20, 26, 6, 12, 18, 24, 4, 10, 16, 22,
+];
+
+/// Disk parameter block for 8" single sided single density.
+const DPB: [u8; 15] = [
+    26, 0,    // SPT sectors per track
+    3,        // BSH block shift
+    7,        // BLM block mask
+    0,        // EXM extent mask
+    242, 0,   // DSM highest block number
+    63, 0,    // DRM highest directory entry
+    0xc0, 0,  // AL0, AL1 directory blocks
+    16, 0,    // CKS checksum vector size
+    2, 0,     // OFF reserved tracks
+];
+
+/// What the machine should do after a BIOS call.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BiosResult {
+    /// Return to the caller.
+    Return,
+    /// PC has been set, continue from there.
+    Jump,
+    /// Stop the machine.
+    Stop,
+}
+
+/// A CP/M 2.2 BIOS implemented in Rust.
+///
+/// The jump table is placed in memory so programs can find
+/// it through the warm boot vector at 0x0000, but calls to
+/// its entries are trapped by the machine and passed to
+/// `call` rather than executed. Drives are backed by 8"
+/// single sided single density disk images.
+pub struct Bios {
+    base: u16,
+    ccp: u16,
+    disks: Vec<Option<Disk>>,
+    /// Load the CCP and BDOS from drive A when booting,
+    /// otherwise warm boot stops the machine.
+    system: bool,
+    drive: usize,
+    track: u16,
+    sector: u16,
+    dma: u16,
+}
+
+impl Bios {
+    /// Create a BIOS for a system whose CCP is
+    /// at `ccp`. The jump table follows the BDOS.
+    pub fn new(ccp: u16) -> Self {
+        let mut disks = Vec::with_capacity(DRIVES);
+        for _ in 0..DRIVES {
+            disks.push(None);
+        }
+        Bios {
+            base: ccp + SYSTEM_SIZE,
+            ccp: ccp,
+            disks: disks,
+            system: false,
+            drive: 0,
+            track: 0,
+            sector: 1,
+            dma: 0x0080,
+        }
+    }
+
+    /// Address of the jump table.
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    /// Address of the BDOS entry point.
+    pub fn bdos_entry(&self) -> u16 {
+        self.ccp + BDOS_OFFSET + 6
+    }
+
+    /// Address of the disk parameter block
+    /// shared by every drive.
+    pub fn dpb(&self) -> u16 {
+        self.base + DPB_OFFSET
+    }
+
+    /// Address of the allocation vector of `drive`.
+    pub fn alv(&self, drive: usize) -> u16 {
+        self.base + ALV_OFFSET + drive as u16 * 32
+    }
+
+    /// Attach a disk image to a drive, 0 is A.
+    pub fn mount(&mut self, drive: usize, disk: Disk) -> Result<(), String> {
+        match self.disks.get_mut(drive) {
+            Some(slot) => {
+                *slot = Some(disk);
+                Ok(())
+            },
+            None => Err(format!("Only {} disk drives are supported, not {}.", DRIVES, drive + 1)),
+        }
+    }
+
+    /// Boot CP/M from the system tracks of
+    /// drive A rather than stopping on a warm boot.
+    pub fn set_system(&mut self, system: bool) {
+        self.system = system;
+    }
+
+    /// The jump table entry at `addr`, if any.
+    pub fn entry_at(&self, addr: u16) -> Option<u16> {
+        if addr < self.base {
+            return None;
+        }
+        let offset = addr - self.base;
+        if offset < ENTRIES * 3 && offset % 3 == 0 {
+            Some(offset / 3)
+        } else {
+            None
+        }
+    }
+
+    /// Write the jump table and disk tables in to memory.
+    pub fn install(&self, cpu: &mut Cpu) {
+        // Entries are never executed, calls to
+        // them are trapped, so each is just a RET.
+        for i in 0..ENTRIES {
+            cpu.mem.write(self.base + i * 3, 0xc9);
+            cpu.mem.write(self.base + i * 3 + 1, 0x00);
+            cpu.mem.write(self.base + i * 3 + 2, 0x00);
+        }
+        for (i, x) in XLT.iter().enumerate() {
+            cpu.mem.write(self.base + XLT_OFFSET + i as u16, *x);
+        }
+        for (i, x) in DPB.iter().enumerate() {
+            cpu.mem.write(self.base + DPB_OFFSET + i as u16, *x);
+        }
+        // One disk parameter header per drive: XLT, three
+        // words of BDOS scratch, DIRBUF, DPB, CSV and ALV.
+        for drive in 0..DRIVES as u16 {
+            let dph = self.base + DPH_OFFSET + drive * 16;
+            let words = [
+                self.base + XLT_OFFSET,
+                0, 0, 0,
+                self.base + DIRBUF_OFFSET,
+                self.dpb(),
+                self.base + CSV_OFFSET + drive * 16,
+                self.alv(drive as usize),
+            ];
+            for (i, w) in words.iter().enumerate() {
+                cpu.mem.write(dph + i as u16 * 2, (*w & 0xff) as u8);
+                cpu.mem.write(dph + i as u16 * 2 + 1, (*w >> 8) as u8);
+            }
+        }
+    }
+
+    /// Set up the zero page jumps to the BIOS warm boot
+    /// at 0x0000 and to the BDOS entry point at 0x0005.
+    pub fn set_vectors(&self, cpu: &mut Cpu, bdos_entry: u16) {
+        let wboot = self.base + 3;
+        cpu.mem.write(0x0000, 0xc3);
+        cpu.mem.write(0x0001, (wboot & 0xff) as u8);
+        cpu.mem.write(0x0002, (wboot >> 8) as u8);
+        cpu.mem.write(0x0005, 0xc3);
+        cpu.mem.write(0x0006, (bdos_entry & 0xff) as u8);
+        cpu.mem.write(0x0007, (bdos_entry >> 8) as u8);
+    }
+
+    /// Load the CCP and BDOS from the system tracks of drive
+    /// A, starting at track 0 sector 2 after the cold start
+    /// loader, and jump to the CCP with the drive in C.
+    fn load_system(&mut self, cpu: &mut Cpu) -> BiosResult {
+        {
+            let ccp = self.ccp;
+            let disk = match self.disks[0] {
+                Some(ref mut disk) => disk,
+                None => return BiosResult::Stop,
+            };
+            let mut buf = [0; SECTOR_SIZE];
+            let (mut track, mut sector) = (0, 2);
+            let mut addr = ccp;
+            while addr < ccp + SYSTEM_SIZE {
+                if !disk.read_sector(track, sector, &mut buf) {
+                    return BiosResult::Stop;
+                }
+                for (i, x) in buf.iter().enumerate() {
+                    cpu.mem.write(addr + i as u16, *x);
+                }
+                addr += SECTOR_SIZE as u16;
+                sector += 1;
+                if sector > SECTORS {
+                    sector = 1;
+                    track += 1;
+                }
+            }
+        }
+        let bdos_entry = self.bdos_entry();
+        self.set_vectors(cpu, bdos_entry);
+        self.dma = 0x0080;
+        cpu.sp = 0x0080;
+        cpu.c = cpu.mem.read(0x0004);
+        cpu.pc = self.ccp;
+        BiosResult::Jump
+    }
+
+    /// DPH address for a drive or 0 if it has no disk.
+    fn select(&mut self, drive: usize) -> u16 {
+        if drive < DRIVES && self.disks[drive].is_some() {
+            self.drive = drive;
+            self.base + DPH_OFFSET + drive as u16 * 16
+        } else {
+            0
+        }
+    }
+
+    /// Transfer the current sector to or from the DMA
+    /// buffer, returning 0 on success or 1 on error.
+    fn transfer(&mut self, cpu: &mut Cpu, write: bool) -> u8 {
+        let (track, sector, dma) = (self.track, self.sector, self.dma);
+        let disk = match self.disks[self.drive] {
+            Some(ref mut disk) => disk,
+            None => return 1,
+        };
+        let mut buf = [0; SECTOR_SIZE];
+        if write {
+            for (i, x) in buf.iter_mut().enumerate() {
+                *x = cpu.mem.read(dma.wrapping_add(i as u16));
+            }
+            if disk.write_sector(track, sector, &buf) { 0 } else { 1 }
+        } else {
+            if !disk.read_sector(track, sector, &mut buf) {
+                return 1;
+            }
+            for (i, x) in buf.iter().enumerate() {
+                cpu.mem.write(dma.wrapping_add(i as u16), *x);
+            }
+            0
+        }
+    }
+
+    /// Perform the BIOS function for jump table `entry`.
+    pub fn call<C: Console>(&mut self, entry: u16, cpu: &mut Cpu, console: &mut C) -> BiosResult {
+        let bc = cpu.bc();
+        match entry {
+            0 => { // BOOT
+                if !self.system {
+                    return BiosResult::Stop;
+                }
+                // Cold boot starts on drive A user 0.
+                cpu.mem.write(0x0003, 0);
+                cpu.mem.write(0x0004, 0);
+                return self.load_system(cpu);
+            },
+            1 => { // WBOOT
+                if !self.system {
+                    return BiosResult::Stop;
+                }
+                return self.load_system(cpu);
+            },
+            2 => { // CONST
+                cpu.a = if console.status() { 0xff } else { 0 };
+            },
+            3 => { // CONIN
+                if console.closed() {
+                    return BiosResult::Stop;
+                }
+                cpu.a = console.read() & 0x7f;
+            },
+            4 => console.write(cpu.c & 0x7f), // CONOUT
+            5 | 6 => {}, // LIST, PUNCH
+            7 => cpu.a = EOF, // READER
+            8 => self.track = 0, // HOME
+            9 => { // SELDSK
+                let dph = self.select(cpu.c as usize);
+                cpu.set_hl(dph);
+            },
+            10 => self.track = bc, // SETTRK
+            11 => self.sector = bc, // SETSEC
+            12 => self.dma = bc, // SETDMA
+            13 => cpu.a = self.transfer(cpu, false), // READ
+            14 => cpu.a = self.transfer(cpu, true), // WRITE
+            15 => cpu.a = 0xff, // LISTST
+            16 => { // SECTRAN
+                let xlt = cpu.de();
+                let sector = if xlt == 0 {
+                    bc + 1
+                } else {
+                    cpu.mem.read(xlt.wrapping_add(bc)) as u16
+                };
+                cpu.set_hl(sector);
+            },
+            _ => unreachable!(),
+        }
+        BiosResult::Return
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+
+use memory::Access;
+
+/// One side of a condition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// A register, pair or the flags by name.
+    Register(String),
+    /// The byte at a location, an address or symbol.
+    Memory(String),
+    Value(u16),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Register(ref name) => write!(f, "{}", name),
+            Operand::Memory(ref location) => write!(f, "[{}]", location),
+            Operand::Value(value) => write!(f, "0x{:x}", value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Compare {
+    pub fn test(self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            Compare::Eq => lhs == rhs,
+            Compare::Ne => lhs != rhs,
+            Compare::Lt => lhs < rhs,
+            Compare::Le => lhs <= rhs,
+            Compare::Gt => lhs > rhs,
+            Compare::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl fmt::Display for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            Compare::Eq => "==",
+            Compare::Ne => "!=",
+            Compare::Lt => "<",
+            Compare::Le => "<=",
+            Compare::Gt => ">",
+            Compare::Ge => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// A comparison that has to hold for a breakpoint to stop.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub lhs: Operand,
+    pub compare: Compare,
+    pub rhs: Operand,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", self.lhs, self.compare, self.rhs)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Stop before the instruction at an address.
+    Break(u16),
+    /// Stop after the CPU reads or writes an address.
+    Watch { addr: u16, read: bool, write: bool },
+}
+
+/// A numbered breakpoint or watchpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub number: usize,
+    pub kind: Kind,
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+    pub hits: usize,
+}
+
+impl Breakpoint {
+    pub fn new(number: usize, kind: Kind, condition: Option<Condition>) -> Self {
+        Breakpoint {
+            number: number,
+            kind: kind,
+            condition: condition,
+            enabled: true,
+            hits: 0,
+        }
+    }
+
+    /// Does an instruction that made `accesses` and left PC
+    /// at `pc` trigger this, before checking its condition?
+    pub fn triggered(&self, pc: u16, accesses: &[(u16, Access)]) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        match self.kind {
+            Kind::Break(addr) => addr == pc,
+            Kind::Watch { addr, read, write } => accesses.iter().any(|&(at, access)| {
+                at == addr && match access {
+                    Access::Read => read,
+                    Access::Write => write,
+                }
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Kind::Break(addr) => write!(f, "{} break {:04x}", self.number, addr)?,
+            Kind::Watch { addr, read, write } => {
+                let access = match (read, write) {
+                    (true, true) => "rw",
+                    (true, false) => "r",
+                    _ => "w",
+                };
+                write!(f, "{} watch {:04x} {}", self.number, addr, access)?
+            },
+        }
+        if let Some(ref condition) = self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        write!(f, ", hit {} times", self.hits)?;
+        if !self.enabled {
+            write!(f, ", disabled")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_triggered() {
+    let mut breakpoint = Breakpoint::new(1, Kind::Break(0x1439), None);
+    assert!(breakpoint.triggered(0x1439, &[]));
+    assert!(!breakpoint.triggered(0x143a, &[]));
+    breakpoint.enabled = false;
+    assert!(!breakpoint.triggered(0x1439, &[]));
+
+    let watch = Breakpoint::new(2, Kind::Watch { addr: 0x20f8, read: false, write: true }, None);
+    assert!(watch.triggered(0, &[(0x20f8, Access::Write)]));
+    assert!(!watch.triggered(0, &[(0x20f8, Access::Read), (0x20f9, Access::Write)]));
+}
+
+#[test]
+fn test_display() {
+    let condition = Condition {
+        lhs: Operand::Register(String::from("a")),
+        compare: Compare::Eq,
+        rhs: Operand::Value(0x20),
+    };
+    let mut breakpoint = Breakpoint::new(1, Kind::Break(0x1439), Some(condition));
+    breakpoint.hits = 2;
+    assert_eq!(format!("{}", breakpoint), "1 break 1439 if a==0x20, hit 2 times");
+
+    let condition = Condition {
+        lhs: Operand::Memory(String::from("Lives")),
+        compare: Compare::Lt,
+        rhs: Operand::Value(3),
+    };
+    let mut watch = Breakpoint::new(2, Kind::Watch { addr: 0x20f8, read: true, write: true }, Some(condition));
+    watch.enabled = false;
+    assert_eq!(format!("{}", watch), "2 watch 20f8 rw if [Lives]<0x3, hit 0 times, disabled");
+}
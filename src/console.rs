@@ -0,0 +1,148 @@
+use std::io;
+use std::io::{Read, Write};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+/// ASCII carriage return.
+pub const CR: u8 = 0x0d;
+/// ASCII line feed.
+pub const LF: u8 = 0x0a;
+/// Ctrl-Z, the CP/M end of file marker.
+pub const EOF: u8 = 0x1a;
+
+/// A character terminal attached to
+/// an emulated machine.
+pub trait Console {
+    /// Is there a character waiting to be read?
+    fn status(&mut self) -> bool;
+
+    /// Wait for the next character. Returns
+    /// `EOF` if there will be no more input.
+    fn read(&mut self) -> u8;
+
+    /// Write a character to the terminal.
+    fn write(&mut self, c: u8);
+
+    /// Has all input been read, with
+    /// no more ever going to arrive?
+    fn closed(&mut self) -> bool;
+
+    /// Does the terminal already show characters
+    /// as they're typed? If so programs shouldn't
+    /// echo them a second time.
+    fn echoes_input(&self) -> bool { false }
+}
+
+/// Console using the host's stdin and stdout.
+///
+/// Stdin is read on a separate thread so that
+/// `status` doesn't block waiting for a line.
+pub struct StdConsole {
+    input: Receiver<u8>,
+    next: Option<u8>,
+    closed: bool,
+}
+
+impl StdConsole {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for byte in stdin.lock().bytes() {
+                let byte = match byte {
+                    Ok(byte) => byte,
+                    Err(_) => break,
+                };
+                // CP/M programs expect lines to
+                // end with a carriage return.
+                let c = if byte == LF { CR } else { byte };
+                if tx.send(c).is_err() {
+                    break;
+                }
+            }
+        });
+        StdConsole {
+            input: rx,
+            next: None,
+            closed: false,
+        }
+    }
+}
+
+impl Console for StdConsole {
+    fn status(&mut self) -> bool {
+        if self.next.is_none() && !self.closed {
+            match self.input.try_recv() {
+                Ok(c) => self.next = Some(c),
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => self.closed = true,
+            }
+        }
+        self.next.is_some()
+    }
+
+    fn read(&mut self) -> u8 {
+        if let Some(c) = self.next.take() {
+            return c;
+        }
+        if self.closed {
+            return EOF;
+        }
+        match self.input.recv() {
+            Ok(c) => c,
+            Err(_) => {
+                self.closed = true;
+                EOF
+            },
+        }
+    }
+
+    fn write(&mut self, c: u8) {
+        let mut stdout = io::stdout();
+        stdout.write_all(&[c]).expect("Could not write to stdout.");
+        stdout.flush().expect("Could not flush stdout.");
+    }
+
+    fn closed(&mut self) -> bool {
+        !self.status() && self.closed
+    }
+
+    fn echoes_input(&self) -> bool { true }
+}
+
+/// Console with scripted input that
+/// collects everything written to it.
+#[cfg(test)]
+pub struct BufferConsole {
+    pub input: ::std::collections::VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+#[cfg(test)]
+impl BufferConsole {
+    pub fn new(input: &[u8]) -> Self {
+        BufferConsole {
+            input: input.iter().cloned().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Console for BufferConsole {
+    fn status(&mut self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn read(&mut self) -> u8 {
+        self.input.pop_front().unwrap_or(EOF)
+    }
+
+    fn write(&mut self, c: u8) {
+        self.output.push(c);
+    }
+
+    fn closed(&mut self) -> bool {
+        self.input.is_empty()
+    }
+}
@@ -1,82 +1,267 @@
+use std::path::Path;
 
-use cpu::{Cpu, make_u16};
+use bdos::Bdos;
+use bios::{Bios, BiosResult, BDOS_OFFSET};
+use console::{Console, StdConsole};
+use cpu::{ConditionCodes, Cpu, Registers, make_u16};
+use disk::Disk;
 use machine::Machine;
-use memory::Memory;
+use memory::{Access, Memory};
+
+/// Base of the CCP in a standard 64K CP/M 2.2
+/// system, the BDOS and BIOS follow it. System
+/// disks must have been built for this size.
+const CCP_BASE: u16 = 0xe400;
+
+/// Base of the BDOS, everything below this
+/// is the zero page and transient program area.
+const BDOS_BASE: u16 = CCP_BASE + BDOS_OFFSET;
+
+/// Address of the BDOS entry point, the
+/// target of the jump at 0x0005.
+const BDOS_ENTRY: u16 = BDOS_BASE + 6;
+
+/// Where programs are loaded and started.
+const TPA: u16 = 0x0100;
+
+// Zero page locations filled in for a program.
+const IOBYTE: u16 = 0x0003;
+const DRIVE_USER: u16 = 0x0004;
+const FCB1: u16 = 0x005c;
+const FCB2: u16 = 0x006c;
+const COMMAND_TAIL: u16 = 0x0080;
+
+/// Parse a command line argument into the drive and
+/// 11 byte name of a default FCB the way the CCP does.
+/// A `*` in the name or type is expanded to `?`s.
+fn parse_fcb(arg: &str) -> (u8, [u8; 11]) {
+    let arg = arg.to_uppercase();
+    let bytes = arg.as_bytes();
+    let (drive, rest) = if bytes.len() >= 2 && bytes[1] == b':' {
+        (bytes[0].wrapping_sub(b'A').wrapping_add(1), &bytes[2..])
+    } else {
+        (0, bytes)
+    };
+    let mut name = [b' '; 11];
+    let mut parts = rest.splitn(2, |&c| c == b'.');
+    let fields = [(0, 8), (8, 3)];
+    for &(start, len) in fields.iter() {
+        let part = parts.next().unwrap_or(&[]);
+        for i in 0..len {
+            match part.get(i) {
+                Some(&b'*') => {
+                    for j in i..len {
+                        name[start + j] = b'?';
+                    }
+                    break;
+                },
+                Some(&c) => name[start + i] = c,
+                None => break,
+            }
+        }
+    }
+    (drive, name)
+}
 
 /// Emulation of the CP/M system.
-pub struct Cpm {
+///
+/// This either runs a single program with the BDOS emulated
+/// on the host filesystem, or boots a real CP/M system disk
+/// that brings its own CCP and BDOS. Either way the BIOS is
+/// emulated and its drives are backed by disk images.
+pub struct Cpm<C: Console = StdConsole> {
     pub cpu: Cpu,
+    console: C,
+    bdos: Option<Bdos>,
+    bios: Bios,
+    running: bool,
+}
+
+impl Cpm<StdConsole> {
+    /// Create a CP/M system using the terminal
+    /// as its console and `dir` as drive A.
+    pub fn new(data: &[u8], args: &[String], dir: &Path) -> Self {
+        Cpm::with_console(data, args, dir, StdConsole::new())
+    }
+
+    /// Boot CP/M from the first disk using the
+    /// terminal as its console.
+    pub fn boot(disks: Vec<Disk>) -> Result<Self, String> {
+        Cpm::boot_with_console(disks, StdConsole::new())
+    }
 }
 
-impl Cpm {
-    pub fn new(data: &[u8]) -> Self {
-        // In a CP/M system we offset
-        // data in memory by 0x100.
-        let memory = Memory::with_data_and_offset(data, 0x100);
+impl<C: Console> Cpm<C> {
+    /// Load the program in `data` in to the TPA and set
+    /// up the zero page as the CCP would for it to run
+    /// with the given arguments.
+    pub fn with_console(data: &[u8], args: &[String], dir: &Path, console: C) -> Self {
+        let memory = Memory::with_data_and_offset(data, TPA as usize);
+        let mut cpu = Cpu::new(memory);
+        let mut bdos = Bdos::new(dir);
+        let bios = Bios::new(CCP_BASE);
+        bios.install(&mut cpu);
+        bdos.set_disk_tables(bios.dpb(), bios.alv(0));
+
+        // JMP WARM_BOOT at 0x0000 and JMP BDOS_ENTRY
+        // at 0x0005, programs read the address of
+        // the BDOS to find out how much memory they have.
+        bios.set_vectors(&mut cpu, BDOS_ENTRY);
+        cpu.mem.write(IOBYTE, 0);
+        cpu.mem.write(DRIVE_USER, bdos.user() << 4 | bdos.drive());
+
+        // The first two arguments are parsed into
+        // the default FCBs, the rest of FCB1 and the
+        // random record field after FCB2 are cleared.
+        for addr in FCB1..COMMAND_TAIL {
+            cpu.mem.write(addr, 0);
+        }
+        for (i, &fcb) in [FCB1, FCB2].iter().enumerate() {
+            let (drive, name) = parse_fcb(args.get(i).map(|s| s.as_str()).unwrap_or(""));
+            cpu.mem.write(fcb, drive);
+            for (j, c) in name.iter().enumerate() {
+                cpu.mem.write(fcb + 1 + j as u16, *c);
+            }
+        }
+
+        // The command tail is everything after the
+        // program name with a leading space, up to
+        // 127 characters and terminated by a zero if
+        // there's room before the program at 0x0100.
+        let mut tail = String::new();
+        for arg in args {
+            tail.push(' ');
+            tail.push_str(&arg.to_uppercase());
+        }
+        let tail = &tail.as_bytes()[..::std::cmp::min(tail.len(), 127)];
+        cpu.mem.write(COMMAND_TAIL, tail.len() as u8);
+        for (i, c) in tail.iter().enumerate() {
+            cpu.mem.write(COMMAND_TAIL + 1 + i as u16, *c);
+        }
+        if tail.len() < 127 {
+            cpu.mem.write(COMMAND_TAIL + 1 + tail.len() as u16, 0);
+        }
+
+        // The CCP calls the program so returning
+        // from it goes to the warm boot at 0x0000.
+        cpu.sp = BDOS_BASE;
+        cpu.push(0x00, 0x00);
+        cpu.pc = TPA;
         Cpm {
-            cpu: Cpu::new(memory),
+            cpu: cpu,
+            console: console,
+            bdos: Some(bdos),
+            bios: bios,
+            running: true,
+        }
+    }
+
+    /// Boot CP/M from the system tracks of the first disk,
+    /// the rest are mounted as drives B, C and so on.
+    pub fn boot_with_console(disks: Vec<Disk>, console: C) -> Result<Self, String> {
+        let mut cpu = Cpu::new(Memory::with_data(&[]));
+        let mut bios = Bios::new(CCP_BASE);
+        for (drive, disk) in disks.into_iter().enumerate() {
+            bios.mount(drive, disk)?;
         }
+        bios.set_system(true);
+        bios.install(&mut cpu);
+        // Start at the cold boot entry of the BIOS.
+        cpu.pc = bios.base();
+        Ok(Cpm {
+            cpu: cpu,
+            console: console,
+            bdos: None,
+            bios: bios,
+            running: true,
+        })
     }
 
+    /// Attach a disk image to a BIOS drive, 0 is A.
+    pub fn mount(&mut self, drive: usize, disk: Disk) -> Result<(), String> {
+        self.bios.mount(drive, disk)
+    }
+
+    /// Copy `data` into memory at `addr`.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        self.cpu.mem.load(addr, data);
+    }
+
+    /// Run until the program exits back to the
+    /// system or, when booted from disk, until
+    /// the console has no more input.
     pub fn run(&mut self) {
-        loop {
+        while self.running {
             self.step();
         }
     }
+
+    /// Consume the machine returning its console.
+    pub fn into_console(self) -> C {
+        self.console
+    }
 }
 
-impl Machine for Cpm {
+impl<C: Console> Machine for Cpm<C> {
     /// Step the Machine by a single instruction.
     #[inline(always)]
     fn step(&mut self) -> u32 {
-        let op = self.cpu.mem.read(self.cpu.pc);
-        // println!("pc: {:>0pad$x} op: {:>0padop$x} cc.p: {}", self.cpu.pc, op, self.cpu.cc.p, pad=4, padop=2);
-        let cycles = match op {
-            0xcd => {
-                let addr = self.cpu.read_u16();
-                match addr {
-                    5 => {
-                        if self.cpu.c == 9 {
-                            let offset = make_u16(self.cpu.e, self.cpu.d) + 3;
-                            let mut c = String::from_utf8(vec![self.cpu.mem.read(offset)]).expect("Not a UTF-8 String");
-                            loop {
-                                match c.as_ref() {
-                                    "$" => break,
-                                    c => print!("{}", c),
-                                }
-                            }
-                            print!("\n");
-                        }
-                        17
-                    },
-                    0 => {
-                        ::std::process::exit(0);
-                        17
-                    },
-                    _ => self.cpu.step()
+        if self.cpu.halted {
+            return self.cpu.step();
+        }
+        let pc = self.cpu.pc;
+        if let Some(ref mut bdos) = self.bdos {
+            if pc == BDOS_ENTRY {
+                if !bdos.call(&mut self.cpu, &mut self.console) {
+                    self.running = false;
                 }
-            },
-            _ => self.cpu.step()
-        };
-        cycles
+                // Return to the caller as if the
+                // BDOS had executed a RET.
+                let (lo, hi) = self.cpu.pop();
+                self.cpu.pc = make_u16(lo, hi);
+                return 10;
+            }
+        }
+        if let Some(entry) = self.bios.entry_at(pc) {
+            match self.bios.call(entry, &mut self.cpu, &mut self.console) {
+                BiosResult::Return => {
+                    let (lo, hi) = self.cpu.pop();
+                    self.cpu.pc = make_u16(lo, hi);
+                },
+                BiosResult::Jump => {},
+                BiosResult::Stop => self.running = false,
+            }
+            return 10;
+        }
+        self.cpu.step()
     }
 
     #[inline(always)]
     fn interrupt(&mut self, int: usize) {
         // TODO: interrupt code should be an enum?
-        // PUSH PC
-        let hi = ((self.cpu.pc & 0xff00) >> 8) as u8;
-        let lo = (self.cpu.pc & 0xff) as u8;
-        self.cpu.push(hi, lo);
-        // Set PC to low memory vector
         // This is identical to an `RST int` instruction
-        self.cpu.pc = (8 * int) as u16;
-        self.cpu.interrupt_enabled = false;
+        self.cpu.interrupt(int as u8);
     }
 
     #[inline(always)]
     fn get_pc(&self) -> u16 { self.cpu.pc }
 
+    #[inline(always)]
+    fn set_pc(&mut self, value: u16) { self.cpu.pc = value; }
+
+    #[inline(always)]
+    fn get_sp(&self) -> u16 { self.cpu.sp }
+    #[inline(always)]
+    fn set_sp(&mut self, value: u16) { self.cpu.sp = value; }
+
+    #[inline(always)]
+    fn get_flags(&self) -> u8 { self.cpu.cc.to_byte() }
+    #[inline(always)]
+    fn set_flags(&mut self, value: u8) { self.cpu.cc = ConditionCodes::from_byte(value); }
+
+    #[inline(always)]
+    fn get_interrupt_enabled(&self) -> bool { self.cpu.interrupt_enabled }
+
     #[inline(always)]
     fn get_a(&self) -> u8 { self.cpu.a }
     #[inline(always)]
@@ -121,4 +306,239 @@ impl Machine for Cpm {
     fn write(&mut self, addr: u16, value: u8) {
         self.cpu.mem.write(addr, value);
     }
+
+    fn set_watches(&mut self, addrs: &[u16]) {
+        self.cpu.mem.set_watches(addrs);
+    }
+
+    fn take_accesses(&mut self) -> Vec<(u16, Access)> {
+        self.cpu.mem.take_accesses()
+    }
+
+    fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    fn set_registers(&mut self, registers: &Registers) {
+        self.cpu.set_registers(registers);
+    }
+
+    type Snapshot = Registers;
+
+    fn snapshot(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    fn restore(&mut self, snapshot: &Registers) {
+        self.cpu.set_registers(snapshot);
+    }
+
+    fn set_journal(&mut self, on: bool) {
+        self.cpu.mem.set_journal(on);
+    }
+
+    fn take_journal(&mut self) -> Vec<(u16, u8)> {
+        self.cpu.mem.take_journal()
+    }
+
+    fn stopped(&self) -> bool {
+        !self.running
+    }
+}
+
+#[cfg(test)]
+use console::BufferConsole;
+
+/// Run one of the standard 8080 exercisers and return
+/// what it printed. These are not distributed with the
+/// emulator so they're looked for in `tests/cpu_tests`
+/// or the directory given by `EMU8080_CPU_TESTS`, and
+/// skipped with a note if they aren't there. The flag
+/// cases they found are unit tested in cpu.rs.
+#[cfg(test)]
+fn run_cpu_test(name: &str) -> Option<String> {
+    use std::env;
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    let dir = match env::var("EMU8080_CPU_TESTS") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("cpu_tests"),
+    };
+    let mut file = match File::open(dir.join(name)) {
+        Ok(file) => file,
+        Err(_) => {
+            println!("Skipping {}, not found in {}", name, dir.display());
+            return None;
+        },
+    };
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+
+    let mut machine = Cpm::with_console(&buf, &[], &dir, BufferConsole::new(b""));
+    machine.run();
+    Some(String::from_utf8_lossy(&machine.into_console().output).into_owned())
+}
+
+#[test]
+fn test_tst8080() {
+    if let Some(output) = run_cpu_test("TST8080.COM") {
+        assert!(output.contains("CPU IS OPERATIONAL"), "{}", output);
+    }
+}
+
+#[test]
+fn test_8080pre() {
+    if let Some(output) = run_cpu_test("8080PRE.COM") {
+        assert!(output.contains("8080 Preliminary tests complete"), "{}", output);
+    }
+}
+
+#[test]
+fn test_cputest() {
+    if let Some(output) = run_cpu_test("CPUTEST.COM") {
+        assert!(output.contains("CPU TESTS OK"), "{}", output);
+    }
+}
+
+// This takes several minutes in a debug build,
+// run it with `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn test_8080exm() {
+    if let Some(output) = run_cpu_test("8080EXM.COM") {
+        assert!(!output.contains("ERROR"), "{}", output);
+        assert!(output.contains("Tests complete"), "{}", output);
+    }
+}
+
+#[test]
+fn test_bdos_print_string() {
+    // LXI D,0x010c; MVI C,9; CALL 5; JMP 0; NOP; "hi$"
+    let program = [0x11, 0x0c, 0x01, 0x0e, 0x09, 0xcd, 0x05, 0x00,
+                   0xc3, 0x00, 0x00, 0x00, b'h', b'i', b'$'];
+    let mut machine = Cpm::with_console(&program, &[], Path::new("."), BufferConsole::new(b""));
+    machine.run();
+    assert_eq!(machine.into_console().output, b"hi".to_vec());
+}
+
+#[test]
+fn test_bdos_disk_tables() {
+    // MVI C,31; CALL 5; SHLD 0x0200; MVI C,27; CALL 5; SHLD 0x0202; JMP 0
+    let program = [0x0e, 0x1f, 0xcd, 0x05, 0x00, 0x22, 0x00, 0x02,
+                   0x0e, 0x1b, 0xcd, 0x05, 0x00, 0x22, 0x02, 0x02, 0xc3, 0x00, 0x00];
+    let mut machine = Cpm::with_console(&program, &[], Path::new("."), BufferConsole::new(b""));
+    machine.run();
+    let mem = &machine.cpu.mem;
+    // Both are the tables the BIOS gives drive A
+    let dpb = make_u16(mem.read(0x0200), mem.read(0x0201));
+    assert_eq!(dpb, machine.bios.dpb());
+    assert_eq!(mem.read(dpb), 26);
+    assert_eq!(make_u16(mem.read(0x0202), mem.read(0x0203)), machine.bios.alv(0));
+}
+
+#[test]
+fn test_parse_fcb() {
+    assert_eq!(parse_fcb("b:zork1.dat"), (2, *b"ZORK1   DAT"));
+    assert_eq!(parse_fcb("*.com"), (0, *b"????????COM"));
+    assert_eq!(parse_fcb("ab*.*"), (0, *b"AB?????????"));
+    assert_eq!(parse_fcb(""), (0, *b"           "));
+}
+
+#[test]
+fn test_zero_page() {
+    let args = vec![String::from("a:in.txt"), String::from("out")];
+    let machine = Cpm::with_console(&[0xc9], &args, Path::new("."), BufferConsole::new(b""));
+    let mem = &machine.cpu.mem;
+    assert_eq!(make_u16(mem.read(0x0006), mem.read(0x0007)), BDOS_ENTRY);
+    // JMP to the BIOS warm boot entry
+    assert_eq!(make_u16(mem.read(0x0001), mem.read(0x0002)), machine.bios.base() + 3);
+    assert_eq!(mem.read(FCB1), 1);
+    assert_eq!(mem.read(FCB1 + 1), b'I');
+    assert_eq!(mem.read(FCB2 + 1), b'O');
+    assert_eq!(mem.read(COMMAND_TAIL), 13);
+    assert_eq!(mem.read(COMMAND_TAIL + 1), b' ');
+    assert_eq!(mem.read(COMMAND_TAIL + 2), b'A');
+    assert_eq!(mem.read(COMMAND_TAIL + 14), 0);
+    assert_eq!(machine.cpu.pc, TPA);
+    assert_eq!(mem.read(machine.cpu.sp), 0);
+}
+
+#[test]
+fn test_long_command_tail() {
+    let args: Vec<String> = (0..50).map(|i| format!("arg{}", i)).collect();
+    let machine = Cpm::with_console(&[0xc3, 0x00, 0x00], &args, Path::new("."), BufferConsole::new(b""));
+    let mem = &machine.cpu.mem;
+    assert_eq!(mem.read(COMMAND_TAIL), 127);
+    // Cut off in the middle of " ARG22"
+    assert_eq!(mem.read(COMMAND_TAIL + 127), b'2');
+    // The program is untouched
+    assert_eq!(mem.read(TPA), 0xc3);
+}
+
+#[test]
+fn test_return_exits() {
+    let mut machine = Cpm::with_console(&[0xc9], &[], Path::new("."), BufferConsole::new(b""));
+    machine.run();
+    assert!(!machine.running);
+}
+
+#[test]
+fn test_boot_from_disk() {
+    use disk::{SECTORS, SECTOR_SIZE, TRACKS};
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+
+    // The "CCP" prints OK through the BIOS then reads track 2
+    // sector 1 and prints its first byte, before waiting for
+    // input that never arrives.
+    let ccp = [
+        0x0e, b'O', 0xcd, 0x0c, 0xfa,   // MVI C,'O'; CALL CONOUT
+        0x0e, b'K', 0xcd, 0x0c, 0xfa,   // MVI C,'K'; CALL CONOUT
+        0x0e, 0x00, 0xcd, 0x1b, 0xfa,   // MVI C,0; CALL SELDSK
+        0x01, 0x02, 0x00, 0xcd, 0x1e, 0xfa, // LXI B,2; CALL SETTRK
+        0x01, 0x01, 0x00, 0xcd, 0x21, 0xfa, // LXI B,1; CALL SETSEC
+        0x01, 0x80, 0x00, 0xcd, 0x24, 0xfa, // LXI B,0x80; CALL SETDMA
+        0xcd, 0x27, 0xfa,               // CALL READ
+        0x3a, 0x80, 0x00, 0x4f,         // LDA 0x80; MOV C,A
+        0xcd, 0x0c, 0xfa,               // CALL CONOUT
+        0xcd, 0x09, 0xfa,               // CALL CONIN
+    ];
+    let mut image = vec![0xe5; TRACKS as usize * SECTORS as usize * SECTOR_SIZE];
+    for (i, x) in ccp.iter().enumerate() {
+        image[SECTOR_SIZE + i] = *x;
+    }
+    image[2 * SECTORS as usize * SECTOR_SIZE] = b'!';
+
+    let dir = ::std::env::temp_dir().join("emu8080-boot");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("system.dsk");
+    File::create(&path).unwrap().write_all(&image).unwrap();
+
+    let disk = Disk::open(&path).unwrap();
+    let mut machine = Cpm::boot_with_console(vec![disk], BufferConsole::new(b"")).unwrap();
+    machine.run();
+    assert_eq!(machine.into_console().output, b"OK!".to_vec());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_too_many_disks() {
+    use bios::DRIVES;
+    use std::fs;
+    use std::fs::File;
+
+    let dir = ::std::env::temp_dir().join("emu8080-drives");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("blank.dsk");
+    File::create(&path).unwrap();
+
+    let disks = (0..DRIVES + 1).map(|_| Disk::open(&path).unwrap()).collect();
+    assert!(Cpm::boot_with_console(disks, BufferConsole::new(b"")).is_err());
+    let mut machine = Cpm::with_console(&[0xc9], &[], Path::new("."), BufferConsole::new(b""));
+    assert!(machine.mount(DRIVES - 1, Disk::open(&path).unwrap()).is_ok());
+    assert!(machine.mount(DRIVES, Disk::open(&path).unwrap()).is_err());
+    fs::remove_dir_all(&dir).unwrap();
 }
@@ -1,6 +1,7 @@
 
 use std::mem;
 
+use disassemble::{decode, Instruction};
 use memory::Memory;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -24,6 +25,30 @@ impl ConditionCodes {
             pad: 0x00,
         }
     }
+
+    /// The flags as the byte `PUSH PSW` stores,
+    /// `S Z 0 AC 0 P 1 CY` from the top bit down.
+    pub fn to_byte(&self) -> u8 {
+        let mut psw = 0b00000010;
+        psw |= self.cy as u8;
+        psw |= self.p << 2;
+        psw |= self.ac << 4;
+        psw |= self.z << 6;
+        psw |= self.s << 7;
+        psw
+    }
+
+    /// The flags from a byte as `POP PSW` loads them.
+    pub fn from_byte(x: u8) -> ConditionCodes {
+        ConditionCodes {
+            z: (x >> 6) & 0x01,
+            s: (x >> 7) & 0x01,
+            p: (x >> 2) & 0x01,
+            cy: (x & 0b00000001) != 0,
+            ac: (x >> 4) & 0x01,
+            pad: 0x00,
+        }
+    }
 }
 
 const PARITY_BYTES: [u8; 8] = [
@@ -66,6 +91,23 @@ fn make_u32(lo: u8, hi: u8) -> u32 {
     (hi as u32) << 8 | lo as u32
 }
 
+/// A snapshot of everything in the CPU but memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub cc: ConditionCodes,
+    pub interrupt_enabled: bool,
+    pub halted: bool,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Cpu {
     pub a: u8,
@@ -80,6 +122,7 @@ pub struct Cpu {
     pub mem: Memory,
     pub cc: ConditionCodes,
     pub interrupt_enabled: bool,
+    pub halted: bool, // Set by HLT, cleared by an interrupt
     pub ports: [u8; 8],
     pub speed: usize, // Clock speed in Hz
 }
@@ -99,11 +142,44 @@ impl Cpu {
             mem: memory,
             cc: ConditionCodes::new(),
             interrupt_enabled: false,
+            halted: false,
             ports: [0; 8],
             speed: 2_000_000,
         }
     }
 
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+            cc: self.cc.clone(),
+            interrupt_enabled: self.interrupt_enabled,
+            halted: self.halted,
+        }
+    }
+
+    pub fn set_registers(&mut self, registers: &Registers) {
+        self.a = registers.a;
+        self.b = registers.b;
+        self.c = registers.c;
+        self.d = registers.d;
+        self.e = registers.e;
+        self.h = registers.h;
+        self.l = registers.l;
+        self.sp = registers.sp;
+        self.pc = registers.pc;
+        self.cc = registers.cc.clone();
+        self.interrupt_enabled = registers.interrupt_enabled;
+        self.halted = registers.halted;
+    }
+
     #[inline(always)]
     pub fn bc(&self) -> u16 {
         make_u16(self.c, self.b)
@@ -140,11 +216,14 @@ impl Cpu {
         self.l = lo;
     }
 
+    /// Push (lo, hi) on to the stack, SP
+    /// wraps around the top of memory.
     #[inline(always)]
     pub fn push(&mut self, lo: u8, hi: u8) {
-        self.mem.write(self.sp - 2, lo);
-        self.mem.write(self.sp - 1, hi);
-        self.sp -= 2;
+        let sp = self.sp;
+        self.mem.write(sp.wrapping_sub(2), lo);
+        self.mem.write(sp.wrapping_sub(1), hi);
+        self.sp = sp.wrapping_sub(2);
     }
 
     /// Pop (lo, hi) from stack and increment
@@ -152,8 +231,8 @@ impl Cpu {
     #[inline(always)]
     pub fn pop(&mut self) -> (u8, u8) {
         let lo = self.mem.read(self.sp);
-        let hi = self.mem.read(self.sp + 1);
-        self.sp += 2;
+        let hi = self.mem.read(self.sp.wrapping_add(1));
+        self.sp = self.sp.wrapping_add(2);
         (lo, hi)
     }
 
@@ -172,7 +251,9 @@ impl Cpu {
     fn dcr(&mut self, x: u8) -> u8 {
         let x = x.wrapping_sub(1);
         self.set_flags_zsp(x);
-        self.cc.ac = if x & 0x0f == 0 { 1 } else { 0 };
+        // Decrement is an addition of 0xff so there is
+        // a half carry unless the low nibble borrowed.
+        self.cc.ac = if x & 0x0f != 0x0f { 1 } else { 0 };
         x
     }
 
@@ -198,26 +279,39 @@ impl Cpu {
         self.a = a as u8;
     }
 
+    /// Subtract `x` and a borrow from the accumulator
+    /// the way the 8080 does, by adding the two's
+    /// complement, and return the result. All flags
+    /// are set but the accumulator is left untouched.
+    #[inline(always)]
+    fn subtract(&mut self, x: u8, borrow: bool) -> u8 {
+        let y = !x;
+        let a = self.a as u16 + y as u16 + (!borrow) as u16;
+        self.set_flags_zsp(a as u8);
+        // Carry is set when there was no carry out,
+        // which means the subtraction borrowed.
+        self.cc.cy = a <= 0xff;
+        self.cc.ac = if (self.a ^ y ^ (a as u8)) & 0x10 > 0 { 1 } else { 0 };
+        a as u8
+    }
+
     #[inline(always)]
     fn sub(&mut self, x: u8) {
-        let a = self.a as u16 - x as u16;
-        self.set_flags_arith(a);
-        self.cc.ac = if (self.a ^ x ^ (a as u8)) & 0x10 > 0 { 1 } else { 0 };
-        self.a = a as u8;
+        self.a = self.subtract(x, false);
     }
 
     #[inline(always)]
     fn sbb(&mut self, x: u8) {
-        let a = self.a as u16 - x as u16 - self.cc.cy as u16;
-        self.set_flags_arith(a);
-        self.cc.ac = if (self.a ^ x ^ (a as u8)) & 0x10 > 0 { 1 } else { 0 };
-        self.a = a as u8;
+        let cy = self.cc.cy;
+        self.a = self.subtract(x, cy);
     }
 
     #[inline(always)]
     fn ana(&mut self, x: u8) {
         let a = self.a & x;
         self.set_flags_logic(a);
+        // AND sets AC from bit 3 of either operand.
+        self.cc.ac = if (self.a | x) & 0x08 != 0 { 1 } else { 0 };
         self.a = a;
     }
 
@@ -261,9 +355,7 @@ impl Cpu {
 
     #[inline(always)]
     fn cmp(&mut self, x: u8) {
-        let a = self.a as u16 - x as u16;
-        self.set_flags_arith(a);
-        self.cc.ac = if (self.a ^ x ^ (a as u8)) & 0x10 > 0 { 1 } else { 0 };
+        self.subtract(x, false);
     }
 
     /// CALL instruction
@@ -283,6 +375,34 @@ impl Cpu {
         self.pc = make_u16(lo, hi);
     }
 
+    /// RST instruction, a one byte CALL
+    /// to the low memory vector 8 * n.
+    #[inline(always)]
+    fn rst(&mut self, n: u8) {
+        let ret = self.pc.wrapping_add(1);
+        let (lo, hi) = split_u16(ret);
+        self.push(lo, hi);
+        self.pc = (n as u16) * 8;
+    }
+
+    /// Service an interrupt by executing `RST n`
+    /// without fetching it from memory. This is
+    /// ignored if interrupts are disabled, otherwise
+    /// it disables further interrupts and wakes the
+    /// CPU if it was halted.
+    pub fn interrupt(&mut self, n: u8) {
+        if !self.interrupt_enabled {
+            return;
+        }
+        // PC already points to the next instruction
+        // (or the one after a HLT) so push it as is.
+        let (lo, hi) = split_u16(self.pc);
+        self.push(lo, hi);
+        self.pc = (n as u16) * 8;
+        self.interrupt_enabled = false;
+        self.halted = false;
+    }
+
     /// Read a data byte and increment PC.
     #[inline(always)]
     fn read_byte(&mut self) -> u8 {
@@ -300,6 +420,16 @@ impl Cpu {
         (lo, hi)
     }
 
+    /// Decode the instruction at PC without executing it.
+    pub fn instruction(&self) -> Instruction {
+        let bytes = [
+            self.mem.read(self.pc),
+            self.mem.read(self.pc.wrapping_add(1)),
+            self.mem.read(self.pc.wrapping_add(2)),
+        ];
+        decode(&bytes, 0).0
+    }
+
     #[inline(always)]
     pub fn read_u16(&mut self) -> u16 {
         let lo = self.mem.read(self.pc + 1);
@@ -336,6 +466,7 @@ impl Cpu {
     fn set_flags_logic(&mut self, x: u8) {
         self.set_flags_zsp(x);
         self.cc.cy = false;
+        self.cc.ac = 0;
     }
 
     /// Read a byte from memory at the address pointed to
@@ -343,9 +474,16 @@ impl Cpu {
     /// Return the number of clock cycles used by that op.
     #[inline(always)]
     pub fn step(&mut self) -> u32 {
+        if self.halted {
+            // Idle until an interrupt arrives, we
+            // still report cycles so time moves on.
+            return 4;
+        }
         let op = self.mem.read(self.pc);
         let cycles = match op {
-            0x00 => { 4 }, // NOP
+            // NOP, undocumented aliases are 0x08, 0x10,
+            // 0x18, 0x20, 0x28, 0x30 and 0x38.
+            0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => { 4 },
             0x01 => { // LXI B,word
                 let (c, b) = self.read_two_bytes();
                 self.c = c;
@@ -549,21 +687,22 @@ impl Cpu {
                 // accumulator is now greater than 9, or if the CY flag is set, 
                 // 6 is added to the most significant 4 bits of the accumulator.
                 // See also https://en.wikipedia.org/wiki/Half-carry_flag
-                let mut a = self.a as u16;
-                if a & 0x0f > 9 || self.cc.ac == 1 {
-                    a += 6;
-                    self.cc.ac = 1;
-                } else {
-                    self.cc.ac = 0;
+                // Both corrections are decided from the original
+                // value and applied as a single addition, which
+                // sets AC. CY is only ever set, never cleared.
+                let lsb = self.a & 0x0f;
+                let msb = self.a >> 4;
+                let mut correction = 0;
+                let mut cy = self.cc.cy;
+                if lsb > 9 || self.cc.ac == 1 {
+                    correction |= 0x06;
                 }
-                if a & 0xf0 > 0x90 || self.cc.cy {
-                    a += 0x60;
-                    self.cc.cy = true;
-                } else {
-                    self.cc.cy = false;
+                if msb > 9 || (msb >= 9 && lsb > 9) || self.cc.cy {
+                    correction |= 0x60;
+                    cy = true;
                 }
-                self.set_flags_zsp(a as u8);
-                self.a = a as u8;
+                self.add(correction);
+                self.cc.cy = cy;
                 4
             },
             0x29 => { // DAD H
@@ -644,9 +783,10 @@ impl Cpu {
             },
             0x39 => { // DAD SP
                 let hl = make_u32(self.l, self.h);
-                let sp = (self.sp as u32).wrapping_add(hl);
-                self.sp = (sp & 0x0000ffff) as u16;
-                self.cc.cy = (sp & 0xffff0000) != 0;
+                let hl = (self.sp as u32).wrapping_add(hl);
+                self.l = (hl & 0xff) as u8;
+                self.h = ((hl & 0xff00) >> 8) as u8;
+                self.cc.cy = (hl & 0xffff0000) != 0;
                 10
             },
             0x3a => { // LDA addr
@@ -676,6 +816,9 @@ impl Cpu {
                 self.cc.cy = !self.cc.cy;
                 4
             },
+            0x40 => { // MOV B,B
+                5
+            },
             0x41 => {  // MOV B,C
                 self.b = self.c;
                 5
@@ -889,9 +1032,10 @@ impl Cpu {
                 self.mem.write(addr, self.l);
                 7
             },
-            // 0x76 => { // HLT
-
-            // },
+            0x76 => { // HLT
+                self.halted = true;
+                7
+            },
             0x77 => { // MOV M,A
                 let addr = self.hl();
                 self.mem.write(addr, self.a);
@@ -1272,7 +1416,7 @@ impl Cpu {
                     10
                 }
             },
-            0xc3 => { // JMP addr
+            0xc3 | 0xcb => { // JMP addr (0xcb is undocumented)
                 self.pc = self.read_u16();
                 // Return here so we don't increment
                 // the program counter at the end of the method.
@@ -1305,7 +1449,11 @@ impl Cpu {
                     5
                 }
             },
-            0xc9 => { // RET
+            0xc7 => { // RST 0
+                self.rst(0);
+                return 11;
+            },
+            0xc9 | 0xd9 => { // RET (0xd9 is undocumented)
                 self.ret();
                 return 10;
             },
@@ -1327,7 +1475,7 @@ impl Cpu {
                 }
                 11
             },
-            0xcd => { // CALL addr
+            0xcd | 0xdd | 0xed | 0xfd => { // CALL addr (0xdd, 0xed, 0xfd are undocumented)
                 self.call();
                 return 17;
             },
@@ -1336,6 +1484,10 @@ impl Cpu {
                 self.adc(x);
                 7
             },
+            0xcf => { // RST 1
+                self.rst(1);
+                return 11;
+            },
             0xd0 => { // RNC
                 if !self.cc.cy {
                     self.ret();
@@ -1383,6 +1535,10 @@ impl Cpu {
                 self.sub(x);
                 7
             },
+            0xd7 => { // RST 2
+                self.rst(2);
+                return 11;
+            },
             0xd8 => { // RC
                 if self.cc.cy {
                     self.ret();
@@ -1419,6 +1575,10 @@ impl Cpu {
                 self.sbb(x);
                 7
             },
+            0xdf => { // RST 3
+                self.rst(3);
+                return 11;
+            },
             0xe0 => { // RPO
                 if self.cc.p == 0 {
                     self.ret();
@@ -1470,6 +1630,10 @@ impl Cpu {
                 self.ana(x);
                 7
             },
+            0xe7 => { // RST 4
+                self.rst(4);
+                return 11;
+            },
             0xe8 => { // RPE
                 if self.cc.p == 1 {
                     self.ret();
@@ -1510,15 +1674,14 @@ impl Cpu {
                 self.xra(x);
                 7
             },
+            0xef => { // RST 5
+                self.rst(5);
+                return 11;
+            },
             0xf1 => { // POP PSW
-                let x = self.mem.read(self.sp);
-                self.cc.cy = (x & 0b00000001) != 0;
-                self.cc.p = (x >> 2) & 0x01;
-                self.cc.ac = (x >> 4) & 0x01;
-                self.cc.z = (x >> 6) & 0x01;
-                self.cc.s = (x >> 7) & 0x01;
-                self.a = self.mem.read(self.sp + 1);
-                self.sp += 2;
+                let (x, a) = self.pop();
+                self.cc = ConditionCodes::from_byte(x);
+                self.a = a;
                 10
             },
             0xf0 => { // RP
@@ -1538,6 +1701,10 @@ impl Cpu {
                 }
                 10
             },
+            0xf3 => { // DI
+                self.interrupt_enabled = false;
+                4
+            },
             0xf4 => { // CP addr
                 if self.cc.s == 0 {
                     self.call();
@@ -1548,15 +1715,9 @@ impl Cpu {
                 11
             },
             0xf5 => { // PUSH PSW
-                self.mem.write(self.sp - 1, self.a);
-                let mut psw = 0b00000010;
-                psw |= self.cc.cy as u8;
-                psw |= self.cc.p << 2;
-                psw |= self.cc.ac << 4;
-                psw |= self.cc.z << 6;
-                psw |= self.cc.s << 7;
-                self.mem.write(self.sp - 2, psw);
-                self.sp -= 2;
+                let psw = self.cc.to_byte();
+                let a = self.a;
+                self.push(psw, a);
                 11
             },
             0xf6 => { // ORI D8
@@ -1564,6 +1725,10 @@ impl Cpu {
                 self.ora(x);
                 7
             },
+            0xf7 => { // RST 6
+                self.rst(6);
+                return 11;
+            },
             0xf8 => { // RM
                 if self.cc.s == 1 {
                     self.ret();
@@ -1603,7 +1768,10 @@ impl Cpu {
                 self.cmp(x);
                 7
             },
-            x => panic!("Unimplemented OpCode: {:>0pad$x}", x, pad=2)
+            0xff => { // RST 7
+                self.rst(7);
+                return 11;
+            },
         };
         self.pc += 1;
         cycles
@@ -1636,3 +1804,252 @@ fn test_parity_odd2() {
     let p = parity(0b1000000);
     assert_eq!(p, 0);
 }
+
+#[cfg(test)]
+fn cpu_with_program(program: &[u8]) -> Cpu {
+    let mut cpu = Cpu::new(Memory::with_data(program));
+    cpu.sp = 0x2400;
+    cpu
+}
+
+#[test]
+fn test_rst() {
+    let mut cpu = cpu_with_program(&[0x00, 0x00, 0x00, 0xdf]);
+    cpu.pc = 3;
+    let cycles = cpu.step();
+    assert_eq!(cycles, 11);
+    assert_eq!(cpu.pc, 0x18);
+    assert_eq!(cpu.pop(), (0x04, 0x00));
+}
+
+#[test]
+fn test_rst_at_top_of_memory() {
+    let mut cpu = cpu_with_program(&[]);
+    cpu.mem.write(0xffff, 0xc7);
+    cpu.pc = 0xffff;
+    cpu.step();
+    assert_eq!(cpu.pc, 0x00);
+    assert_eq!(cpu.pop(), (0x00, 0x00));
+}
+
+#[test]
+fn test_hlt_until_interrupt() {
+    let mut cpu = cpu_with_program(&[0xfb, 0x76, 0x00]);
+    cpu.step();
+    assert_eq!(cpu.step(), 7);
+    assert!(cpu.halted);
+    assert_eq!(cpu.pc, 2);
+    cpu.step();
+    assert_eq!(cpu.pc, 2);
+    cpu.interrupt(1);
+    assert!(!cpu.halted);
+    assert!(!cpu.interrupt_enabled);
+    assert_eq!(cpu.pc, 0x08);
+    assert_eq!(cpu.pop(), (0x02, 0x00));
+}
+
+#[test]
+fn test_interrupt_ignored_when_disabled() {
+    let mut cpu = cpu_with_program(&[0xf3, 0x76]);
+    cpu.interrupt_enabled = true;
+    cpu.step();
+    assert!(!cpu.interrupt_enabled);
+    cpu.step();
+    cpu.interrupt(2);
+    assert!(cpu.halted);
+    assert_eq!(cpu.pc, 2);
+}
+
+#[test]
+fn test_undocumented_aliases() {
+    // 0x08 NOP, 0xcb JMP 0x0008, 0xdd CALL 0x000c, 0xd9 RET
+    let mut cpu = cpu_with_program(&[
+        0x08, 0xcb, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xdd, 0x0c, 0x00, 0x00, 0xd9]);
+    assert_eq!(cpu.step(), 4);
+    assert_eq!(cpu.step(), 10);
+    assert_eq!(cpu.pc, 0x08);
+    assert_eq!(cpu.step(), 17);
+    assert_eq!(cpu.pc, 0x0c);
+    assert_eq!(cpu.step(), 10);
+    assert_eq!(cpu.pc, 0x0b);
+}
+
+#[test]
+fn test_all_opcodes_implemented() {
+    for op in 0..256 {
+        let mut cpu = cpu_with_program(&[op as u8, 0x00, 0x20]);
+        // Point every register pair at RAM.
+        cpu.a = 0xff;
+        cpu.set_bc(0x2100);
+        cpu.set_de(0x2100);
+        cpu.set_hl(0x2100);
+        cpu.step();
+    }
+}
+
+#[test]
+fn test_decode_agrees_with_step() {
+    // With every flag clear and then every flag set each
+    // conditional branch is both taken and not taken.
+    for &flag in &[0, 1] {
+        for op in 0..256 {
+            let mut cpu = cpu_with_program(&[op as u8, 0x00, 0x20]);
+            cpu.a = 0xff;
+            cpu.set_bc(0x2100);
+            cpu.set_de(0x2100);
+            cpu.set_hl(0x2100);
+            cpu.cc.z = flag;
+            cpu.cc.s = flag;
+            cpu.cc.p = flag;
+            cpu.cc.cy = flag == 1;
+            // Returns go to 0x1234
+            cpu.mem.write(cpu.sp, 0x34);
+            cpu.mem.write(cpu.sp + 1, 0x12);
+            let instr = cpu.instruction();
+            let cycles = cpu.step();
+            if cpu.pc as usize == instr.size() {
+                assert_eq!(cycles, instr.cycles(), "{:02x} {}", op, instr);
+            } else {
+                let target = match instr {
+                    Instruction::Ret | Instruction::Rcc(_) => Some(0x1234),
+                    Instruction::Pchl => Some(0x2100),
+                    _ => instr.target(),
+                };
+                assert_eq!(Some(cpu.pc), target, "{:02x} {}", op, instr);
+                assert_eq!(cycles, instr.cycles_taken(), "{:02x} {}", op, instr);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sub_flags() {
+    // MVI A,0x10; SUI 0x01
+    let mut cpu = cpu_with_program(&[0x3e, 0x10, 0xd6, 0x01]);
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.a, 0x0f);
+    assert!(!cpu.cc.cy);
+    assert_eq!(cpu.cc.ac, 0);
+    // SUI 0x20 borrows
+    let mut cpu = cpu_with_program(&[0xd6, 0x20]);
+    cpu.a = 0x10;
+    cpu.step();
+    assert_eq!(cpu.a, 0xf0);
+    assert!(cpu.cc.cy);
+    assert_eq!(cpu.cc.ac, 1);
+    assert_eq!(cpu.cc.s, 1);
+}
+
+#[test]
+fn test_daa() {
+    // MVI A,0x19; ADI 0x28; DAA
+    let mut cpu = cpu_with_program(&[0x3e, 0x19, 0xc6, 0x28, 0x27]);
+    cpu.step();
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.a, 0x47);
+    assert!(!cpu.cc.cy);
+    // MVI A,0x99; ADI 0x01; DAA
+    let mut cpu = cpu_with_program(&[0x3e, 0x99, 0xc6, 0x01, 0x27]);
+    cpu.step();
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.a, 0x00);
+    assert!(cpu.cc.cy);
+    assert_eq!(cpu.cc.z, 1);
+}
+
+#[test]
+fn test_sbb_and_cmp_flags() {
+    // SBB 0 with a borrow in takes the low nibble
+    // below zero, so AC is clear
+    let mut cpu = cpu_with_program(&[0xde, 0x00]);
+    cpu.a = 0x10;
+    cpu.cc.cy = true;
+    cpu.step();
+    assert_eq!(cpu.a, 0x0f);
+    assert!(!cpu.cc.cy);
+    assert_eq!(cpu.cc.ac, 0);
+    // CMP of equal values leaves A and sets Z and AC
+    let mut cpu = cpu_with_program(&[0xb8]);
+    cpu.a = 0x05;
+    cpu.b = 0x05;
+    cpu.step();
+    assert_eq!(cpu.a, 0x05);
+    assert_eq!(cpu.cc.z, 1);
+    assert!(!cpu.cc.cy);
+    assert_eq!(cpu.cc.ac, 1);
+    // CPI of a larger value borrows
+    let mut cpu = cpu_with_program(&[0xfe, 0x06]);
+    cpu.a = 0x05;
+    cpu.step();
+    assert!(cpu.cc.cy);
+    assert_eq!(cpu.cc.z, 0);
+}
+
+#[test]
+fn test_dcr_ac() {
+    // DCR A; DCR A
+    let mut cpu = cpu_with_program(&[0x3d, 0x3d]);
+    cpu.a = 0x11;
+    cpu.step();
+    assert_eq!(cpu.a, 0x10);
+    assert_eq!(cpu.cc.ac, 1);
+    cpu.step();
+    assert_eq!(cpu.a, 0x0f);
+    assert_eq!(cpu.cc.ac, 0);
+}
+
+#[test]
+fn test_ana_ac() {
+    // ANI 0; ANI 0
+    let mut cpu = cpu_with_program(&[0xe6, 0x00, 0xe6, 0x00]);
+    cpu.a = 0x08;
+    cpu.step();
+    assert_eq!(cpu.a, 0x00);
+    assert_eq!(cpu.cc.ac, 1);
+    assert!(!cpu.cc.cy);
+    cpu.step();
+    assert_eq!(cpu.cc.ac, 0);
+    // ANA B with bit 3 set in B only
+    let mut cpu = cpu_with_program(&[0xa0]);
+    cpu.a = 0xf0;
+    cpu.b = 0x0f;
+    cpu.step();
+    assert_eq!(cpu.a, 0x00);
+    assert_eq!(cpu.cc.ac, 1);
+}
+
+#[test]
+fn test_daa_half_carry() {
+    // MVI A,0x09; ADI 0x09; DAA, the low nibble is
+    // only out of range going by AC
+    let mut cpu = cpu_with_program(&[0x3e, 0x09, 0xc6, 0x09, 0x27]);
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.a, 0x12);
+    assert_eq!(cpu.cc.ac, 1);
+    cpu.step();
+    assert_eq!(cpu.a, 0x18);
+    assert!(!cpu.cc.cy);
+}
+
+#[test]
+fn test_dad_sp() {
+    // DAD SP
+    let mut cpu = cpu_with_program(&[0x39, 0x39]);
+    cpu.h = 0x12;
+    cpu.l = 0x34;
+    cpu.sp = 0x1111;
+    cpu.step();
+    assert_eq!((cpu.h, cpu.l), (0x23, 0x45));
+    assert!(!cpu.cc.cy);
+    cpu.h = 0x80;
+    cpu.l = 0x00;
+    cpu.sp = 0x8001;
+    cpu.step();
+    assert_eq!((cpu.h, cpu.l), (0x00, 0x01));
+    assert!(cpu.cc.cy);
+}
@@ -1,36 +1,69 @@
 
+use std::collections::VecDeque;
 use std::io;
-use std::io::{BufWriter, BufRead, BufReader, Read, Write};
+use std::io::{BufRead, Write};
+use std::str;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
 use nom;
-use nom::{eof, hex_u32, space};
+use nom::{digit, eof, hex_digit, space};
 
+use breakpoint::{Breakpoint, Compare, Condition, Kind, Operand};
+use disassemble::{decode, disassemble_with, opcode_len, Instruction, Line};
 use machine;
 use machine::{Machine};
+use memory::Access;
+use symbols::Symbols;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum Printable {
     Register(machine::Reg),
-    Addr(u16),
+    Addr(String),
     Breakpoints
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum Cmd {
-    Break(u16),
+    Break(String, Option<Condition>),
+    Watch(String, bool, bool, Option<Condition>), // Break on reading and/or writing a location
+    Delete(Option<usize>), // Delete a breakpoint, or all of them
+    Enable(usize),
+    Disable(usize),
     Continue,
     Exit, // Exit the debugger
-    List, // Print context around current instruction
-    Step,
+    Next, // Step over calls
+    Finish, // Run until the current subroutine returns
+    Until(String), // Run until PC reaches a location
+    ReverseStep(Option<usize>),
+    ReverseContinue, // Run backwards until a breakpoint is hit
+    List(Option<String>, Option<usize>), // Print context around an instruction, PC by default
+    Step(Option<usize>),
     Print(Printable),
+    Regs, // Print all the registers and flags
+    Dump(String, Option<usize>), // Hexdump memory
+    Set(String, Vec<u16>), // Write to memory or a register
+    Find(Vec<u16>), // Search memory for bytes
+    Stack(Option<usize>), // Print the top of the stack
 }
 
+// An address in hex or the name of a symbol,
+// resolved by the debugger.
+named!(parse_location<String>,
+    map_res!(
+        is_not!(" \t"),
+        |bytes| str::from_utf8(bytes).map(String::from)
+    )
+);
+
 named!(parse_addr<Printable>,
     chain!(
         tag!("a")     ~
         many1!(space) ~
-        addr: hex_u32 ,
-        || { Printable::Addr(addr as u16) }
+        addr: parse_location ,
+        || { Printable::Addr(addr) }
     )
 );
 
@@ -68,92 +101,1223 @@ named!(parse_print<Cmd>,
     )
 );
 
+named!(parse_register_name<String>,
+    map_res!(
+        alt_complete!(
+            tag!("bc") | tag!("de") | tag!("hl") | tag!("sp") | tag!("pc") |
+            tag!("a") | tag!("b") | tag!("c") | tag!("d") | tag!("e") | tag!("f") | tag!("h") | tag!("l")
+        ),
+        |bytes| str::from_utf8(bytes).map(String::from)
+    )
+);
+
+// A hex number, with or without a leading 0x.
+named!(parse_number<u16>,
+    chain!(
+        opt!(complete!(tag!("0x"))) ~
+        value: parse_hex ,
+        || { value }
+    )
+);
+
+named!(parse_operand<Operand>,
+    alt_complete!(
+        chain!(
+            tag!("[") ~
+            location: map_res!(is_not!("]"), str::from_utf8) ~
+            tag!("]") ,
+            || { Operand::Memory(String::from(location)) }
+        ) |
+        parse_register_name => { |name| Operand::Register(name) } |
+        parse_number => { |value| Operand::Value(value) }
+    )
+);
+
+named!(parse_compare<Compare>,
+    alt_complete!(
+        tag!("==") => { |_| Compare::Eq } |
+        tag!("!=") => { |_| Compare::Ne } |
+        tag!("<=") => { |_| Compare::Le } |
+        tag!(">=") => { |_| Compare::Ge } |
+        tag!("<") => { |_| Compare::Lt } |
+        tag!(">") => { |_| Compare::Gt }
+    )
+);
+
+named!(parse_condition<Condition>,
+    chain!(
+        lhs: parse_operand           ~
+        opt!(complete!(space))       ~
+        compare: parse_compare       ~
+        opt!(complete!(space))       ~
+        rhs: parse_operand ,
+        || { Condition { lhs: lhs, compare: compare, rhs: rhs } }
+    )
+);
+
+named!(parse_if<Condition>,
+    chain!(
+        many1!(space) ~
+        tag!("if")    ~
+        many1!(space) ~
+        condition: parse_condition ,
+        || { condition }
+    )
+);
+
 named!(parse_break<Cmd>,
     chain!(
         tag!("b")      ~
         many1!(space)  ~
-        value: hex_u32 , // TODO: make a hex_u16 parser based on hex_u32
-        || { Cmd::Break(value as u16) }
+        location: parse_location ~
+        condition: opt!(complete!(parse_if)) ,
+        || { Cmd::Break(location, condition) }
+    )
+);
+
+named!(parse_access<(bool, bool)>,
+    chain!(
+        many1!(space) ~
+        access: alt_complete!(
+            tag!("rw") => { |_| (true, true) } |
+            tag!("r") => { |_| (true, false) } |
+            tag!("w") => { |_| (false, true) }
+        ) ,
+        || { access }
+    )
+);
+
+named!(parse_watch<Cmd>,
+    chain!(
+        tag!("watch")  ~
+        many1!(space)  ~
+        location: parse_location ~
+        access: opt!(complete!(parse_access)) ~
+        condition: opt!(complete!(parse_if)) ,
+        || {
+            let (read, write) = access.unwrap_or((false, true));
+            Cmd::Watch(location, read, write, condition)
+        }
+    )
+);
+
+named!(parse_delete<Cmd>,
+    chain!(
+        tag!("delete") ~
+        number: opt!(complete!(chain!(many1!(space) ~ number: parse_count, || { number }))) ,
+        || { Cmd::Delete(number) }
+    )
+);
+
+named!(parse_enable<Cmd>,
+    chain!(
+        tag!("enable") ~
+        many1!(space)  ~
+        number: parse_count ,
+        || { Cmd::Enable(number) }
+    )
+);
+
+named!(parse_disable<Cmd>,
+    chain!(
+        tag!("disable") ~
+        many1!(space)   ~
+        number: parse_count ,
+        || { Cmd::Disable(number) }
+    )
+);
+
+named!(parse_count<usize>,
+    map_res!(
+        map_res!(digit, str::from_utf8),
+        str::FromStr::from_str
+    )
+);
+
+named!(parse_list<Cmd>,
+    chain!(
+        tag!("l") ~
+        location: opt!(chain!(many1!(space) ~ location: parse_location, || { location })) ~
+        count: opt!(chain!(many1!(space) ~ count: parse_count, || { count })) ,
+        || { Cmd::List(location, count) }
+    )
+);
+
+named!(parse_hex<u16>,
+    map_res!(
+        map_res!(hex_digit, str::from_utf8),
+        |digits| u16::from_str_radix(digits, 16)
+    )
+);
+
+// One or more hex values, each after a space.
+named!(parse_values<Vec<u16> >,
+    many1!(complete!(chain!(many1!(space) ~ value: parse_hex, || { value })))
+);
+
+named!(parse_dump<Cmd>,
+    chain!(
+        tag!("x")     ~
+        many1!(space) ~
+        location: parse_location ~
+        count: opt!(chain!(many1!(space) ~ count: parse_count, || { count })) ,
+        || { Cmd::Dump(location, count) }
+    )
+);
+
+named!(parse_set<Cmd>,
+    chain!(
+        tag!("set")   ~
+        many1!(space) ~
+        target: parse_location ~
+        values: parse_values ,
+        || { Cmd::Set(target, values) }
+    )
+);
+
+named!(parse_find<Cmd>,
+    chain!(
+        tag!("find") ~
+        values: parse_values ,
+        || { Cmd::Find(values) }
+    )
+);
+
+named!(parse_step<Cmd>,
+    chain!(
+        tag!("s") ~
+        count: opt!(complete!(chain!(many1!(space) ~ count: parse_count, || { count }))) ,
+        || { Cmd::Step(count) }
+    )
+);
+
+named!(parse_reverse_step<Cmd>,
+    chain!(
+        tag!("rs") ~
+        count: opt!(complete!(chain!(many1!(space) ~ count: parse_count, || { count }))) ,
+        || { Cmd::ReverseStep(count) }
+    )
+);
+
+named!(parse_until<Cmd>,
+    chain!(
+        tag!("until") ~
+        many1!(space) ~
+        location: parse_location ,
+        || { Cmd::Until(location) }
+    )
+);
+
+named!(parse_stack<Cmd>,
+    chain!(
+        tag!("stack") ~
+        count: opt!(chain!(many1!(space) ~ count: parse_count, || { count })) ,
+        || { Cmd::Stack(count) }
     )
 );
 
 named!(parse_cmd<Cmd>,
-    // alt! tags must be in length order, shortest -> longest
-    // See http://rust.unhandledexpression.com/nom/macro.alt!.html
-    alt!(
-        eof => { |_| Cmd::Exit } |
-        tag!("c") => { |_| Cmd::Continue } |
-        tag!("s") => { |_| Cmd::Step } |
-        parse_break |
-        parse_print
+    // Commands that start with the same letters must
+    // come longest first, so `s` doesn't match `stack`,
+    // and the whole line must be used
+    chain!(
+        cmd: alt_complete!(
+            eof => { |_| Cmd::Exit } |
+            parse_disable |
+            parse_delete |
+            parse_enable |
+            parse_watch |
+            tag!("finish") => { |_| Cmd::Finish } |
+            parse_until |
+            tag!("regs") => { |_| Cmd::Regs } |
+            tag!("rc") => { |_| Cmd::ReverseContinue } |
+            parse_reverse_step |
+            parse_stack |
+            parse_find |
+            parse_set |
+            tag!("c") => { |_| Cmd::Continue } |
+            tag!("n") => { |_| Cmd::Next } |
+            parse_step |
+            parse_dump |
+            parse_list |
+            parse_break |
+            parse_print
+        ) ~
+        eof ,
+        || { cmd }
     )
 );
 
+/// How often the machine gets to idle
+/// while waiting at the prompt.
+const IDLE_MS: u64 = 16;
+
+/// Read lines from stdin on another thread so
+/// the machine can idle while waiting for them.
+fn stdin_lines() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => if tx.send(line).is_err() { break },
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
+
+/// Register names `set` takes, ahead of
+/// addresses so `set a 1` sets A, not 000a.
+const REGISTERS: [&'static str; 13] = ["a", "b", "c", "d", "e", "h", "l", "f", "bc", "de", "hl", "sp", "pc"];
+
+/// Instructions listed by `l` without a count.
+const LIST_COUNT: usize = 10;
+
+/// Instructions listed ahead of the one asked for.
+const LIST_BEFORE: usize = 3;
+
+/// Stack entries shown by `stack` without a count.
+const STACK_COUNT: usize = 8;
+
+/// Bytes shown by `x` without a count.
+const DUMP_COUNT: usize = 64;
+
+/// Bytes on each line of `x`.
+const DUMP_WIDTH: usize = 16;
+
+/// Steps kept to be undone by reverse execution.
+const HISTORY_SIZE: usize = 100_000;
+
+/// What a step changed, to undo it.
+struct Delta<S> {
+    snapshot: S, // Of the machine before the step
+    writes: Vec<(u16, u8)>, // Addresses written with their old values
+    cycles: u32,
+}
+
 /// Virtual debugger that provides a nicer interface
 /// for stepping through a program.
 pub struct Debugger<T: Machine> {
     machine: T,
-    breakpoints: Vec<u16>,
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint: usize, // Number given to the next breakpoint
+    symbols: Symbols,
+    cycles: u64, // Cycles run under the debugger
+    history: VecDeque<Delta<T::Snapshot>>, // Most recent step last
 }
 
 impl<T: Machine> Debugger<T> {
-    pub fn new(machine: T) -> Self {
+    pub fn new(mut machine: T) -> Self {
+        machine.set_journal(true);
         Debugger {
             machine: machine,
             breakpoints: Vec::new(),
+            next_breakpoint: 1,
+            symbols: Symbols::new(),
+            cycles: 0,
+            history: VecDeque::new(),
         }
     }
 
-    /// Should the program break execution?
-    fn should_break(&self) -> bool {
-        self.breakpoints.iter().any(|&pc| self.machine.get_pc() == pc)
+    /// Use `symbols` to name addresses in listings
+    /// and allow them in place of addresses.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
     }
 
-    pub fn run(&mut self) {
-        let mut last_cmd = Cmd::Step;
-        'main: loop {
-            match last_cmd {
-                Cmd::Continue => { // continue until breakpoint is hit
-                    if self.should_break() {
-                        last_cmd = Cmd::Step;
-                    }
+    /// The address of a location given
+    /// as a symbol name or in hex.
+    fn resolve(&self, location: &str) -> Option<u16> {
+        self.symbols.addr(location).or_else(|| {
+            let digits = if location.starts_with("0x") { &location[2..] } else { location };
+            u16::from_str_radix(digits, 16).ok()
+        })
+    }
+
+    /// The value of one side of a condition, if
+    /// the register or location exists.
+    fn operand(&self, operand: &Operand) -> Option<u16> {
+        match *operand {
+            Operand::Register(ref name) => self.get_register(name),
+            Operand::Memory(ref location) => self.resolve(location).map(|addr| self.machine.read(addr) as u16),
+            Operand::Value(value) => Some(value),
+        }
+    }
+
+    fn holds(&self, condition: &Condition) -> bool {
+        match (self.operand(&condition.lhs), self.operand(&condition.rhs)) {
+            (Some(lhs), Some(rhs)) => condition.compare.test(lhs, rhs),
+            _ => false,
+        }
+    }
+
+    /// Add a breakpoint, returning its number.
+    fn add_breakpoint(&mut self, kind: Kind, condition: Option<Condition>) -> Result<usize, String> {
+        if let Some(ref condition) = condition {
+            for operand in [&condition.lhs, &condition.rhs].iter() {
+                if self.operand(operand).is_none() {
+                    return Err(format!("Unknown location in {}", operand));
+                }
+            }
+        }
+        let number = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.push(Breakpoint::new(number, kind, condition));
+        self.update_watches();
+        Ok(number)
+    }
+
+    fn set_enabled(&mut self, number: usize, enabled: bool) {
+        match self.breakpoints.iter_mut().find(|breakpoint| breakpoint.number == number) {
+            Some(breakpoint) => breakpoint.enabled = enabled,
+            None => println!("No breakpoint {}", number),
+        }
+        self.update_watches();
+    }
+
+    /// Have the machine watch the addresses
+    /// of the enabled watchpoints.
+    fn update_watches(&mut self) {
+        let addrs: Vec<u16> = self.breakpoints.iter()
+            .filter(|breakpoint| breakpoint.enabled)
+            .filter_map(|breakpoint| match breakpoint.kind {
+                Kind::Watch { addr, .. } => Some(addr),
+                Kind::Break(_) => None,
+            })
+            .collect();
+        self.machine.set_watches(&addrs);
+    }
+
+    /// Is there an enabled breakpoint at `addr`?
+    fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.iter().any(|breakpoint| breakpoint.enabled && breakpoint.kind == Kind::Break(addr))
+    }
+
+    /// The breakpoint the last step stopped at, if
+    /// any, with its hit counted.
+    fn hit(&mut self) -> Option<&Breakpoint> {
+        let accesses = self.machine.take_accesses();
+        self.hit_by(&accesses)
+    }
+
+    /// The breakpoint at PC, or watching one of
+    /// `accesses`, if any, with its hit counted.
+    fn hit_by(&mut self, accesses: &[(u16, Access)]) -> Option<&Breakpoint> {
+        let pc = self.machine.get_pc();
+        let index = self.breakpoints.iter().position(|breakpoint| {
+            breakpoint.triggered(pc, accesses) &&
+                breakpoint.condition.as_ref().map_or(true, |condition| self.holds(condition))
+        });
+        index.map(move |index| {
+            let breakpoint = &mut self.breakpoints[index];
+            breakpoint.hits += 1;
+            &*breakpoint
+        })
+    }
+
+    /// Describe where a breakpoint stopped.
+    fn describe_hit(&self, breakpoint: &Breakpoint) -> String {
+        match breakpoint.kind {
+            Kind::Break(addr) => format!("Breakpoint {}, {}", breakpoint.number, self.name(addr)),
+            Kind::Watch { addr, .. } => format!("Watchpoint {}, {} = {:02x}",
+                                                breakpoint.number, self.name(addr), self.machine.read(addr)),
+        }
+    }
+
+    /// Print the instruction about to be executed.
+    fn print_instruction(&self) {
+        let pc = self.machine.get_pc();
+        for line in disassemble_with(|addr| self.machine.read(addr), pc, 1) {
+            if let Some(name) = self.symbols.name(line.addr) {
+                println!("{}:", name);
+            }
+            println!("{:04x} {}", line.addr, self.line_text(&line));
+        }
+    }
+
+    /// A line of disassembly with symbols in place of addresses.
+    fn line_text(&self, line: &Line) -> String {
+        line.to_string_with(|addr| self.symbols.name(addr).map(String::from))
+    }
+
+    /// Where to start disassembling to show up to `before`
+    /// instructions ahead of `addr`. Instructions can't be
+    /// decoded backwards so this starts as far back as
+    /// it can and still land on `addr`.
+    fn list_start(&self, addr: u16, before: usize) -> u16 {
+        // Instructions are at most 3 bytes long
+        for back in (1..before * 3 + 1).rev() {
+            let start = addr.wrapping_sub(back as u16);
+            let mut offset = 0;
+            let mut count = 0;
+            while offset < back {
+                offset += opcode_len(self.machine.read(start.wrapping_add(offset as u16)));
+                count += 1;
+            }
+            if offset == back && count <= before {
+                return start;
+            }
+        }
+        addr
+    }
+
+    /// Disassemble `count` instructions around `addr`, marking
+    /// the one at PC with `=>` and breakpoints with `*`.
+    fn list(&self, addr: u16, count: usize) -> Vec<String> {
+        let pc = self.machine.get_pc();
+        let start = self.list_start(addr, LIST_BEFORE);
+        let mut text = Vec::new();
+        for line in disassemble_with(|addr| self.machine.read(addr), start, count) {
+            if let Some(name) = self.symbols.name(line.addr) {
+                text.push(format!("{}:", name));
+            }
+            text.push(format!("{}{}{:04x} {}",
+                if line.addr == pc { "=>" } else { "  " },
+                if self.is_breakpoint(line.addr) { "*" } else { " " },
+                line.addr,
+                self.line_text(&line)));
+        }
+        text
+    }
+
+    /// Step the machine, counting its cycles and keeping
+    /// what changed to undo it. Memory accesses made by the
+    /// debugger are dropped first so they don't trigger
+    /// watchpoints or get undone.
+    fn step(&mut self) {
+        self.machine.take_accesses();
+        self.machine.take_journal();
+        let snapshot = self.machine.snapshot();
+        let cycles = self.machine.step();
+        self.cycles += cycles as u64;
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(Delta {
+            snapshot: snapshot,
+            writes: self.machine.take_journal(),
+            cycles: cycles,
+        });
+    }
+
+    /// Undo the last step, returning the addresses
+    /// it wrote, or None when there's no history left.
+    fn reverse_step(&mut self) -> Option<Vec<u16>> {
+        let delta = match self.history.pop_back() {
+            Some(delta) => delta,
+            None => return None,
+        };
+        for &(addr, value) in delta.writes.iter().rev() {
+            self.machine.write(addr, value);
+        }
+        self.machine.restore(&delta.snapshot);
+        self.cycles -= delta.cycles as u64;
+        Some(delta.writes.iter().map(|&(addr, _)| addr).collect())
+    }
+
+    /// Step backwards until `done` says to stop, a breakpoint
+    /// at PC or watching a write that was undone is hit, or
+    /// the history runs out, then print where it stopped.
+    fn reverse_until<F: FnMut(&Self) -> bool>(&mut self, mut done: F) {
+        loop {
+            let writes = match self.reverse_step() {
+                Some(writes) => writes,
+                None => {
+                    println!("No more history");
+                    break;
                 },
-                _ => { // prompt user for input
-                    let mut writer = BufWriter::new(io::stdout());
-                    writer.write(b">>> ").expect("Could not write to stdout.");
-                    writer.flush();                    
-
-                    let mut buf = String::new();
-                    let mut reader = BufReader::new(io::stdin());
-                    reader.read_line(&mut buf).expect("Could not read from stdin");
-
-                    let cmd = parse_cmd(buf.trim().as_bytes());
-                    // let cmd = parse_break(buf.trim().as_bytes());
-                    match cmd {
-                        nom::IResult::Done(input, output) => {
-                            match output {
-                                // TODO: allow printing in hexadecimal format
-                                Cmd::Break(addr) => self.breakpoints.push(addr), // break when PC becomes this address
-                                Cmd::Continue => {}, // continue until next breakpoint is hit
-                                Cmd::Exit => { break 'main },
-                                Cmd::List => {}, // TODO: implement this
-                                Cmd::Step => { self.machine.step(); }, // step into next instruction
-                                Cmd::Print(Printable::Addr(addr)) => println!("{}", self.machine.read(addr)),
-                                Cmd::Print(Printable::Register(machine::Reg::A)) => println!("{}", self.machine.get_a()),
-                                Cmd::Print(Printable::Register(machine::Reg::B)) => println!("{}", self.machine.get_b()),
-                                Cmd::Print(Printable::Register(machine::Reg::C)) => println!("{}", self.machine.get_c()),
-                                Cmd::Print(Printable::Register(machine::Reg::D)) => println!("{}", self.machine.get_d()),
-                                Cmd::Print(Printable::Register(machine::Reg::E)) => println!("{}", self.machine.get_e()),
-                                Cmd::Print(Printable::Register(machine::Reg::H)) => println!("{}", self.machine.get_h()),
-                                Cmd::Print(Printable::Register(machine::Reg::L)) => println!("{}", self.machine.get_l()),
-                                Cmd::Print(Printable::Breakpoints) => println!("{:?}", self.breakpoints),
-                            }
-                            last_cmd = output;
-                        },
-                        _ => println!("Unrecognised command"),
+            };
+            let accesses: Vec<(u16, Access)> = writes.into_iter().map(|addr| (addr, Access::Write)).collect();
+            if let Some(breakpoint) = self.hit_by(&accesses).cloned() {
+                println!("{}", self.describe_hit(&breakpoint));
+                break;
+            }
+            if done(self) {
+                break;
+            }
+        }
+        self.print_instruction();
+    }
+
+    /// An address by its symbol name if it has one.
+    fn name(&self, addr: u16) -> String {
+        match self.symbols.name(addr) {
+            Some(name) => format!("{:04x} {}", addr, name),
+            None => format!("{:04x}", addr),
+        }
+    }
+
+    /// All the registers and flags in hex.
+    fn regs(&self) -> Vec<String> {
+        let m = &self.machine;
+        let flags = m.get_flags();
+        let flag = |name: &'static str, bit: u8| format!("{}={}", name, (flags >> bit) & 1);
+        vec![
+            format!("A={:02x} B={:02x} C={:02x} D={:02x} E={:02x} H={:02x} L={:02x}",
+                    m.get_a(), m.get_b(), m.get_c(), m.get_d(), m.get_e(), m.get_h(), m.get_l()),
+            format!("BC={:02x}{:02x} DE={:02x}{:02x} HL={:02x}{:02x} SP={:04x} PC={}",
+                    m.get_b(), m.get_c(), m.get_d(), m.get_e(), m.get_h(), m.get_l(),
+                    m.get_sp(), self.name(m.get_pc())),
+            format!("{} {} {} {} {} F={:02x} interrupts {} cycles {}",
+                    flag("S", 7), flag("Z", 6), flag("AC", 4), flag("P", 2), flag("CY", 0), flags,
+                    if m.get_interrupt_enabled() { "on" } else { "off" }, self.cycles),
+        ]
+    }
+
+    /// The CALL or RST that would have pushed `ret`
+    /// as its return address, and where it is.
+    fn caller(&self, ret: u16) -> Option<(u16, Instruction)> {
+        let addr = ret.wrapping_sub(3);
+        let bytes: Vec<u8> = (0..3).map(|i| self.machine.read(addr.wrapping_add(i))).collect();
+        match decode(&bytes, 0).0 {
+            instr @ Instruction::Call(_) | instr @ Instruction::Ccc(_, _) => return Some((addr, instr)),
+            _ => {},
+        }
+        let addr = ret.wrapping_sub(1);
+        match decode(&[self.machine.read(addr), 0, 0], 0).0 {
+            instr @ Instruction::Rst(_) => Some((addr, instr)),
+            _ => None,
+        }
+    }
+
+    /// The top `count` words of the stack, noting
+    /// those that look like return addresses.
+    fn stack(&self, count: usize) -> Vec<String> {
+        let sp = self.machine.get_sp();
+        (0..count).map(|i| {
+            let addr = sp.wrapping_add(2 * i as u16);
+            let lo = self.machine.read(addr);
+            let hi = self.machine.read(addr.wrapping_add(1));
+            let word = (hi as u16) << 8 | lo as u16;
+            match self.caller(word) {
+                Some((at, instr)) => {
+                    let text = instr.to_string_with(|addr| self.symbols.name(addr).map(String::from));
+                    format!("{:04x}  {:04x}  return from {} at {}", addr, word, text, self.name(at))
+                },
+                None => format!("{:04x}  {:04x}", addr, word),
+            }
+        }).collect()
+    }
+
+    /// `count` bytes of memory from `addr` in hex
+    /// and as ASCII, with a dot for anything else.
+    fn dump(&self, addr: u16, count: usize) -> Vec<String> {
+        let bytes: Vec<u8> = (0..count).map(|i| self.machine.read(addr.wrapping_add(i as u16))).collect();
+        bytes.chunks(DUMP_WIDTH).enumerate().map(|(i, row)| {
+            let hex: Vec<String> = row.iter().map(|x| format!("{:02x}", x)).collect();
+            let ascii: String = row.iter()
+                .map(|&x| if (0x20..0x7f).contains(&x) { x as char } else { '.' })
+                .collect();
+            format!("{:04x}  {:<width$}  |{}|",
+                    addr.wrapping_add((i * DUMP_WIDTH) as u16), hex.join(" "), ascii,
+                    width = DUMP_WIDTH * 3 - 1)
+        }).collect()
+    }
+
+    /// A register, pair of them or the flags by name.
+    fn get_register(&self, name: &str) -> Option<u16> {
+        let m = &self.machine;
+        let pair = |hi: u8, lo: u8| (hi as u16) << 8 | lo as u16;
+        let value = match name {
+            "a" => m.get_a() as u16,
+            "b" => m.get_b() as u16,
+            "c" => m.get_c() as u16,
+            "d" => m.get_d() as u16,
+            "e" => m.get_e() as u16,
+            "h" => m.get_h() as u16,
+            "l" => m.get_l() as u16,
+            "f" => m.get_flags() as u16,
+            "bc" => pair(m.get_b(), m.get_c()),
+            "de" => pair(m.get_d(), m.get_e()),
+            "hl" => pair(m.get_h(), m.get_l()),
+            "sp" => m.get_sp(),
+            "pc" => m.get_pc(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Set a register, or pair of them, by name.
+    fn set_register(&mut self, name: &str, value: u16) -> Result<(), String> {
+        let byte = || if value > 0xff {
+            Err(format!("{:x} doesn't fit in {}", value, name))
+        } else {
+            Ok(value as u8)
+        };
+        let (hi, lo) = ((value >> 8) as u8, value as u8);
+        let m = &mut self.machine;
+        match name {
+            "a" => m.set_a(byte()?),
+            "b" => m.set_b(byte()?),
+            "c" => m.set_c(byte()?),
+            "d" => m.set_d(byte()?),
+            "e" => m.set_e(byte()?),
+            "h" => m.set_h(byte()?),
+            "l" => m.set_l(byte()?),
+            "f" => m.set_flags(byte()?),
+            "bc" => { m.set_b(hi); m.set_c(lo); },
+            "de" => { m.set_d(hi); m.set_e(lo); },
+            "hl" => { m.set_h(hi); m.set_l(lo); },
+            "sp" => m.set_sp(value),
+            "pc" => m.set_pc(value),
+            _ => return Err(format!("Unknown register {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Set registers by name, or write bytes to memory at a location.
+    /// Steps from before can't be undone afterwards, as the state
+    /// they'd go back to was never run.
+    fn set(&mut self, target: &str, values: &[u16]) -> Result<(), String> {
+        if REGISTERS.contains(&target) {
+            if values.len() != 1 {
+                return Err(format!("Expected one value for {}", target));
+            }
+            self.set_register(target, values[0])?;
+            self.history.clear();
+            return Ok(());
+        }
+        let addr = match self.resolve(target) {
+            Some(addr) => addr,
+            None => return Err(format!("Unknown location {}", target)),
+        };
+        if let Some(value) = values.iter().find(|&&value| value > 0xff) {
+            return Err(format!("{:x} isn't a byte", value));
+        }
+        for (i, &value) in values.iter().enumerate() {
+            self.machine.write(addr.wrapping_add(i as u16), value as u8);
+        }
+        self.history.clear();
+        Ok(())
+    }
+
+    /// Every address in memory where `bytes` are found.
+    fn find(&self, bytes: &[u8]) -> Vec<u16> {
+        (0..0x10000).map(|addr| addr as u16).filter(|&addr| {
+            bytes.iter().enumerate().all(|(i, &x)| self.machine.read(addr.wrapping_add(i as u16)) == x)
+        }).collect()
+    }
+
+    /// The instruction at PC.
+    fn instruction(&self) -> Instruction {
+        let pc = self.machine.get_pc();
+        let bytes: Vec<u8> = (0..3).map(|i| self.machine.read(pc.wrapping_add(i))).collect();
+        decode(&bytes, 0).0
+    }
+
+    /// Step until `done`, given the instruction just executed
+    /// and SP before it, says to stop or a breakpoint is hit,
+    /// then print where it stopped.
+    fn run_until<F: FnMut(&Self, &Instruction, u16) -> bool>(&mut self, mut done: F) {
+        loop {
+            let instr = self.instruction();
+            let sp = self.machine.get_sp();
+            self.step();
+            if self.machine.stopped() {
+                println!("Machine stopped");
+                return;
+            }
+            if let Some(breakpoint) = self.hit().cloned() {
+                println!("{}", self.describe_hit(&breakpoint));
+                break;
+            }
+            if done(self, &instr, sp) {
+                break;
+            }
+        }
+        self.print_instruction();
+    }
+
+    /// Step over a CALL or RST by running until it returns.
+    fn next(&mut self) {
+        let pc = self.machine.get_pc();
+        let instr = self.instruction();
+        match instr {
+            Instruction::Call(_) | Instruction::Ccc(_, _) | Instruction::Rst(_) => {
+                // Only the same call returning, not a recursive one,
+                // has the stack back where it is now
+                let ret = pc.wrapping_add(instr.size() as u16);
+                let sp = self.machine.get_sp();
+                self.run_until(|debugger, _, _| {
+                    debugger.machine.get_pc() == ret && debugger.machine.get_sp() >= sp
+                });
+            },
+            _ => self.run_until(|_, _, _| true),
+        }
+    }
+
+    /// Run until the current subroutine returns. Its return
+    /// address is at or above SP, so a return that pops
+    /// from there, rather than below, is the one.
+    fn finish(&mut self) {
+        let sp = self.machine.get_sp();
+        self.run_until(|debugger, instr, before| {
+            let returned = match *instr {
+                Instruction::Ret | Instruction::Rcc(_) => debugger.machine.get_sp() == before.wrapping_add(2),
+                _ => false,
+            };
+            returned && before >= sp
+        });
+    }
+
+    /// Prompt for a command, letting the machine idle
+    /// until one comes. Ends of input read as empty.
+    fn prompt(&mut self, lines: &Receiver<String>) -> String {
+        print!(">>> ");
+        io::stdout().flush().expect("Could not write to stdout.");
+        loop {
+            match lines.recv_timeout(Duration::from_millis(IDLE_MS)) {
+                Ok(line) => return line,
+                Err(RecvTimeoutError::Timeout) => self.machine.idle(),
+                Err(RecvTimeoutError::Disconnected) => return String::new(),
+            }
+        }
+    }
+
+    /// Carry out a command, returning
+    /// false when it's time to exit.
+    fn command(&mut self, cmd: Cmd) -> bool {
+        match cmd {
+            // TODO: allow printing in hexadecimal format
+            Cmd::Break(ref location, ref condition) => { // break when PC becomes this address
+                match self.resolve(location) {
+                    Some(addr) => match self.add_breakpoint(Kind::Break(addr), condition.clone()) {
+                        Ok(number) => println!("Breakpoint {} at {}", number, self.name(addr)),
+                        Err(e) => println!("{}", e),
+                    },
+                    None => println!("Unknown location {}", location),
+                }
+            },
+            Cmd::Watch(ref location, read, write, ref condition) => {
+                match self.resolve(location) {
+                    Some(addr) => {
+                        let kind = Kind::Watch { addr: addr, read: read, write: write };
+                        match self.add_breakpoint(kind, condition.clone()) {
+                            Ok(number) => println!("Watchpoint {} at {}", number, self.name(addr)),
+                            Err(e) => println!("{}", e),
+                        }
+                    },
+                    None => println!("Unknown location {}", location),
+                }
+            },
+            Cmd::Delete(Some(number)) => {
+                let count = self.breakpoints.len();
+                self.breakpoints.retain(|breakpoint| breakpoint.number != number);
+                if self.breakpoints.len() == count {
+                    println!("No breakpoint {}", number);
+                }
+                self.update_watches();
+            },
+            Cmd::Delete(None) => {
+                self.breakpoints.clear();
+                self.update_watches();
+            },
+            Cmd::Enable(number) => self.set_enabled(number, true),
+            Cmd::Disable(number) => self.set_enabled(number, false),
+            Cmd::Continue => self.run_until(|_, _, _| false), // continue until next breakpoint is hit
+            Cmd::Exit => return false,
+            Cmd::List(ref location, count) => {
+                let addr = match *location {
+                    Some(ref location) => self.resolve(location),
+                    None => Some(self.machine.get_pc()),
+                };
+                match addr {
+                    Some(addr) => {
+                        for line in self.list(addr, count.unwrap_or(LIST_COUNT)) {
+                            println!("{}", line);
+                        }
+                    },
+                    None => println!("Unknown location {}", location.as_ref().unwrap()),
+                }
+            },
+            Cmd::Step(count) => { // step into the next instructions
+                let mut count = count.unwrap_or(1);
+                self.run_until(|_, _, _| {
+                    count = count.saturating_sub(1);
+                    count == 0
+                });
+            },
+            Cmd::ReverseStep(count) => {
+                let mut count = count.unwrap_or(1);
+                self.reverse_until(|_| {
+                    count = count.saturating_sub(1);
+                    count == 0
+                });
+            },
+            Cmd::ReverseContinue => self.reverse_until(|_| false),
+            Cmd::Next => self.next(),
+            Cmd::Finish => self.finish(),
+            Cmd::Until(ref location) => {
+                match self.resolve(location) {
+                    Some(addr) => self.run_until(|debugger, _, _| debugger.machine.get_pc() == addr),
+                    None => println!("Unknown location {}", location),
+                }
+            },
+            Cmd::Print(Printable::Addr(ref location)) => {
+                match self.resolve(location) {
+                    Some(addr) => println!("{}", self.machine.read(addr)),
+                    None => println!("Unknown location {}", location),
+                }
+            },
+            Cmd::Print(Printable::Register(machine::Reg::A)) => println!("{}", self.machine.get_a()),
+            Cmd::Print(Printable::Register(machine::Reg::B)) => println!("{}", self.machine.get_b()),
+            Cmd::Print(Printable::Register(machine::Reg::C)) => println!("{}", self.machine.get_c()),
+            Cmd::Print(Printable::Register(machine::Reg::D)) => println!("{}", self.machine.get_d()),
+            Cmd::Print(Printable::Register(machine::Reg::E)) => println!("{}", self.machine.get_e()),
+            Cmd::Print(Printable::Register(machine::Reg::H)) => println!("{}", self.machine.get_h()),
+            Cmd::Print(Printable::Register(machine::Reg::L)) => println!("{}", self.machine.get_l()),
+            Cmd::Print(Printable::Breakpoints) => {
+                for breakpoint in &self.breakpoints {
+                    println!("{}", breakpoint);
+                }
+            },
+            Cmd::Regs => {
+                for line in self.regs() {
+                    println!("{}", line);
+                }
+            },
+            Cmd::Dump(ref location, count) => {
+                match self.resolve(location) {
+                    Some(addr) => {
+                        for line in self.dump(addr, count.unwrap_or(DUMP_COUNT)) {
+                            println!("{}", line);
+                        }
+                    },
+                    None => println!("Unknown location {}", location),
+                }
+            },
+            Cmd::Set(ref target, ref values) => {
+                if let Err(e) = self.set(target, values) {
+                    println!("{}", e);
+                }
+            },
+            Cmd::Find(ref values) => {
+                if values.iter().any(|&value| value > 0xff) {
+                    println!("Can only find bytes");
+                } else {
+                    let bytes: Vec<u8> = values.iter().map(|&value| value as u8).collect();
+                    for addr in self.find(&bytes) {
+                        println!("{}", self.name(addr));
                     }
                 }
+            },
+            Cmd::Stack(count) => {
+                for line in self.stack(count.unwrap_or(STACK_COUNT)) {
+                    println!("{}", line);
+                }
+            },
+        }
+        true
+    }
+
+    pub fn run(&mut self) {
+        let lines = stdin_lines();
+        self.print_instruction();
+        loop {
+            let buf = self.prompt(&lines);
+            match parse_cmd(buf.trim().as_bytes()) {
+                nom::IResult::Done(_, cmd) => if !self.command(cmd) { break },
+                _ => println!("Unrecognised command"),
             }
         }
     }
 }
+
+#[test]
+fn test_parse_break() {
+    assert_eq!(parse_cmd(b"b 1a2f"), nom::IResult::Done(&b""[..], Cmd::Break(String::from("1a2f"), None)));
+    assert_eq!(parse_cmd(b"b DrawSprite"), nom::IResult::Done(&b""[..], Cmd::Break(String::from("DrawSprite"), None)));
+    assert_eq!(parse_cmd(b"p a Score"),
+               nom::IResult::Done(&b""[..], Cmd::Print(Printable::Addr(String::from("Score")))));
+}
+
+#[test]
+fn test_parse_list() {
+    assert_eq!(parse_cmd(b"l"), nom::IResult::Done(&b""[..], Cmd::List(None, None)));
+    assert_eq!(parse_cmd(b"l 0100"), nom::IResult::Done(&b""[..], Cmd::List(Some(String::from("0100")), None)));
+    assert_eq!(parse_cmd(b"l DrawSprite 20"),
+               nom::IResult::Done(&b""[..], Cmd::List(Some(String::from("DrawSprite")), Some(20))));
+}
+
+#[test]
+fn test_list() {
+    use bare::Bare;
+
+    // MVI A,1; LXI H,2000h; MOV M,A; INX H; JMP 0003h
+    let program = [0x3e, 0x01, 0x21, 0x00, 0x20, 0x77, 0x23, 0xc3, 0x03, 0x00];
+    let mut debugger = Debugger::new(Bare::new(&program, 0x100));
+    for _ in 0..3 {
+        debugger.machine.step();
+    }
+    debugger.add_breakpoint(Kind::Break(0x107), None).unwrap();
+    let mut symbols = Symbols::new();
+    symbols.insert(0x107, "Loop");
+    debugger.set_symbols(symbols);
+    assert_eq!(debugger.list(0x106, 5), vec![
+        "   0100 MVI A,#01",
+        "   0102 LXI H,#$2000",
+        "   0105 MOV M,A",
+        "=> 0106 INX H",
+        "Loop:",
+        "  *0107 JMP $0003",
+    ]);
+}
+
+#[test]
+fn test_parse_words() {
+    assert_eq!(parse_cmd(b"s"), nom::IResult::Done(&b""[..], Cmd::Step(None)));
+    assert_eq!(parse_cmd(b"s 100"), nom::IResult::Done(&b""[..], Cmd::Step(Some(100))));
+    assert_eq!(parse_cmd(b"n"), nom::IResult::Done(&b""[..], Cmd::Next));
+    assert_eq!(parse_cmd(b"finish"), nom::IResult::Done(&b""[..], Cmd::Finish));
+    assert_eq!(parse_cmd(b"until 0105"), nom::IResult::Done(&b""[..], Cmd::Until(String::from("0105"))));
+    assert_eq!(parse_cmd(b"stack"), nom::IResult::Done(&b""[..], Cmd::Stack(None)));
+    assert_eq!(parse_cmd(b"stack 4"), nom::IResult::Done(&b""[..], Cmd::Stack(Some(4))));
+    assert_eq!(parse_cmd(b"regs"), nom::IResult::Done(&b""[..], Cmd::Regs));
+    assert!(!parse_cmd(b"sx").is_done());
+}
+
+#[test]
+fn test_regs_and_stack() {
+    use bare::Bare;
+
+    // LXI SP,2400h; MVI A,80h; ORA A; CALL 0010h; RST 3
+    let program = [0x31, 0x00, 0x24, 0x3e, 0x80, 0xb7, 0xcd, 0x10, 0x00];
+    let mut debugger = Debugger::new(Bare::new(&program, 0));
+    debugger.machine.write(0x10, 0xdf);
+    for _ in 0..5 {
+        debugger.step();
+    }
+    let mut symbols = Symbols::new();
+    symbols.insert(0x10, "Sub");
+    debugger.set_symbols(symbols);
+    assert_eq!(debugger.regs(), vec![
+        "A=80 B=00 C=00 D=00 E=00 H=00 L=00",
+        "BC=0000 DE=0000 HL=0000 SP=23fc PC=0018",
+        "S=1 Z=0 AC=0 P=0 CY=0 F=82 interrupts off cycles 49",
+    ]);
+    assert_eq!(debugger.stack(3), vec![
+        "23fc  0011  return from RST 3 at 0010 Sub",
+        "23fe  0009  return from CALL Sub at 0006",
+        "2400  0000",
+    ]);
+}
+
+#[test]
+fn test_parse_memory() {
+    assert_eq!(parse_cmd(b"x 2000"), nom::IResult::Done(&b""[..], Cmd::Dump(String::from("2000"), None)));
+    assert_eq!(parse_cmd(b"x Score 2"), nom::IResult::Done(&b""[..], Cmd::Dump(String::from("Score"), Some(2))));
+    assert_eq!(parse_cmd(b"set 20f8 10 0"),
+               nom::IResult::Done(&b""[..], Cmd::Set(String::from("20f8"), vec![0x10, 0])));
+    assert_eq!(parse_cmd(b"set hl 2400"), nom::IResult::Done(&b""[..], Cmd::Set(String::from("hl"), vec![0x2400])));
+    assert_eq!(parse_cmd(b"find cd 39 14"), nom::IResult::Done(&b""[..], Cmd::Find(vec![0xcd, 0x39, 0x14])));
+    assert!(!parse_cmd(b"set 20f8").is_done());
+}
+
+#[test]
+fn test_memory_commands() {
+    use bare::Bare;
+
+    let mut debugger = Debugger::new(Bare::new(b"Hello, world!\n", 0x2000));
+    assert_eq!(debugger.dump(0x2000, 20), vec![
+        "2000  48 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0a 00 00  |Hello, world!...|",
+        "2010  00 00 00 00                                      |....|",
+    ]);
+    assert_eq!(debugger.find(b"world"), vec![0x2007]);
+
+    debugger.set("2007", &[0x57]).unwrap();
+    assert_eq!(debugger.machine.read(0x2007), b'W');
+    assert!(debugger.set("2007", &[0x100]).is_err());
+
+    debugger.set("a", &[0x12]).unwrap();
+    debugger.set("hl", &[0x2400]).unwrap();
+    debugger.set("f", &[0xff]).unwrap();
+    debugger.set("pc", &[0x0100]).unwrap();
+    assert_eq!(debugger.machine.get_a(), 0x12);
+    assert_eq!((debugger.machine.get_h(), debugger.machine.get_l()), (0x24, 0x00));
+    assert_eq!(debugger.machine.get_flags(), 0xd7);
+    assert_eq!(debugger.machine.get_pc(), 0x0100);
+    assert!(debugger.set("b", &[0x100]).is_err());
+}
+
+#[test]
+fn test_parse_breakpoints() {
+    let condition = Condition {
+        lhs: Operand::Register(String::from("a")),
+        compare: Compare::Eq,
+        rhs: Operand::Value(0x20),
+    };
+    assert_eq!(parse_cmd(b"b 1439 if a==0x20"),
+               nom::IResult::Done(&b""[..], Cmd::Break(String::from("1439"), Some(condition))));
+    let condition = Condition {
+        lhs: Operand::Memory(String::from("Lives")),
+        compare: Compare::Lt,
+        rhs: Operand::Value(3),
+    };
+    assert_eq!(parse_cmd(b"watch 20f8 rw if [Lives] < 3"),
+               nom::IResult::Done(&b""[..], Cmd::Watch(String::from("20f8"), true, true, Some(condition))));
+    assert_eq!(parse_cmd(b"watch 20f8"),
+               nom::IResult::Done(&b""[..], Cmd::Watch(String::from("20f8"), false, true, None)));
+    assert_eq!(parse_cmd(b"watch 20f8 r"),
+               nom::IResult::Done(&b""[..], Cmd::Watch(String::from("20f8"), true, false, None)));
+    assert_eq!(parse_cmd(b"delete"), nom::IResult::Done(&b""[..], Cmd::Delete(None)));
+    assert_eq!(parse_cmd(b"delete 2"), nom::IResult::Done(&b""[..], Cmd::Delete(Some(2))));
+    assert_eq!(parse_cmd(b"disable 1"), nom::IResult::Done(&b""[..], Cmd::Disable(1)));
+    assert_eq!(parse_cmd(b"enable 1"), nom::IResult::Done(&b""[..], Cmd::Enable(1)));
+    assert!(!parse_cmd(b"b 1439 if a=1").is_done());
+}
+
+#[test]
+fn test_breakpoints() {
+    use bare::Bare;
+
+    // Loop: INR A; STA 2000h; JMP Loop
+    let program = [0x3c, 0x32, 0x00, 0x20, 0xc3, 0x00, 0x00];
+    let mut debugger = Debugger::new(Bare::new(&program, 0));
+    let run = |debugger: &mut Debugger<Bare>| -> usize {
+        loop {
+            debugger.step();
+            if let Some(breakpoint) = debugger.hit() {
+                return breakpoint.number;
+            }
+        }
+    };
+
+    let condition = Condition {
+        lhs: Operand::Register(String::from("a")),
+        compare: Compare::Eq,
+        rhs: Operand::Value(3),
+    };
+    assert_eq!(debugger.add_breakpoint(Kind::Break(0x0004), Some(condition)), Ok(1));
+    assert_eq!(run(&mut debugger), 1);
+    assert_eq!(debugger.machine.get_a(), 3);
+    assert_eq!(debugger.breakpoints[0].hits, 1);
+    debugger.breakpoints[0].enabled = false;
+
+    let condition = Condition {
+        lhs: Operand::Memory(String::from("2000")),
+        compare: Compare::Ge,
+        rhs: Operand::Value(5),
+    };
+    let watch = Kind::Watch { addr: 0x2000, read: false, write: true };
+    assert_eq!(debugger.add_breakpoint(watch, Some(condition)), Ok(2));
+    assert_eq!(run(&mut debugger), 2);
+    assert_eq!(debugger.machine.read(0x2000), 5);
+    assert_eq!(debugger.machine.get_pc(), 0x0004);
+
+    let condition = Condition {
+        lhs: Operand::Memory(String::from("Nowhere")),
+        compare: Compare::Eq,
+        rhs: Operand::Value(0),
+    };
+    assert!(debugger.add_breakpoint(Kind::Break(0), Some(condition)).is_err());
+}
+
+#[test]
+fn test_step_over_and_out() {
+    use bare::Bare;
+
+    // LXI SP,2400h; CALL Sub; NOP; HLT
+    // Sub: MVI B,2; Loop: DCR B; JNZ Loop; RET
+    let program = [0x31, 0x00, 0x24, 0xcd, 0x08, 0x00, 0x00, 0x76,
+                   0x06, 0x02, 0x05, 0xc2, 0x0a, 0x00, 0xc9];
+    let mut debugger = Debugger::new(Bare::new(&program, 0));
+    debugger.command(Cmd::Next);
+    assert_eq!(debugger.machine.get_pc(), 0x0003);
+    debugger.command(Cmd::Next);
+    assert_eq!(debugger.machine.get_pc(), 0x0006);
+    assert_eq!(debugger.machine.get_b(), 0);
+
+    debugger.set("pc", &[0x0003]).unwrap();
+    debugger.set("sp", &[0x2400]).unwrap();
+    debugger.command(Cmd::Step(Some(2)));
+    assert_eq!(debugger.machine.get_pc(), 0x000a);
+    debugger.command(Cmd::Finish);
+    assert_eq!(debugger.machine.get_pc(), 0x0006);
+    assert_eq!(debugger.machine.get_sp(), 0x2400);
+
+    debugger.set("pc", &[0x0003]).unwrap();
+    debugger.set("sp", &[0x2400]).unwrap();
+    debugger.command(Cmd::Until(String::from("0e")));
+    assert_eq!(debugger.machine.get_pc(), 0x000e);
+
+    // Breakpoints stop them early
+    debugger.set("pc", &[0x0003]).unwrap();
+    debugger.add_breakpoint(Kind::Break(0x000b), None).unwrap();
+    debugger.command(Cmd::Next);
+    assert_eq!(debugger.machine.get_pc(), 0x000b);
+}
+
+#[test]
+fn test_reverse() {
+    use bare::Bare;
+
+    // Loop: INR A; STA 2000h; PUSH PSW; JMP Loop
+    let program = [0x3c, 0x32, 0x00, 0x20, 0xf5, 0xc3, 0x00, 0x00];
+    let mut debugger = Debugger::new(Bare::new(&program, 0));
+    debugger.set("sp", &[0x2400]).unwrap();
+    let start = debugger.machine.registers();
+    debugger.command(Cmd::Step(Some(40)));
+    assert_eq!(debugger.machine.get_a(), 10);
+    assert_eq!(debugger.machine.read(0x2000), 10);
+
+    debugger.command(Cmd::ReverseStep(Some(3)));
+    assert_eq!(debugger.machine.get_pc(), 0x0001);
+    assert_eq!(debugger.machine.read(0x2000), 9);
+    assert_eq!(debugger.machine.get_sp(), 0x2400 - 2 * 9);
+
+    let watch = Kind::Watch { addr: 0x2000, read: false, write: true };
+    debugger.add_breakpoint(watch, None).unwrap();
+    debugger.command(Cmd::ReverseContinue);
+    assert_eq!(debugger.machine.get_pc(), 0x0001);
+    assert_eq!(debugger.machine.read(0x2000), 8);
+
+    debugger.command(Cmd::Delete(None));
+    debugger.command(Cmd::ReverseContinue);
+    assert_eq!(debugger.machine.registers(), start);
+    assert_eq!(debugger.machine.read(0x2000), 0);
+    assert_eq!(debugger.machine.read(0x23ff), 0);
+    assert_eq!(debugger.cycles, 0);
+
+    // Nothing from before an edit can be undone
+    debugger.command(Cmd::Step(Some(4)));
+    debugger.set("2000", &[0x55]).unwrap();
+    debugger.command(Cmd::ReverseStep(Some(1)));
+    assert_eq!(debugger.machine.get_pc(), 0x0000);
+    assert_eq!(debugger.machine.read(0x2000), 0x55);
+}
+
+#[test]
+fn test_reverse_space_invaders() {
+    use invaders::{Headless, SpaceInvaders};
+
+    // EI; loop: IN 3; OUT 4; INR A; STA 2000h; JMP loop, with
+    // RST 1 and RST 2 counting interrupts at 2001h: PUSH PSW;
+    // LDA 2001h; INR A; STA 2001h; POP PSW; EI; RET
+    let mut rom = vec![0; 0x40];
+    rom[0x00..0x0c].copy_from_slice(&[0xfb, 0xdb, 0x03, 0xd3, 0x04, 0x3c, 0x32, 0x00, 0x20,
+                                      0xc3, 0x01, 0x00]);
+    rom[0x20..0x2b].copy_from_slice(&[0xf5, 0x3a, 0x01, 0x20, 0x3c, 0x32, 0x01, 0x20,
+                                      0xf1, 0xfb, 0xc9]);
+    // RST 1 and RST 2 jump to it
+    rom[0x08..0x0b].copy_from_slice(&[0xc3, 0x20, 0x00]);
+    rom[0x10..0x13].copy_from_slice(&[0xc3, 0x20, 0x00]);
+    let mut debugger = Debugger::new(SpaceInvaders::new(&rom, Headless));
+    debugger.set("sp", &[0x2400]).unwrap();
+
+    // Across a frame, then back and forward again
+    let steps = 12_000;
+    debugger.command(Cmd::Step(Some(steps)));
+    let snapshot = debugger.machine.snapshot();
+    let ram: Vec<u8> = (0x2000..0x2400).map(|addr| debugger.machine.read(addr)).collect();
+    assert!(debugger.machine.core.frames() >= 1);
+    assert!(debugger.machine.read(0x2001) >= 2);
+
+    debugger.command(Cmd::ReverseStep(Some(steps)));
+    assert_eq!(debugger.machine.core.frames(), 0);
+    assert_eq!(debugger.machine.read(0x2001), 0);
+    debugger.command(Cmd::Step(Some(steps)));
+    assert_eq!(debugger.machine.snapshot(), snapshot);
+    assert!((0x2000..0x2400).all(|addr| debugger.machine.read(addr) == ram[addr as usize - 0x2000]));
+}
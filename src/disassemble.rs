@@ -1,570 +1,1067 @@
+use std::collections::BTreeSet;
+use std::fmt;
 
+use cpu::make_u16;
 use machine::{Reg, RegPair};
+use memory::Memory;
+use symbols::Symbols;
 
-// /// A source/target location for an
-// /// instruction.
-// #[derive(Clone, Debug, PartialEq, Eq)]
-// enum Loc {
-//     Mem,
-//     Reg(Reg),
-// }
-
-// #[derive(Clone, Debug, PartialEq, Eq)]
-// pub enum OpCode {
-//     NOP,
-//     LXI(RegPair, u8, u8),
-//     STAX(RegPair),
-//     INX(RegPair),
-//     INR(Reg),
-//     DCR(Reg),
-//     MVI(Reg, u8),
-//     RLC,
-//     RRC,
-//     RAL,
-//     RAR,
-//     RIM,
-//     DAD(RegPair),
-//     LDAX(RegPair),
-//     DCX(RegPair),
-//     SHLD,
-//     LHLD,
-//     DAA,
-//     CMA,
-//     CMC,
-//     STA,
-//     STC,
-//     MOV(Loc, Loc),
-//     ADD(Loc),
-//     SUB(Loc),
-//     ANA(Loc),
-//     XRA(Loc),
-//     ORA(Loc),
-//     CMP(Loc),
-//     RNZ,
-//     POP(RegPair),
-// }
-
-// impl OpCode {
-//     /// The number of bytes this instruction uses.
-//     pub fn bytes(&self) -> usize {
-//         match *self {
-//             NOP => 1,
-//             LXI(_, _, _) => 3,
-//             STAX(_) => 1,
-//             INX(_) => 1,
-//             INR(_) => 1,
-//             DCR(_) => 1,
-//             MVI(_, _) => 2,
-//             RLC => 1,
-//             RRC => 1,
-//             RAL => 1,
-//             RAR => 1,
-//             RIM => 1
-//             DAD(_) => 1,
-//             LDAX(_) => 1,
-//             DCX(_) => 1,
-//             SHLD => 1,
-//             LHLD => 1,
-//             DAA => 1,
-//             CMA => 1,
-//             CMC => 1,
-//             STA => 1,
-//             STC => 1,
-//             MOV(_, _) => 1,
-//             ADD(_) => 1,
-//             SUB(_) => 1,
-//             ANA(_) => 1,
-//             XRA(_) => 1,
-//             ORA(_) => 1,
-//             CMP(_) => 1,
-//             RNZ => 1,
-//             POP(_) => 1,
-//         }
-//     }
-// }
-
-// fn disassemble_op(bytes: &[u8], pc: usize) -> OpCode {
-//     let op = bytes[pc];
-//     match op {
-//         0x00 => OpCode::NOP,
-//         0x01 => {
-//             let lo = bytes[pc + 1];
-//             let hi = bytes[pc + 2];
-//             OpCode::LXI(RegPair::BC, lo, hi)
-//         },
-//         0x02 => {
-//             OpCode::STAX(RegPair::BC)
-//         },
-//         0x03 => {
-//             OpCode::INX(RegPair::BC)
-//         },
-//         0x04 => {
-//             OpCode::INR(RegPair::BC)
-//         },
-//         0x05 => {
-//             OpCode::DCR(RegPair::BC)
-//         },
-//         0x06 => {
-//             let x = bytes[pc + 1];
-//             OpCode::MVI(Reg::B, x)
-//         },
-//     }
-// }
+/// A source/target location for an
+/// instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Loc {
+    /// The byte in memory pointed to by HL.
+    Mem,
+    Reg(Reg),
+}
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Loc::Mem => write!(f, "M"),
+            Loc::Reg(ref reg) => write!(f, "{}", reg),
+        }
+    }
+}
+
+/// Flag condition tested by conditional
+/// jumps, calls and returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    NotZero,
+    Zero,
+    NoCarry,
+    Carry,
+    ParityOdd,
+    ParityEven,
+    Plus,
+    Minus,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Condition::NotZero => "NZ",
+            Condition::Zero => "Z",
+            Condition::NoCarry => "NC",
+            Condition::Carry => "C",
+            Condition::ParityOdd => "PO",
+            Condition::ParityEven => "PE",
+            Condition::Plus => "P",
+            Condition::Minus => "M",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A decoded 8080 instruction with its operands.
+///
+/// The undocumented opcodes decode to the documented
+/// instruction they behave as, so 0x08 is a `Nop`
+/// and 0xcb a `Jmp`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    Lxi(RegPair, u16),
+    Stax(RegPair),
+    Inx(RegPair),
+    Inr(Loc),
+    Dcr(Loc),
+    Mvi(Loc, u8),
+    Rlc,
+    Rrc,
+    Ral,
+    Rar,
+    Dad(RegPair),
+    Ldax(RegPair),
+    Dcx(RegPair),
+    Shld(u16),
+    Lhld(u16),
+    Daa,
+    Cma,
+    Sta(u16),
+    Lda(u16),
+    Stc,
+    Cmc,
+    Mov(Loc, Loc),
+    Hlt,
+    Add(Loc),
+    Adc(Loc),
+    Sub(Loc),
+    Sbb(Loc),
+    Ana(Loc),
+    Xra(Loc),
+    Ora(Loc),
+    Cmp(Loc),
+    Rcc(Condition),
+    Ret,
+    Pop(RegPair),
+    Push(RegPair),
+    Jcc(Condition, u16),
+    Jmp(u16),
+    Ccc(Condition, u16),
+    Call(u16),
+    Adi(u8),
+    Aci(u8),
+    Sui(u8),
+    Sbi(u8),
+    Ani(u8),
+    Xri(u8),
+    Ori(u8),
+    Cpi(u8),
+    Rst(u8),
+    Out(u8),
+    In(u8),
+    Xthl,
+    Pchl,
+    Xchg,
+    Sphl,
+    Di,
+    Ei,
+}
+
+/// Register or memory encoded in the low three bits.
+fn loc(bits: u8) -> Loc {
+    match bits & 0x07 {
+        0 => Loc::Reg(Reg::B),
+        1 => Loc::Reg(Reg::C),
+        2 => Loc::Reg(Reg::D),
+        3 => Loc::Reg(Reg::E),
+        4 => Loc::Reg(Reg::H),
+        5 => Loc::Reg(Reg::L),
+        6 => Loc::Mem,
+        _ => Loc::Reg(Reg::A),
+    }
+}
+
+/// Register pair encoded in bits 4 and 5,
+/// with 3 being SP or PSW for PUSH and POP.
+fn pair(op: u8, psw: bool) -> RegPair {
+    match (op >> 4) & 0x03 {
+        0 => RegPair::BC,
+        1 => RegPair::DE,
+        2 => RegPair::HL,
+        _ => if psw { RegPair::PSW } else { RegPair::SP },
+    }
+}
+
+/// Condition encoded in bits 3 to 5.
+fn condition(op: u8) -> Condition {
+    match (op >> 3) & 0x07 {
+        0 => Condition::NotZero,
+        1 => Condition::Zero,
+        2 => Condition::NoCarry,
+        3 => Condition::Carry,
+        4 => Condition::ParityOdd,
+        5 => Condition::ParityEven,
+        6 => Condition::Plus,
+        _ => Condition::Minus,
+    }
+}
+
+fn loc_bits(loc: Loc) -> u8 {
+    match loc {
+        Loc::Reg(Reg::B) => 0,
+        Loc::Reg(Reg::C) => 1,
+        Loc::Reg(Reg::D) => 2,
+        Loc::Reg(Reg::E) => 3,
+        Loc::Reg(Reg::H) => 4,
+        Loc::Reg(Reg::L) => 5,
+        Loc::Mem => 6,
+        Loc::Reg(Reg::A) => 7,
+    }
+}
+
+fn pair_bits(rp: RegPair) -> u8 {
+    match rp {
+        RegPair::BC => 0x00,
+        RegPair::DE => 0x10,
+        RegPair::HL => 0x20,
+        RegPair::SP | RegPair::PSW => 0x30,
+    }
+}
+
+fn condition_bits(cc: Condition) -> u8 {
+    match cc {
+        Condition::NotZero => 0,
+        Condition::Zero => 1,
+        Condition::NoCarry => 2,
+        Condition::Carry => 3,
+        Condition::ParityOdd => 4,
+        Condition::ParityEven => 5,
+        Condition::Plus => 6,
+        Condition::Minus => 7,
+    }
+}
+
+/// Decode the instruction starting at `bytes[pc]`,
+/// returning it with its length in bytes.
+///
+/// Panics if the operands run past the end of `bytes`,
+/// use `opcode_len` first to check they're all there.
+pub fn decode(bytes: &[u8], pc: usize) -> (Instruction, usize) {
+    use self::Instruction::*;
+
+    let op = bytes[pc];
+    let byte = || bytes[pc + 1];
+    let word = || make_u16(bytes[pc + 1], bytes[pc + 2]);
+    let instr = match op {
+        0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => Nop,
+        0x01 | 0x11 | 0x21 | 0x31 => Lxi(pair(op, false), word()),
+        0x02 | 0x12 => Stax(pair(op, false)),
+        0x22 => Shld(word()),
+        0x32 => Sta(word()),
+        0x03 | 0x13 | 0x23 | 0x33 => Inx(pair(op, false)),
+        0x07 => Rlc,
+        0x0f => Rrc,
+        0x17 => Ral,
+        0x1f => Rar,
+        0x27 => Daa,
+        0x2f => Cma,
+        0x37 => Stc,
+        0x3f => Cmc,
+        0x09 | 0x19 | 0x29 | 0x39 => Dad(pair(op, false)),
+        0x0a | 0x1a => Ldax(pair(op, false)),
+        0x2a => Lhld(word()),
+        0x3a => Lda(word()),
+        0x0b | 0x1b | 0x2b | 0x3b => Dcx(pair(op, false)),
+        x if x & 0xc7 == 0x04 => Inr(loc(op >> 3)),
+        x if x & 0xc7 == 0x05 => Dcr(loc(op >> 3)),
+        x if x & 0xc7 == 0x06 => Mvi(loc(op >> 3), byte()),
+        0x76 => Hlt,
+        x if x & 0xc0 == 0x40 => Mov(loc(op >> 3), loc(op)),
+        x if x & 0xc0 == 0x80 => {
+            let src = loc(op);
+            match (op >> 3) & 0x07 {
+                0 => Add(src),
+                1 => Adc(src),
+                2 => Sub(src),
+                3 => Sbb(src),
+                4 => Ana(src),
+                5 => Xra(src),
+                6 => Ora(src),
+                _ => Cmp(src),
+            }
+        },
+        0xc3 | 0xcb => Jmp(word()),
+        0xc9 | 0xd9 => Ret,
+        0xcd | 0xdd | 0xed | 0xfd => Call(word()),
+        0xd3 => Out(byte()),
+        0xdb => In(byte()),
+        0xe3 => Xthl,
+        0xe9 => Pchl,
+        0xeb => Xchg,
+        0xf3 => Di,
+        0xf9 => Sphl,
+        0xfb => Ei,
+        x if x & 0xcf == 0xc1 => Pop(pair(op, true)),
+        x if x & 0xcf == 0xc5 => Push(pair(op, true)),
+        x if x & 0xc7 == 0xc0 => Rcc(condition(op)),
+        x if x & 0xc7 == 0xc2 => Jcc(condition(op), word()),
+        x if x & 0xc7 == 0xc4 => Ccc(condition(op), word()),
+        x if x & 0xc7 == 0xc6 => {
+            let x = byte();
+            match (op >> 3) & 0x07 {
+                0 => Adi(x),
+                1 => Aci(x),
+                2 => Sui(x),
+                3 => Sbi(x),
+                4 => Ani(x),
+                5 => Xri(x),
+                6 => Ori(x),
+                _ => Cpi(x),
+            }
+        },
+        // Only the RSTs, 0xc7 & 0xc7 == 0xc7, are left
+        _ => Rst((op >> 3) & 0x07),
+    };
+    (instr, instr.size())
+}
+
+/// Length in bytes of the instruction
+/// starting with opcode `op`.
+pub fn opcode_len(op: u8) -> usize {
+    decode(&[op, 0, 0], 0).1
+}
+
+impl Instruction {
+    /// The number of bytes this instruction uses.
+    pub fn size(&self) -> usize {
+        use self::Instruction::*;
+
+        match *self {
+            Lxi(_, _) | Shld(_) | Lhld(_) | Sta(_) | Lda(_) |
+            Jcc(_, _) | Jmp(_) | Ccc(_, _) | Call(_) => 3,
+            Mvi(_, _) | Adi(_) | Aci(_) | Sui(_) | Sbi(_) |
+            Ani(_) | Xri(_) | Ori(_) | Cpi(_) | Out(_) | In(_) => 2,
+            _ => 1,
+        }
+    }
+
+    /// The number of cycles this instruction takes. For
+    /// conditional calls and returns this is when the
+    /// condition fails, see `cycles_taken`.
+    pub fn cycles(&self) -> u32 {
+        use self::Instruction::*;
+
+        match *self {
+            Nop | Rlc | Rrc | Ral | Rar | Daa | Cma | Stc | Cmc |
+            Xchg | Di | Ei => 4,
+            Inx(_) | Dcx(_) | Pchl | Sphl | Rcc(_) => 5,
+            Inr(Loc::Mem) | Dcr(Loc::Mem) | Mvi(Loc::Mem, _) => 10,
+            Inr(_) | Dcr(_) => 5,
+            Mvi(_, _) | Stax(_) | Ldax(_) | Hlt => 7,
+            Mov(Loc::Mem, _) | Mov(_, Loc::Mem) => 7,
+            Mov(_, _) => 5,
+            Add(Loc::Mem) | Adc(Loc::Mem) | Sub(Loc::Mem) | Sbb(Loc::Mem) |
+            Ana(Loc::Mem) | Xra(Loc::Mem) | Ora(Loc::Mem) | Cmp(Loc::Mem) => 7,
+            Add(_) | Adc(_) | Sub(_) | Sbb(_) |
+            Ana(_) | Xra(_) | Ora(_) | Cmp(_) => 4,
+            Adi(_) | Aci(_) | Sui(_) | Sbi(_) |
+            Ani(_) | Xri(_) | Ori(_) | Cpi(_) => 7,
+            Lxi(_, _) | Dad(_) | Ret | Pop(_) | Jcc(_, _) | Jmp(_) |
+            Out(_) | In(_) => 10,
+            Push(_) | Rst(_) | Ccc(_, _) => 11,
+            Sta(_) | Lda(_) => 13,
+            Shld(_) | Lhld(_) => 16,
+            Call(_) => 17,
+            Xthl => 18,
+        }
+    }
+
+    /// The number of cycles this instruction
+    /// takes when its condition is met.
+    pub fn cycles_taken(&self) -> u32 {
+        match *self {
+            Instruction::Rcc(_) => 11,
+            Instruction::Ccc(_, _) => 17,
+            _ => self.cycles(),
+        }
+    }
+
+    /// Where this instruction may transfer control to,
+    /// other than the instruction following it.
+    pub fn target(&self) -> Option<u16> {
+        match *self {
+            Instruction::Jmp(addr) | Instruction::Jcc(_, addr) |
+            Instruction::Call(addr) | Instruction::Ccc(_, addr) => Some(addr),
+            Instruction::Rst(n) => Some(n as u16 * 8),
+            _ => None,
+        }
+    }
+
+    /// The address or 16 bit value this instruction uses.
+    pub fn address(&self) -> Option<u16> {
+        use self::Instruction::*;
+
+        match *self {
+            Lxi(_, x) | Shld(x) | Lhld(x) | Sta(x) | Lda(x) |
+            Jcc(_, x) | Jmp(x) | Ccc(_, x) | Call(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Can execution carry on to the following instruction?
+    /// PCHL jumps somewhere only known at run time.
+    pub fn falls_through(&self) -> bool {
+        match *self {
+            Instruction::Jmp(_) | Instruction::Ret | Instruction::Pchl => false,
+            _ => true,
+        }
+    }
+
+    /// The mnemonic and operands of this instruction, writing
+    /// addresses with `addr`, 16 bit immediate values with
+    /// `word` and 8 bit ones with `byte`.
+    fn parts<A, W, B>(&self, addr: A, word: W, byte: B) -> (String, String)
+        where A: Fn(u16) -> String, W: Fn(u16) -> String, B: Fn(u8) -> String
+    {
+        use self::Instruction::*;
+
+        let (mnemonic, operands) = match *self {
+            Nop => ("NOP", String::new()),
+            Lxi(rp, x) => ("LXI", format!("{},{}", rp, word(x))),
+            Stax(rp) => ("STAX", format!("{}", rp)),
+            Inx(rp) => ("INX", format!("{}", rp)),
+            Inr(r) => ("INR", format!("{}", r)),
+            Dcr(r) => ("DCR", format!("{}", r)),
+            Mvi(r, x) => ("MVI", format!("{},{}", r, byte(x))),
+            Rlc => ("RLC", String::new()),
+            Rrc => ("RRC", String::new()),
+            Ral => ("RAL", String::new()),
+            Rar => ("RAR", String::new()),
+            Dad(rp) => ("DAD", format!("{}", rp)),
+            Ldax(rp) => ("LDAX", format!("{}", rp)),
+            Dcx(rp) => ("DCX", format!("{}", rp)),
+            Shld(x) => ("SHLD", addr(x)),
+            Lhld(x) => ("LHLD", addr(x)),
+            Daa => ("DAA", String::new()),
+            Cma => ("CMA", String::new()),
+            Sta(x) => ("STA", addr(x)),
+            Lda(x) => ("LDA", addr(x)),
+            Stc => ("STC", String::new()),
+            Cmc => ("CMC", String::new()),
+            Mov(dst, src) => ("MOV", format!("{},{}", dst, src)),
+            Hlt => ("HLT", String::new()),
+            Add(r) => ("ADD", format!("{}", r)),
+            Adc(r) => ("ADC", format!("{}", r)),
+            Sub(r) => ("SUB", format!("{}", r)),
+            Sbb(r) => ("SBB", format!("{}", r)),
+            Ana(r) => ("ANA", format!("{}", r)),
+            Xra(r) => ("XRA", format!("{}", r)),
+            Ora(r) => ("ORA", format!("{}", r)),
+            Cmp(r) => ("CMP", format!("{}", r)),
+            Rcc(cc) => return (format!("R{}", cc), String::new()),
+            Ret => ("RET", String::new()),
+            Pop(rp) => ("POP", format!("{}", rp)),
+            Push(rp) => ("PUSH", format!("{}", rp)),
+            Jcc(cc, x) => return (format!("J{}", cc), addr(x)),
+            Jmp(x) => ("JMP", addr(x)),
+            Ccc(cc, x) => return (format!("C{}", cc), addr(x)),
+            Call(x) => ("CALL", addr(x)),
+            Adi(x) => ("ADI", byte(x)),
+            Aci(x) => ("ACI", byte(x)),
+            Sui(x) => ("SUI", byte(x)),
+            Sbi(x) => ("SBI", byte(x)),
+            Ani(x) => ("ANI", byte(x)),
+            Xri(x) => ("XRI", byte(x)),
+            Ori(x) => ("ORI", byte(x)),
+            Cpi(x) => ("CPI", byte(x)),
+            Rst(n) => ("RST", format!("{}", n)),
+            Out(port) => ("OUT", byte(port)),
+            In(port) => ("IN", byte(port)),
+            Xthl => ("XTHL", String::new()),
+            Pchl => ("PCHL", String::new()),
+            Xchg => ("XCHG", String::new()),
+            Sphl => ("SPHL", String::new()),
+            Di => ("DI", String::new()),
+            Ei => ("EI", String::new()),
+        };
+        (String::from(mnemonic), operands)
+    }
+
+    /// Format the instruction with addresses replaced by
+    /// the name `name` gives them, if it gives one.
+    pub fn to_string_with<F: Fn(u16) -> Option<String>>(&self, name: F) -> String {
+        let (mnemonic, operands) = self.parts(
+            |x| name(x).unwrap_or(format!("${:04x}", x)),
+            |x| name(x).unwrap_or(format!("#${:04x}", x)),
+            |x| format!("#{:02x}", x));
+        if operands.is_empty() {
+            mnemonic
+        } else {
+            format!("{} {}", mnemonic, operands)
+        }
+    }
+
+    /// Format the instruction as assembly language source,
+    /// naming addresses as for `to_string_with`.
+    pub fn to_asm_with<F: Fn(u16) -> Option<String>>(&self, name: F) -> String {
+        let (mnemonic, operands) = self.parts(
+            |x| name(x).unwrap_or(asm_word(x)),
+            |x| name(x).unwrap_or(asm_word(x)),
+            asm_byte);
+        format!("{:<8}{}", mnemonic, operands).trim().to_string()
+    }
+
+    /// The machine code for this instruction. Undocumented
+    /// opcodes encode as the documented instruction.
+    pub fn encode(&self) -> Vec<u8> {
+        use self::Instruction::*;
+
+        let (op, operand) = match *self {
+            Nop => (0x00, None),
+            Lxi(rp, x) => (0x01 | pair_bits(rp), Some(x)),
+            Stax(rp) => (0x02 | pair_bits(rp), None),
+            Inx(rp) => (0x03 | pair_bits(rp), None),
+            Inr(r) => (0x04 | loc_bits(r) << 3, None),
+            Dcr(r) => (0x05 | loc_bits(r) << 3, None),
+            Mvi(r, x) => (0x06 | loc_bits(r) << 3, Some(x as u16)),
+            Rlc => (0x07, None),
+            Rrc => (0x0f, None),
+            Ral => (0x17, None),
+            Rar => (0x1f, None),
+            Dad(rp) => (0x09 | pair_bits(rp), None),
+            Ldax(rp) => (0x0a | pair_bits(rp), None),
+            Dcx(rp) => (0x0b | pair_bits(rp), None),
+            Shld(x) => (0x22, Some(x)),
+            Lhld(x) => (0x2a, Some(x)),
+            Daa => (0x27, None),
+            Cma => (0x2f, None),
+            Sta(x) => (0x32, Some(x)),
+            Lda(x) => (0x3a, Some(x)),
+            Stc => (0x37, None),
+            Cmc => (0x3f, None),
+            Mov(dst, src) => (0x40 | loc_bits(dst) << 3 | loc_bits(src), None),
+            Hlt => (0x76, None),
+            Add(r) => (0x80 | loc_bits(r), None),
+            Adc(r) => (0x88 | loc_bits(r), None),
+            Sub(r) => (0x90 | loc_bits(r), None),
+            Sbb(r) => (0x98 | loc_bits(r), None),
+            Ana(r) => (0xa0 | loc_bits(r), None),
+            Xra(r) => (0xa8 | loc_bits(r), None),
+            Ora(r) => (0xb0 | loc_bits(r), None),
+            Cmp(r) => (0xb8 | loc_bits(r), None),
+            Rcc(cc) => (0xc0 | condition_bits(cc) << 3, None),
+            Ret => (0xc9, None),
+            Pop(rp) => (0xc1 | pair_bits(rp), None),
+            Push(rp) => (0xc5 | pair_bits(rp), None),
+            Jcc(cc, x) => (0xc2 | condition_bits(cc) << 3, Some(x)),
+            Jmp(x) => (0xc3, Some(x)),
+            Ccc(cc, x) => (0xc4 | condition_bits(cc) << 3, Some(x)),
+            Call(x) => (0xcd, Some(x)),
+            Adi(x) => (0xc6, Some(x as u16)),
+            Aci(x) => (0xce, Some(x as u16)),
+            Sui(x) => (0xd6, Some(x as u16)),
+            Sbi(x) => (0xde, Some(x as u16)),
+            Ani(x) => (0xe6, Some(x as u16)),
+            Xri(x) => (0xee, Some(x as u16)),
+            Ori(x) => (0xf6, Some(x as u16)),
+            Cpi(x) => (0xfe, Some(x as u16)),
+            Rst(n) => (0xc7 | (n & 0x07) << 3, None),
+            Out(port) => (0xd3, Some(port as u16)),
+            In(port) => (0xdb, Some(port as u16)),
+            Xthl => (0xe3, None),
+            Pchl => (0xe9, None),
+            Xchg => (0xeb, None),
+            Sphl => (0xf9, None),
+            Di => (0xf3, None),
+            Ei => (0xfb, None),
+        };
+        let mut bytes = vec![op];
+        if let Some(x) = operand {
+            bytes.push((x & 0xff) as u8);
+            if self.size() == 3 {
+                bytes.push((x >> 8) as u8);
+            }
+        }
+        bytes
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(|_| None))
+    }
+}
+
+/// Write an 8 bit value as an assembler would read it.
+fn asm_byte(x: u8) -> String {
+    asm_hex(format!("{:02X}", x))
+}
+
+/// Write a 16 bit value as an assembler would read it.
+fn asm_word(x: u16) -> String {
+    asm_hex(format!("{:04X}", x))
+}
+
+/// Intel style hex, with a trailing H and a leading
+/// 0 if needed so it doesn't look like a name.
+fn asm_hex(digits: String) -> String {
+    if digits.starts_with(|c: char| c.is_alphabetic()) {
+        format!("0{}H", digits)
+    } else {
+        format!("{}H", digits)
+    }
+}
+
+/// One disassembled instruction, or bytes at the end
+/// of the input too short to hold a whole instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    /// `None` if the instruction was truncated.
+    pub instr: Option<Instruction>,
+}
+
+impl Line {
+    /// The instruction, or DB for data, with addresses
+    /// replaced by the name `name` gives them.
+    pub fn to_string_with<F: Fn(u16) -> Option<String>>(&self, name: F) -> String {
+        match self.instr {
+            Some(ref instr) => instr.to_string_with(name),
+            None => {
+                let bytes: Vec<String> = self.bytes.iter()
+                    .map(|x| format!("#{:02x}", x))
+                    .collect();
+                format!("DB {}", bytes.join(","))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x} {}", self.addr, self.to_string_with(|_| None))
+    }
+}
+
+/// Iterator over the instructions in a buffer
+/// of compiled 8080 code, see `disassemble`.
+pub struct Disassembler<'a> {
+    bytes: &'a [u8],
+    offset: u16,
+    pc: usize,
+}
+
+impl<'a> Iterator for Disassembler<'a> {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        if self.pc >= self.bytes.len() {
+            return None;
+        }
+        let addr = self.offset.wrapping_add(self.pc as u16);
+        let len = opcode_len(self.bytes[self.pc]);
+        let line = if self.pc + len > self.bytes.len() {
+            Line {
+                addr: addr,
+                bytes: self.bytes[self.pc..].to_vec(),
+                instr: None,
+            }
+        } else {
+            Line {
+                addr: addr,
+                bytes: self.bytes[self.pc..self.pc + len].to_vec(),
+                instr: Some(decode(self.bytes, self.pc).0),
+            }
+        };
+        self.pc += line.bytes.len();
+        Some(line)
+    }
+}
 
 /// Disassemble the given compiled 8080 binary
 /// code in `bytes`.
 /// `offset` is where the first instruction
 /// starts in memory.
-pub fn disassemble(bytes: &[u8], offset: usize) {
-    let mut pc = 0;
-    let mut iter = bytes.iter();
-    while let Some(op) = iter.next() {
-        print!("{:>0pad$x} ", pc + offset, pad=4);
-        match *op {
-            0x00 => println!("NOP"),
-            0x01 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("LXI B #${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0x02 => println!("STAX B"),
-            0x03 => println!("INX B"),
-            0x04 => println!("INR B"),
-            0x05 => println!("DCR B"),
-            0x06 => {
-                let x = iter.next().unwrap();
-                println!("MVI B,#{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0x07 => println!("RLC"),
-            // 0x08 =>
-            0x09 => println!("DAD B"),
-            0x0a => println!("LDAX B"),
-            0x0b => println!("DCX B"),
-            0x0c => println!("INR C"),
-            0x0d => println!("DCR C"),
-            0x0e => {
-                let x = iter.next().unwrap();
-                println!("MVI C,#{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0x0f => println!("RRC"),
-            // 0x10 =>
-            0x11 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("LXI D,#${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0x12 => println!("STAX D"),
-            0x13 => println!("INX D"),
-            0x14 => println!("INR D"),
-            0x15 => println!("DCR D"),
-            0x16 => {
-                let x = iter.next().unwrap();
-                println!("MVI D,#{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0x17 => println!("RAL"),
-            // 0x18 => 
-            0x19 => println!("DAD D"),
-            0x1a => println!("LDAX D"),
-            0x1b => println!("DCX D"),
-            0x1c => println!("INR E"),
-            0x1d => println!("DCR E"),
-            0x1e => {
-                let x = iter.next().unwrap();
-                println!("MVI E,#{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0x1f => println!("RAR"),
-            0x20 => println!("RIM"),
-            0x21 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("LXI H,#{:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0x22 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("SHLD ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
+pub fn disassemble<'a>(bytes: &'a [u8], offset: u16) -> Disassembler<'a> {
+    Disassembler {
+        bytes: bytes,
+        offset: offset,
+        pc: 0,
+    }
+}
+
+/// Disassemble `count` instructions starting at `addr`,
+/// reading each byte with `read`. This works on live
+/// memory such as a running machine's.
+pub fn disassemble_with<F: Fn(u16) -> u8>(read: F, addr: u16, count: usize) -> Vec<Line> {
+    let mut lines = Vec::with_capacity(count);
+    let mut addr = addr;
+    for _ in 0..count {
+        let bytes = [read(addr), read(addr.wrapping_add(1)), read(addr.wrapping_add(2))];
+        let (instr, len) = decode(&bytes, 0);
+        lines.push(Line {
+            addr: addr,
+            bytes: bytes[..len].to_vec(),
+            instr: Some(instr),
+        });
+        addr = addr.wrapping_add(len as u16);
+    }
+    lines
+}
+
+/// Disassemble `count` instructions from
+/// `mem` starting at `addr`.
+pub fn disassemble_memory(mem: &Memory, addr: u16, count: usize) -> Vec<Line> {
+    disassemble_with(|addr| mem.read(addr), addr, count)
+}
+
+/// Most data bytes to put on one DB line.
+const DB_WIDTH: usize = 8;
+
+/// A disassembled binary with labels for the
+/// addresses control can be transferred to.
+pub struct Listing {
+    pub lines: Vec<Line>,
+    /// Branch targets and entry points.
+    pub labels: BTreeSet<u16>,
+    /// Names and comments that take the place
+    /// of generated labels.
+    pub symbols: Symbols,
+}
+
+impl Listing {
+    /// A listing of `lines` with no labels.
+    pub fn new(lines: Vec<Line>) -> Self {
+        Listing::with_targets(lines, &BTreeSet::new())
+    }
+
+    /// Label those of `targets` that start a line,
+    /// anything else is referred to by number.
+    fn with_targets(lines: Vec<Line>, targets: &BTreeSet<u16>) -> Self {
+        let labels = lines.iter()
+            .map(|line| line.addr)
+            .filter(|addr| targets.contains(addr))
+            .collect();
+        Listing {
+            lines: lines,
+            labels: labels,
+            symbols: Symbols::new(),
+        }
+    }
+
+    /// Disassemble all of `bytes` in a linear sweep,
+    /// labelling the targets of jumps and calls.
+    pub fn linear(bytes: &[u8], offset: u16) -> Self {
+        let lines: Vec<Line> = disassemble(bytes, offset).collect();
+        let targets = lines.iter()
+            .filter_map(|line| line.instr.and_then(|instr| instr.target()))
+            .collect();
+        Listing::with_targets(lines, &targets)
+    }
+
+    /// The name for `addr`, from the symbols or
+    /// a generated label, if it has one.
+    pub fn label(&self, addr: u16) -> Option<String> {
+        if let Some(name) = self.symbols.name(addr) {
+            Some(String::from(name))
+        } else if self.labels.contains(&addr) {
+            Some(format!("L_{:04X}", addr))
+        } else {
+            None
+        }
+    }
+
+    /// Assembly language source that assembles back to
+    /// the same binary. Undocumented opcodes are written
+    /// as DB since assemblers only produce the documented
+    /// encoding.
+    pub fn to_asm(&self) -> String {
+        let mut asm = String::new();
+        // Names used for addresses that don't start
+        // a line have to be defined separately.
+        let starts: BTreeSet<u16> = self.lines.iter().map(|line| line.addr).collect();
+        let equs: BTreeSet<u16> = self.lines.iter()
+            .filter_map(|line| line.instr.and_then(|instr| instr.address()))
+            .filter(|addr| !starts.contains(addr) && self.symbols.name(*addr).is_some())
+            .collect();
+        for addr in equs {
+            let name = self.label(addr).unwrap();
+            asm.push_str(&format!("{:<8}{:<8}{}\n", name, "EQU", asm_word(addr)));
+        }
+        if let Some(line) = self.lines.first() {
+            asm.push_str(&format!("        {:<8}{}\n", "ORG", asm_word(line.addr)));
+        }
+        for line in self.lines.iter() {
+            if let Some(label) = self.label(line.addr) {
+                asm.push_str(&format!("{}:\n", label));
             }
-            0x23 => println!("INX H"),
-            0x24 => println!("INR H"),
-            0x25 => println!("DCR H"),
-            0x26 => {
-                let x = iter.next().unwrap();
-                println!("MVI H,#{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0x27 => println!("DAA"),
-            0x29 => println!("DAD H"),
-            0x2a => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("LHLD ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0x2b => println!("DCX H"),
-            0x2c => println!("INR L"),
-            0x2d => println!("DCR L"),
-            0x2e => {
-                let x = iter.next().unwrap();
-                println!("MVI L,#{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0x2f => println!("CMA"),
-            0x30 => println!("SIM"),
-            0x31 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("LXI SP,#{:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0x32 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("STA ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0x33 => println!("INX SP"),
-            0x34 => println!("INR M"),
-            0x35 => println!("DCR M"),
-            0x36 => {
-                let x = iter.next().unwrap();
-                println!("MVI M,#{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0x37 => println!("STC"),
-            0x3a => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("LDA ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0x3b => println!("DCX SP"),
-            0x3c => println!("INR A"),
-            0x3d => println!("DCR A"),
-            0x3e =>  {
-                let x = iter.next().unwrap();
-                println!("MVI A,#{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0x3f => println!("CMC"),
-            0x40 => println!("MOV B,B"),
-            0x41 => println!("MOV B,C"),
-            0x42 => println!("MOV B,D"),
-            0x43 => println!("MOV B,E"),
-            0x44 => println!("MOV B,H"),
-            0x45 => println!("MOV B,L"),
-            0x46 => println!("MOV B,M"),
-            0x47 => println!("MOV B,A"),
-            0x48 => println!("MOV C,B"),
-            0x49 => println!("MOV C,C"),
-            0x4a => println!("MOV C,D"),
-            0x4b => println!("MOV C,E"),
-            0x4c => println!("MOV C,H"),
-            0x4d => println!("MOV C,L"),
-            0x4e => println!("MOV C,M"),
-            0x4f => println!("MOV C,A"),
-            0x50 => println!("MOV D,B"),
-            0x51 => println!("MOV D,C"),
-            0x52 => println!("MOV D,D"),
-            0x53 => println!("MOV D,E"),
-            0x54 => println!("MOV D,H"),
-            0x55 => println!("MOV D,L"),
-            0x56 => println!("MOV D,M"),
-            0x57 => println!("MOV D,A"),
-            0x58 => println!("MOV E,B"),
-            0x59 => println!("MOV E,C"),
-            0x5a => println!("MOV E,D"),
-            0x5b => println!("MOV E,E"),
-            0x5c => println!("MOV E,H"),
-            0x5d => println!("MOV E,L"),
-            0x5e => println!("MOV E,M"),
-            0x5f => println!("MOV E,A"),
-            0x60 => println!("MOV H,B"),
-            0x61 => println!("MOV H,C"),
-            0x62 => println!("MOV H,D"),
-            0x63 => println!("MOV H,E"),
-            0x64 => println!("MOV H,H"),
-            0x65 => println!("MOV H,L"),
-            0x66 => println!("MOV H,M"),
-            0x67 => println!("MOV H,A"),
-            0x68 => println!("MOV L,B"),
-            0x69 => println!("MOV L,C"),
-            0x6a => println!("MOV L,D"),
-            0x6b => println!("MOV L,E"),
-            0x6c => println!("MOV L,H"),
-            0x6d => println!("MOV L,L"),
-            0x6e => println!("MOV L,M"),
-            0x6f => println!("MOV L,A"),
-            0x70 => println!("MOV M,B"),
-            0x71 => println!("MOV M,C"),
-            0x72 => println!("MOV M,D"),
-            0x73 => println!("MOV M,E"),
-            0x74 => println!("MOV M,H"),
-            0x75 => println!("MOV M,L"),
-            0x76 => println!("HLT"),
-            0x77 => println!("MOV M,A"),
-            0x78 => println!("MOV A,B"),
-            0x79 => println!("MOV A,C"),
-            0x7a => println!("MOV A,D"),
-            0x7b => println!("MOV A,E"),
-            0x7c => println!("MOV A,H"),
-            0x7d => println!("MOV A,L"),
-            0x7e => println!("MOV A,M"),
-            0x7f => println!("MOV A,A"),
-            0x80 => println!("ADD B"),
-            0x81 => println!("ADD C"),
-            0x82 => println!("ADD D"),
-            0x83 => println!("ADD E"),
-            0x84 => println!("ADD H"),
-            0x85 => println!("ADD L"),
-            0x86 => println!("ADD M"),
-            0x87 => println!("ADD A"),
-            0x88 => println!("ADC B"),
-            0x89 => println!("ADC C"),
-            0x8a => println!("ADC D"),
-            0x8b => println!("ADC E"),
-            0x8c => println!("ADC H"),
-            0x8d => println!("ADC L"),
-            0x8e => println!("ADC M"),
-            0x8f => println!("ADC A"),
-            0x90 => println!("SUB B"),
-            0x91 => println!("SUB C"),
-            0x92 => println!("SUB D"),
-            0x93 => println!("SUB E"),
-            0x94 => println!("SUB H"),
-            0x95 => println!("SUB L"),
-            0x96 => println!("SUB M"),
-            0x97 => println!("SUB A"),
-            0x98 => println!("SBB B"),
-            0x99 => println!("SBB C"),
-            0x9a => println!("SBB D"),
-            0x9b => println!("SBB E"),
-            0x9c => println!("SBB H"),
-            0x9d => println!("SBB L"),
-            0x9e => println!("SBB M"),
-            0x9f => println!("SBB A"),
-            0xa0 => println!("ANA B"),
-            0xa1 => println!("ANA C"),
-            0xa2 => println!("ANA D"),
-            0xa3 => println!("ANA E"),
-            0xa4 => println!("ANA H"),
-            0xa5 => println!("ANA L"),
-            0xa6 => println!("ANA M"),
-            0xa7 => println!("ANA A"),
-            0xa8 => println!("XRA B"),
-            0xa9 => println!("XRA C"),
-            0xaa => println!("XRA D"),
-            0xab => println!("XRA E"),
-            0xac => println!("XRA H"),
-            0xad => println!("XRA L"),
-            0xae => println!("XRA M"),
-            0xaf => println!("XRA A"),
-            0xb0 => println!("ORA B"),
-            0xb1 => println!("ORA C"),
-            0xb2 => println!("ORA D"),
-            0xb3 => println!("ORA E"),
-            0xb4 => println!("ORA H"),
-            0xb5 => println!("ORA L"),
-            0xb6 => println!("ORA M"),
-            0xb7 => println!("ORA A"),
-            0xb8 => println!("CMP B"),
-            0xb9 => println!("CMP C"),
-            0xba => println!("CMP D"),
-            0xbb => println!("CMP E"),
-            0xbc => println!("CMP H"),
-            0xbd => println!("CMP L"),
-            0xbe => println!("CMP M"),
-            0xbf => println!("CMP A"),
-            0xc0 => println!("RNZ"),
-            0xc1 => println!("POP B"),
-            0xc2 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JNZ ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xc3 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JMP ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xc4 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CNZ ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
+            let comment = self.symbols.comment(line.addr);
+            let text = match line.instr {
+                Some(instr) if instr.encode() == line.bytes => {
+                    with_comment(instr.to_asm_with(|addr| self.label(addr)), comment)
+                },
+                Some(instr) => {
+                    let instr = instr.to_asm_with(|addr| self.label(addr));
+                    let comment = match comment {
+                        Some(comment) => format!("{} {}", instr, comment),
+                        None => instr,
+                    };
+                    with_comment(asm_db(&line.bytes), Some(&comment))
+                },
+                None => with_comment(asm_db(&line.bytes), comment),
+            };
+            asm.push_str(&format!("        {}\n", text));
+        }
+        asm
+    }
+}
+
+/// A DB directive holding `bytes`.
+fn asm_db(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().cloned().map(asm_byte).collect();
+    format!("{:<8}{}", "DB", bytes.join(","))
+}
+
+/// Follow `text` with a comment, if there is one.
+fn with_comment(text: String, comment: Option<&str>) -> String {
+    match comment {
+        Some(comment) => format!("{:<24}; {}", text, comment),
+        None => text,
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.lines.iter() {
+            if let Some(label) = self.label(line.addr) {
+                writeln!(f, "{}:", label)?;
             }
-            0xc5 => println!("PUSH B"),
-            0xc6 => {
-                let x = iter.next().unwrap();
-                println!("ADI #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xc8 => println!("RZ"),
-            0xc9 => println!("RET"),
-            0xca => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JZ ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xcc => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CZ ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xcd => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CALL ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xce => {
-                let x = iter.next().unwrap();
-                println!("ACI #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xd0 => println!("RNC"),
-            0xd1 => println!("POP D"),
-            0xd2 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JNC ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xd3 => {
-                let x = iter.next().unwrap();
-                println!("OUT #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xd4 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CNC ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xd5 => println!("PUSH D"),
-            0xd6 => {
-                let x = iter.next().unwrap();
-                println!("SUI #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xda => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JC ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
+            let text = line.to_string_with(|addr| self.label(addr));
+            let comment = self.symbols.comment(line.addr);
+            writeln!(f, "{:04x} {}", line.addr, with_comment(text, comment))?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassemble `bytes`, loaded at `offset`, by following
+/// jumps, calls and RSTs from each of the `entries` rather
+/// than decoding everything in a linear sweep. Bytes that
+/// are never reached are assumed to be data.
+pub fn disassemble_from(bytes: &[u8], offset: u16, entries: &[u16]) -> Listing {
+    let index = |addr: u16| {
+        let i = addr.wrapping_sub(offset) as usize;
+        if addr >= offset && i < bytes.len() { Some(i) } else { None }
+    };
+    let mut starts = vec![false; bytes.len()];
+    let mut code = vec![false; bytes.len()];
+    let mut targets = BTreeSet::new();
+    let mut pending: Vec<u16> = entries.to_vec();
+    while let Some(mut addr) = pending.pop() {
+        targets.insert(addr);
+        while let Some(i) = index(addr) {
+            let len = opcode_len(bytes[i]);
+            // Stop at anything already decoded, an instruction
+            // running off the end or overlapping another.
+            if i + len > bytes.len() || code[i..i + len].iter().any(|x| *x) {
+                break;
             }
-            0xdb => {
-                let x = iter.next().unwrap();
-                println!("IN #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xdc => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CC ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xde => {
-                let x = iter.next().unwrap();
-                println!("SBI #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xe0 => println!("RPO"),
-            0xe1 => println!("POP H"),
-            0xe2 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JPO ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xe3 => println!("XTHL"),
-            0xe4 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CPO ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xe5 => println!("PUSH H"),
-            0xe6 => {
-                let x = iter.next().unwrap();
-                println!("ANI #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xe8 => println!("RPE"),
-            0xe9 => println!("PCHL"),
-            0xea => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JPE ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xeb => println!("XCHG"),
-            0xec => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CPE ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xee => {
-                let x = iter.next().unwrap();
-                println!("XRI #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xf0 => println!("RP"),
-            0xf1 => println!("POP PSW"),
-            0xf2 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JP ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xf4 => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CP ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xf5 => println!("PUSH PSW"),
-            0xf6 => {
-                let x = iter.next().unwrap();
-                println!("ORI #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xf7 => println!("RST 6"),
-            0xf8 => println!("RM"),
-            0xf9 => println!("SPHL"),
-            0xfa => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("JM ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xfb => println!("EI"),
-            0xfc => {
-                let lo = iter.next().unwrap();
-                let hi = iter.next().unwrap();
-                println!("CM ${:>0pad$x}{:>0pad$x}", hi, lo, pad=2);
-                pc += 2;
-            },
-            0xfe => {
-                let x = iter.next().unwrap();
-                println!("CPI #{:>0pad$x}", x, pad=2);
-                pc += 1;
-            },
-            0xff => println!("RST 7"),
-            x => println!("unknown {:>0pad$x}", x, pad=2)
+            starts[i] = true;
+            for x in code[i..i + len].iter_mut() {
+                *x = true;
+            }
+            let instr = decode(bytes, i).0;
+            if let Some(target) = instr.target() {
+                if !targets.contains(&target) {
+                    pending.push(target);
+                }
+                targets.insert(target);
+            }
+            if !instr.falls_through() {
+                break;
+            }
+            addr = addr.wrapping_add(len as u16);
         }
-        pc += 1;
     }
-}
\ No newline at end of file
+
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let addr = offset.wrapping_add(i as u16);
+        if starts[i] {
+            let (instr, len) = decode(bytes, i);
+            lines.push(Line {
+                addr: addr,
+                bytes: bytes[i..i + len].to_vec(),
+                instr: Some(instr),
+            });
+            i += len;
+        } else {
+            // Data runs up to the next code or label
+            let mut end = i + 1;
+            while end < bytes.len() && end - i < DB_WIDTH && !code[end] &&
+                !targets.contains(&offset.wrapping_add(end as u16)) {
+                end += 1;
+            }
+            lines.push(Line {
+                addr: addr,
+                bytes: bytes[i..end].to_vec(),
+                instr: None,
+            });
+            i = end;
+        }
+    }
+
+    Listing::with_targets(lines, &targets)
+}
+
+/// The default entry points, the reset and RST
+/// vectors, that fall within a binary loaded at
+/// `offset`. If none do the first byte is used.
+pub fn default_entries(len: usize, offset: u16) -> Vec<u16> {
+    let end = offset as usize + len;
+    let entries: Vec<u16> = (0..8)
+        .map(|n| n * 8)
+        .filter(|addr| *addr >= offset && (*addr as usize) < end)
+        .collect();
+    if entries.is_empty() { vec![offset] } else { entries }
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(decode(&[0x00], 0), (Instruction::Nop, 1));
+    assert_eq!(decode(&[0x00, 0x31, 0x00, 0x24], 1),
+               (Instruction::Lxi(RegPair::SP, 0x2400), 3));
+    assert_eq!(decode(&[0x36, 0x12], 0), (Instruction::Mvi(Loc::Mem, 0x12), 2));
+    assert_eq!(decode(&[0x7e], 0), (Instruction::Mov(Loc::Reg(Reg::A), Loc::Mem), 1));
+    assert_eq!(decode(&[0xf1], 0), (Instruction::Pop(RegPair::PSW), 1));
+    assert_eq!(decode(&[0xfa, 0x34, 0x12], 0),
+               (Instruction::Jcc(Condition::Minus, 0x1234), 3));
+    assert_eq!(decode(&[0xdf], 0), (Instruction::Rst(3), 1));
+    // Undocumented aliases
+    assert_eq!(decode(&[0x38], 0), (Instruction::Nop, 1));
+    assert_eq!(decode(&[0xdd, 0x00, 0x10], 0), (Instruction::Call(0x1000), 3));
+}
+
+#[test]
+fn test_display() {
+    let cases = [
+        (vec![0x01, 0x34, 0x12], "LXI B,#$1234"),
+        (vec![0x0e, 0x0a], "MVI C,#0a"),
+        (vec![0x22, 0x00, 0x20], "SHLD $2000"),
+        (vec![0x70], "MOV M,B"),
+        (vec![0x9e], "SBB M"),
+        (vec![0xc0], "RNZ"),
+        (vec![0xe2, 0xd4, 0x18], "JPO $18d4"),
+        (vec![0xf5], "PUSH PSW"),
+        (vec![0xd3, 0x06], "OUT #06"),
+        (vec![0xcf], "RST 1"),
+    ];
+    for &(ref bytes, text) in cases.iter() {
+        assert_eq!(format!("{}", decode(bytes, 0).0), text);
+    }
+}
+
+#[test]
+fn test_len_and_cycles() {
+    for op in 0..256 {
+        let bytes = [op as u8, 0, 0];
+        let (instr, len) = decode(&bytes, 0);
+        assert_eq!(len, opcode_len(op as u8));
+        assert!(instr.cycles() >= 4 && instr.cycles_taken() >= instr.cycles());
+    }
+    assert_eq!(decode(&[0xc4, 0, 0], 0).0.cycles_taken(), 17);
+    assert_eq!(decode(&[0xe3], 0).0.cycles(), 18);
+}
+
+#[test]
+fn test_disassemble() {
+    // LXI SP,$2400; MVI A,#01; JMP $0000
+    let bytes = [0x31, 0x00, 0x24, 0x3e, 0x01, 0xc3, 0x00, 0x00];
+    let lines: Vec<Line> = disassemble(&bytes, 0x100).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[1], Line {
+        addr: 0x103,
+        bytes: vec![0x3e, 0x01],
+        instr: Some(Instruction::Mvi(Loc::Reg(Reg::A), 0x01)),
+    });
+    assert_eq!(format!("{}", lines[2]), "0105 JMP $0000");
+}
+
+#[test]
+fn test_disassemble_truncated() {
+    // NOP then a CALL missing its high byte
+    let lines: Vec<Line> = disassemble(&[0x00, 0xcd, 0x12], 0).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1].instr, None);
+    assert_eq!(lines[1].bytes, vec![0xcd, 0x12]);
+    assert_eq!(format!("{}", lines[1]), "0001 DB #cd,#12");
+}
+
+#[test]
+fn test_disassemble_memory() {
+    let mem = Memory::with_data_and_offset(&[0x21, 0x00, 0x20, 0x77, 0xc9], 0x1000);
+    let lines = disassemble_memory(&mem, 0x1000, 3);
+    let text: Vec<String> = lines.iter().map(|line| format!("{}", line)).collect();
+    assert_eq!(text, vec!["1000 LXI H,#$2000", "1003 MOV M,A", "1004 RET"]);
+}
+
+#[test]
+fn test_disassemble_from() {
+    let bytes = [
+        0xc3, 0x06, 0x00,       // 0000 JMP L_0006
+        0x41, 0x42, 0x43,       // 0003 "ABC"
+        0xcd, 0x0e, 0x00,       // 0006 CALL L_000E
+        0xc3, 0x00, 0x00,       // 0009 JMP L_0000
+        0x18, 0xfe,             // 000c undocumented NOP, data
+        0xc9,                   // 000e RET
+    ];
+    let listing = disassemble_from(&bytes, 0, &[0]);
+    let addrs: Vec<u16> = listing.lines.iter().map(|line| line.addr).collect();
+    assert_eq!(addrs, vec![0x0000, 0x0003, 0x0006, 0x0009, 0x000c, 0x000e]);
+    assert_eq!(listing.lines[1].instr, None);
+    assert_eq!(listing.lines[1].bytes, vec![0x41, 0x42, 0x43]);
+    assert_eq!(listing.labels.iter().cloned().collect::<Vec<u16>>(), vec![0x0000, 0x0006, 0x000e]);
+    assert_eq!(format!("{}", listing), "\
+L_0000:
+0000 JMP L_0006
+0003 DB #41,#42,#43
+L_0006:
+0006 CALL L_000E
+0009 JMP L_0000
+000c DB #18,#fe
+L_000E:
+000e RET
+");
+}
+
+#[test]
+fn test_default_entries() {
+    assert_eq!(default_entries(0x2000, 0), vec![0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38]);
+    assert_eq!(default_entries(0x0c, 0), vec![0x00, 0x08]);
+    assert_eq!(default_entries(0x80, 0x100), vec![0x100]);
+}
+
+#[test]
+fn test_encode() {
+    for op in 0..256 {
+        let bytes = [op as u8, 0x34, 0x12];
+        let (instr, len) = decode(&bytes, 0);
+        let encoded = instr.encode();
+        assert_eq!(encoded.len(), len);
+        // Only the undocumented aliases encode differently
+        if encoded[0] == op as u8 {
+            assert_eq!(&encoded[..], &bytes[..len]);
+        } else {
+            assert_eq!(decode(&encoded, 0).0, instr);
+        }
+    }
+}
+
+#[test]
+fn test_to_asm() {
+    let bytes = [
+        0x3e, 0xff,             // MVI A,0FFH
+        0xda, 0x08, 0x01,       // JC L_0108
+        0xcb, 0x00, 0x01,       // undocumented JMP
+        0x32, 0x00, 0x20,       // STA 2000H
+        0xc9,                   // RET
+    ];
+    let asm = [
+        "        ORG     0100H",
+        "L_0100:",
+        "        MVI     A,0FFH",
+        "        JC      L_0108",
+        "        DB      0CBH,00H,01H    ; JMP     L_0100",
+        "L_0108:",
+        "        STA     2000H",
+        "        RET",
+        "",
+    ];
+    assert_eq!(Listing::linear(&bytes, 0x100).to_asm(), asm.join("\n"));
+}
+
+#[test]
+fn test_symbols() {
+    // CALL $0006; STA $2000; RET; RET
+    let bytes = [0xcd, 0x06, 0x00, 0x32, 0x00, 0x20, 0xc9];
+    let mut listing = disassemble_from(&bytes, 0, &[0]);
+    listing.symbols = Symbols::parse("\
+0006 Done ; All finished
+2000 Score
+").unwrap();
+    let text = [
+        "L_0000:",
+        "0000 CALL Done",
+        "0003 STA Score",
+        "Done:",
+        "0006 RET                     ; All finished",
+        "",
+    ];
+    assert_eq!(format!("{}", listing), text.join("\n"));
+    let asm = [
+        "Score   EQU     2000H",
+        "        ORG     0000H",
+        "L_0000:",
+        "        CALL    Done",
+        "        STA     Score",
+        "Done:",
+        "        RET                     ; All finished",
+        "",
+    ];
+    assert_eq!(listing.to_asm(), asm.join("\n"));
+}
@@ -0,0 +1,86 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Tracks on an 8" disk.
+pub const TRACKS: u16 = 77;
+/// Sectors per track, numbered from 1.
+pub const SECTORS: u16 = 26;
+/// Bytes per sector.
+pub const SECTOR_SIZE: usize = 128;
+
+/// Value of bytes on a freshly formatted disk.
+const FORMAT_BYTE: u8 = 0xe5;
+
+/// An image of an IBM 3740 8" single sided single
+/// density floppy disk, the standard CP/M 2.2
+/// distribution format. Sectors are stored in
+/// physical order, track by track, for 256,256
+/// bytes in total.
+pub struct Disk {
+    file: File,
+    read_only: bool,
+}
+
+impl Disk {
+    /// Open an image for reading and writing,
+    /// falling back to read only if the file
+    /// can't be written to.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => Ok(Disk { file: file, read_only: false }),
+            Err(_) => {
+                let file = File::open(path)?;
+                Ok(Disk { file: file, read_only: true })
+            },
+        }
+    }
+
+    /// Byte offset of a sector in the image.
+    fn offset(track: u16, sector: u16) -> Option<u64> {
+        if track >= TRACKS || sector < 1 || sector > SECTORS {
+            return None;
+        }
+        let index = track as u64 * SECTORS as u64 + (sector - 1) as u64;
+        Some(index * SECTOR_SIZE as u64)
+    }
+
+    /// Read a sector, returning false if it doesn't
+    /// exist. Sectors past the end of a short image
+    /// read as freshly formatted.
+    pub fn read_sector(&mut self, track: u16, sector: u16, buf: &mut [u8; SECTOR_SIZE]) -> bool {
+        let offset = match Disk::offset(track, sector) {
+            Some(offset) => offset,
+            None => return false,
+        };
+        for x in buf.iter_mut() {
+            *x = FORMAT_BYTE;
+        }
+        if self.file.seek(SeekFrom::Start(offset)).is_err() {
+            return false;
+        }
+        let mut n = 0;
+        while n < SECTOR_SIZE {
+            match self.file.read(&mut buf[n..]) {
+                Ok(0) => break,
+                Ok(read) => n += read,
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Write a sector, returning false if it doesn't
+    /// exist or the image is read only.
+    pub fn write_sector(&mut self, track: u16, sector: u16, buf: &[u8; SECTOR_SIZE]) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let offset = match Disk::offset(track, sector) {
+            Some(offset) => offset,
+            None => return false,
+        };
+        self.file.seek(SeekFrom::Start(offset)).is_ok() && self.file.write_all(buf).is_ok()
+    }
+}
@@ -0,0 +1,479 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use machine::Machine;
+use memory::Access;
+
+/// How often the machine gets to idle
+/// while waiting for a packet.
+const IDLE_MS: u64 = 16;
+
+/// Steps run between looking for an interrupt
+/// from gdb, and letting the machine idle,
+/// while continuing.
+const POLL_STEPS: usize = 10_000;
+
+/// The largest packet gdb may send or be sent,
+/// which bounds the memory read at once.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Sent by gdb outside of a packet to
+/// stop the machine while it's running.
+const INTERRUPT: u8 = 0x03;
+
+// Signals given in stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Watch {
+    addr: u16,
+    read: bool,
+    write: bool,
+}
+
+/// A stub for the GDB Remote Serial Protocol
+/// that lets gdb debug a machine.
+///
+/// The 8080 registers are given in the order gdb's z80
+/// target uses, as 16-bit little endian pairs: AF, BC,
+/// DE, HL, SP and PC, so `gdb-multiarch` with
+/// `set architecture z80` can read them.
+pub struct GdbStub<T: Machine> {
+    machine: T,
+    stream: TcpStream,
+    breakpoints: Vec<u16>,
+    watches: Vec<Watch>,
+}
+
+/// Wait for gdb to connect to `addr`, then serve it
+/// until it detaches or kills the machine.
+pub fn serve<T: Machine>(machine: T, addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("Waiting for gdb on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    println!("gdb connected from {}", peer);
+    GdbStub::new(machine, stream).run()
+}
+
+/// Bytes as a string of hex digits.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// A string of hex digits as bytes.
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len() / 2)
+        .map(|i| hex.get(2 * i..2 * i + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok()))
+        .collect()
+}
+
+/// Split `ADDR,LENGTH` in hex.
+fn addr_length(args: &str) -> Option<(u16, usize)> {
+    let mut parts = args.splitn(2, ',');
+    let addr = parts.next().and_then(|addr| u16::from_str_radix(addr, 16).ok());
+    let length = parts.next().and_then(|length| usize::from_str_radix(length, 16).ok());
+    match (addr, length) {
+        (Some(addr), Some(length)) => Some((addr, length)),
+        _ => None,
+    }
+}
+
+/// A packet as sent on the wire, `$data#checksum`.
+fn frame(data: &str) -> String {
+    let sum = data.bytes().fold(0u8, |sum, x| sum.wrapping_add(x));
+    format!("${}#{:02x}", data, sum)
+}
+
+impl<T: Machine> GdbStub<T> {
+    pub fn new(machine: T, stream: TcpStream) -> Self {
+        GdbStub {
+            machine: machine,
+            stream: stream,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+        }
+    }
+
+    /// Read a byte, letting the machine idle until one comes.
+    fn read_byte(&mut self) -> io::Result<u8> {
+        self.stream.set_read_timeout(Some(Duration::from_millis(IDLE_MS)))?;
+        let mut byte = [0];
+        loop {
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "gdb disconnected")),
+                Ok(_) => return Ok(byte[0]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                    self.machine.idle();
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Read the next packet, acknowledging it, or
+    /// None for an interrupt sent while stopped.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                b'$' => {},
+                INTERRUPT => return Ok(None),
+                // Acks and anything else between packets
+                _ => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    x => data.push(x),
+                }
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let sum = data.iter().fold(0u8, |sum, x| sum.wrapping_add(*x));
+            let expected = ::std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if expected == Some(sum) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    /// Send a packet, resending until gdb acknowledges it.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = frame(data);
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    /// Has gdb sent an interrupt while the machine's running?
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = match self.stream.read(&mut byte) {
+            Ok(0) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "gdb disconnected")),
+            Ok(_) => Ok(byte[0] == INTERRUPT),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    fn update_watches(&mut self) {
+        let addrs: Vec<u16> = self.watches.iter().map(|watch| watch.addr).collect();
+        self.machine.set_watches(&addrs);
+    }
+
+    /// Step once, returning a stop reply if
+    /// the machine should stop after it.
+    fn step(&mut self) -> Option<String> {
+        self.machine.take_accesses();
+        self.machine.step();
+        if self.machine.stopped() {
+            return Some(String::from("W00"));
+        }
+        for (addr, access) in self.machine.take_accesses() {
+            let watch = self.watches.iter().find(|watch| watch.addr == addr && match access {
+                Access::Read => watch.read,
+                Access::Write => watch.write,
+            });
+            if let Some(watch) = watch {
+                let kind = match (watch.read, watch.write) {
+                    (true, true) => "awatch",
+                    (true, false) => "rwatch",
+                    _ => "watch",
+                };
+                return Some(format!("T{:02x}{}:{:04x};", SIGTRAP, kind, addr));
+            }
+        }
+        if self.breakpoints.contains(&self.machine.get_pc()) {
+            return Some(format!("S{:02x}", SIGTRAP));
+        }
+        None
+    }
+
+    /// Run until a breakpoint or watchpoint
+    /// is hit or gdb interrupts.
+    fn resume(&mut self) -> io::Result<String> {
+        loop {
+            for _ in 0..POLL_STEPS {
+                if let Some(reply) = self.step() {
+                    return Ok(reply);
+                }
+            }
+            // Keep a window drawn and responding
+            self.machine.idle();
+            if self.interrupted()? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    /// The registers as gdb numbers them.
+    fn register(&self, n: usize) -> Option<u16> {
+        let m = &self.machine;
+        let pair = |hi: u8, lo: u8| (hi as u16) << 8 | lo as u16;
+        match n {
+            0 => Some(pair(m.get_a(), m.get_flags())),
+            1 => Some(pair(m.get_b(), m.get_c())),
+            2 => Some(pair(m.get_d(), m.get_e())),
+            3 => Some(pair(m.get_h(), m.get_l())),
+            4 => Some(m.get_sp()),
+            5 => Some(m.get_pc()),
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: u16) -> bool {
+        let (hi, lo) = ((value >> 8) as u8, value as u8);
+        let m = &mut self.machine;
+        match n {
+            0 => { m.set_a(hi); m.set_flags(lo); },
+            1 => { m.set_b(hi); m.set_c(lo); },
+            2 => { m.set_d(hi); m.set_e(lo); },
+            3 => { m.set_h(hi); m.set_l(lo); },
+            4 => m.set_sp(value),
+            5 => m.set_pc(value),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Add or remove a breakpoint or watchpoint
+    /// from a `Z` or `z` packet's arguments.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> &'static str {
+        let mut parts = args.splitn(3, ',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(|addr| u16::from_str_radix(addr, 16).ok());
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return "E01",
+        };
+        let (read, write) = match kind {
+            Some("0") | Some("1") => {
+                self.breakpoints.retain(|&at| at != addr);
+                if insert {
+                    self.breakpoints.push(addr);
+                }
+                return "OK";
+            },
+            Some("2") => (false, true),
+            Some("3") => (true, false),
+            Some("4") => (true, true),
+            _ => return "",
+        };
+        let watch = Watch { addr: addr, read: read, write: write };
+        self.watches.retain(|&other| other != watch);
+        if insert {
+            self.watches.push(watch);
+        }
+        self.update_watches();
+        "OK"
+    }
+
+    /// The reply to a packet, or None to end the session.
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let (command, args) = if packet.is_empty() { ("", "") } else { packet.split_at(1) };
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => {
+                let bytes: Vec<u8> = (0..6)
+                    .filter_map(|n| self.register(n))
+                    .flat_map(|value| vec![value as u8, (value >> 8) as u8])
+                    .collect();
+                to_hex(&bytes)
+            },
+            "G" => match from_hex(args) {
+                Some(ref bytes) if bytes.len() >= 12 => {
+                    for n in 0..6 {
+                        let value = (bytes[2 * n + 1] as u16) << 8 | bytes[2 * n] as u16;
+                        self.set_register(n, value);
+                    }
+                    String::from("OK")
+                },
+                _ => String::from("E01"),
+            },
+            "p" => match usize::from_str_radix(args, 16).ok().and_then(|n| self.register(n)) {
+                Some(value) => to_hex(&[value as u8, (value >> 8) as u8]),
+                None => String::from("E01"),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+                let value = parts.next().and_then(from_hex);
+                match (n, value) {
+                    (Some(n), Some(ref bytes)) if bytes.len() == 2 &&
+                        self.set_register(n, (bytes[1] as u16) << 8 | bytes[0] as u16) => String::from("OK"),
+                    _ => String::from("E01"),
+                }
+            },
+            "m" => match addr_length(args) {
+                // Two hex digits a byte have to fit in the reply
+                Some((addr, length)) if length <= PACKET_SIZE / 2 => {
+                    let bytes: Vec<u8> = (0..length).map(|i| self.machine.read(addr.wrapping_add(i as u16))).collect();
+                    to_hex(&bytes)
+                },
+                _ => String::from("E01"),
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let target = parts.next().and_then(addr_length);
+                let bytes = parts.next().and_then(from_hex);
+                match (target, bytes) {
+                    (Some((addr, length)), Some(bytes)) if bytes.len() == length => {
+                        for (i, &x) in bytes.iter().enumerate() {
+                            self.machine.write(addr.wrapping_add(i as u16), x);
+                        }
+                        String::from("OK")
+                    },
+                    _ => String::from("E01"),
+                }
+            },
+            "Z" => String::from(self.breakpoint(true, args)),
+            "z" => String::from(self.breakpoint(false, args)),
+            "s" => self.step().unwrap_or(format!("S{:02x}", SIGTRAP)),
+            "c" => self.resume()?,
+            "H" | "T" => String::from("OK"),
+            "q" if args == "Attached" => String::from("1"),
+            "q" if args == "C" => String::from("QC1"),
+            "q" if args == "fThreadInfo" => String::from("m1"),
+            "q" if args == "sThreadInfo" => String::from("l"),
+            "q" if args.starts_with("Supported") => format!("PacketSize={:x}", PACKET_SIZE),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            },
+            "k" => return Ok(None),
+            // Anything else isn't supported
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    /// Serve gdb until it detaches, kills the machine or disconnects.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                // Already stopped
+                None => {
+                    self.send(&format!("S{:02x}", SIGINT))?;
+                    continue;
+                },
+            };
+            match self.handle(&packet)? {
+                Some(reply) => self.send(&reply)?,
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_frame() {
+    assert_eq!(frame("OK"), "$OK#9a");
+    assert_eq!(from_hex("00ff1a"), Some(vec![0x00, 0xff, 0x1a]));
+    assert_eq!(from_hex("0"), None);
+    assert_eq!(addr_length("2000,10"), Some((0x2000, 16)));
+}
+
+#[test]
+fn test_session() {
+    use std::io::BufReader;
+    use std::thread;
+
+    use bare::Bare;
+
+    // Loop: INR A; STA 2000h; JMP Loop
+    let program = [0x3c, 0x32, 0x00, 0x20, 0xc3, 0x00, 0x00];
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(Bare::new(&program, 0), stream).run().unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut request = |data: &str| -> String {
+        writer.write_all(frame(data).as_bytes()).unwrap();
+        let mut byte = [0];
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+');
+        let mut reply = Vec::new();
+        loop {
+            reader.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            reply.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        reader.read_exact(&mut checksum).unwrap();
+        writer.write_all(b"+").unwrap();
+        assert_eq!(reply[0], b'$');
+        String::from_utf8(reply[1..].to_vec()).unwrap()
+    };
+
+    assert_eq!(request("qSupported:multiprocess+"), "PacketSize=1000");
+    assert_eq!(request("?"), "S05");
+    assert_eq!(request("s"), "S05");
+    assert_eq!(request("g"), "020100000000000000000100");
+    assert_eq!(request("Z0,4,1"), "OK");
+    assert_eq!(request("c"), "S05");
+    assert_eq!(request("p5"), "0400");
+    assert_eq!(request("m2000,2"), "0100");
+    assert_eq!(request("m0,800").len(), 0x1000);
+    assert_eq!(request("m0,801"), "E01");
+    assert_eq!(request("m0,ffffffff"), "E01");
+    assert_eq!(request("z0,4,1"), "OK");
+    assert_eq!(request("Z2,2000,1"), "OK");
+    assert_eq!(request("c"), "T05watch:2000;");
+    assert_eq!(request("m2000,1"), "02");
+    assert_eq!(request("M2000,2:abcd"), "OK");
+    assert_eq!(request("m2000,2"), "abcd");
+    assert_eq!(request("P0=ff12"), "OK");
+    assert_eq!(request("p0"), "d712");
+    assert_eq!(request("vMustReplyEmpty"), "");
+    assert_eq!(request("D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn test_disconnect_while_running() {
+    use std::thread;
+
+    use bare::Bare;
+
+    // Loop: JMP Loop
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(Bare::new(&[0xc3, 0x00, 0x00], 0), stream).run()
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(frame("c").as_bytes()).unwrap();
+    let mut ack = [0];
+    stream.read_exact(&mut ack).unwrap();
+    assert_eq!(ack[0], b'+');
+    drop(stream);
+    let error = server.join().unwrap().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+}
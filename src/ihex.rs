@@ -0,0 +1,134 @@
+/// Data bytes in each record written.
+const RECORD_SIZE: usize = 16;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// One record, `:LLAAAATT<data>CC`, where the checksum
+/// makes all the bytes of the record sum to zero.
+fn record(addr: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, (addr & 0xff) as u8, kind];
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, x| sum.wrapping_add(*x));
+    bytes.push(0u8.wrapping_sub(sum));
+    let hex: Vec<String> = bytes.iter().map(|x| format!("{:02X}", x)).collect();
+    format!(":{}\n", hex.concat())
+}
+
+/// Write runs of bytes, each with the address
+/// it starts at, in Intel HEX format.
+pub fn write(chunks: &[(u16, Vec<u8>)]) -> String {
+    let mut hex = String::new();
+    for &(start, ref bytes) in chunks.iter() {
+        for (i, data) in bytes.chunks(RECORD_SIZE).enumerate() {
+            let addr = start.wrapping_add((i * RECORD_SIZE) as u16);
+            hex.push_str(&record(addr, DATA, data));
+        }
+    }
+    hex.push_str(&record(0, END_OF_FILE, &[]));
+    hex
+}
+
+/// Decode the bytes of one record, checking its length and checksum.
+fn decode_record(line: &str) -> Result<Vec<u8>, String> {
+    if !line.starts_with(':') {
+        return Err(String::from("record doesn't start with ':'"));
+    }
+    let digits = line[1..].as_bytes();
+    if digits.len() % 2 != 0 {
+        return Err(String::from("odd number of hex digits"));
+    }
+    let mut bytes = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.chunks(2) {
+        let byte = ::std::str::from_utf8(pair).ok()
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok());
+        match byte {
+            Some(byte) => bytes.push(byte),
+            None => return Err(String::from("invalid hex digit")),
+        }
+    }
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err(String::from("record length doesn't match its byte count"));
+    }
+    if bytes.iter().fold(0u8, |sum, x| sum.wrapping_add(*x)) != 0 {
+        return Err(String::from("bad checksum"));
+    }
+    Ok(bytes)
+}
+
+/// Read Intel HEX into runs of bytes, each with the address it
+/// starts at. Consecutive records are joined into one run.
+///
+/// Extended address records are followed as long as the data
+/// stays within the 8080's 64K, start address records are ignored.
+pub fn parse(text: &str) -> Result<Vec<(u16, Vec<u8>)>, String> {
+    let mut chunks: Vec<(u16, Vec<u8>)> = Vec::new();
+    let mut base = 0;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bytes = decode_record(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        let data = &bytes[4..bytes.len() - 1];
+        let offset = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        match bytes[3] {
+            DATA => {
+                let addr = base + offset;
+                if addr + data.len() > 0x10000 {
+                    return Err(format!("line {}: data beyond 64K at {:x}", n + 1, addr));
+                }
+                let addr = addr as u16;
+                let joined = match chunks.last_mut() {
+                    Some(&mut (start, ref mut bytes)) if start as usize + bytes.len() == addr as usize => {
+                        bytes.extend_from_slice(data);
+                        true
+                    },
+                    _ => false,
+                };
+                if !joined && !data.is_empty() {
+                    chunks.push((addr, data.to_vec()));
+                }
+            },
+            END_OF_FILE => return Ok(chunks),
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                let upper = (data[0] as usize) << 8 | data[1] as usize;
+                base = if bytes[3] == EXTENDED_SEGMENT_ADDRESS { upper << 4 } else { upper << 16 };
+            },
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {},
+            kind => return Err(format!("line {}: unknown record type {:02x}", n + 1, kind)),
+        }
+    }
+    Err(String::from("missing end of file record"))
+}
+
+#[test]
+fn test_write() {
+    let bytes: Vec<u8> = (0..20).collect();
+    let hex = write(&[(0x0100, bytes), (0x2000, vec![0xc9])]);
+    assert_eq!(hex, "\
+:10010000000102030405060708090A0B0C0D0E0F77
+:0401100010111213A5
+:01200000C916
+:00000001FF
+");
+}
+
+#[test]
+fn test_parse() {
+    let bytes: Vec<u8> = (0..20).collect();
+    let chunks = vec![(0x0100, bytes), (0x2000, vec![0xc9])];
+    assert_eq!(parse(&write(&chunks)), Ok(chunks));
+
+    // Segment 0x0f00 starts at 0xf000, 0x1000 is beyond 64K
+    assert_eq!(parse(":020000020F00ED\n:010FFF0001F0\n:00000001FF\n"), Ok(vec![(0xffff, vec![0x01])]));
+    assert!(parse(":020000021000EC\n:01001000C926\n:00000001FF\n").is_err());
+
+    assert_eq!(parse(":01200000C917\n:00000001FF\n"), Err(String::from("line 1: bad checksum")));
+    assert!(parse(":01200000C916\n").is_err());
+    assert!(parse("01200000C916\n:00000001FF\n").is_err());
+}
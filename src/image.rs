@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use ihex;
+
+/// Is `path` an Intel HEX file, going by its extension?
+pub fn is_hex(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("hex") || ext.eq_ignore_ascii_case("ihx"),
+        None => false,
+    }
+}
+
+/// Runs of bytes as one flat binary, with any gaps
+/// zero filled, and the address it starts at.
+pub fn flatten(chunks: &[(u16, Vec<u8>)]) -> (u16, Vec<u8>) {
+    let start = chunks.iter().map(|&(addr, _)| addr as usize).min().unwrap_or(0);
+    let end = chunks.iter()
+        .map(|&(addr, ref bytes)| addr as usize + bytes.len())
+        .max()
+        .unwrap_or(0);
+    let mut binary = vec![0; end - start];
+    for &(addr, ref bytes) in chunks.iter() {
+        let offset = addr as usize - start;
+        binary[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+    (start as u16, binary)
+}
+
+/// A file to load into memory, given on the command
+/// line as `FILE@ADDR` with the address in hex.
+///
+/// Intel HEX files carry their own addresses so
+/// they're given without one, raw binaries need it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Load {
+    pub path: PathBuf,
+    pub addr: Option<u16>,
+}
+
+impl Load {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        // Paths can have an @ in them too, it only
+        // splits off an address when one follows it
+        let split = spec.rfind('@').and_then(|i| {
+            let digits = &spec[i + 1..];
+            let digits = if digits.starts_with("0x") { &digits[2..] } else { digits };
+            u16::from_str_radix(digits, 16).ok().map(|addr| (&spec[..i], Some(addr)))
+        });
+        let (path, addr) = split.unwrap_or((spec, None));
+        let path = PathBuf::from(path);
+        match (is_hex(&path), addr) {
+            (true, Some(_)) => Err(format!("{}: Intel HEX files are loaded at their own addresses", spec)),
+            (false, None) => Err(format!("{}: raw binaries need an address, FILE@ADDR", spec)),
+            _ => Ok(Load { path: path, addr: addr }),
+        }
+    }
+
+    /// Read the file into runs of bytes,
+    /// each with the address it starts at.
+    pub fn read(&self) -> Result<Vec<(u16, Vec<u8>)>, String> {
+        let error = |e: ::std::io::Error| format!("{}: {}", self.path.display(), e);
+        let mut file = File::open(&self.path).map_err(&error)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).map_err(&error)?;
+        match self.addr {
+            Some(addr) => {
+                if addr as usize + buf.len() > 0x10000 {
+                    return Err(format!("{}: doesn't fit in memory at {:04x}", self.path.display(), addr));
+                }
+                Ok(vec![(addr, buf)])
+            },
+            None => {
+                let text = String::from_utf8_lossy(&buf);
+                ihex::parse(&text).map_err(|e| format!("{}: {}", self.path.display(), e))
+            },
+        }
+    }
+}
+
+#[test]
+fn test_parse_load() {
+    assert_eq!(Load::parse("monitor.rom@f000"),
+               Ok(Load { path: PathBuf::from("monitor.rom"), addr: Some(0xf000) }));
+    assert_eq!(Load::parse("test.hex"), Ok(Load { path: PathBuf::from("test.hex"), addr: None }));
+    assert_eq!(Load::parse("user@host/test.IHX"), Ok(Load { path: PathBuf::from("user@host/test.IHX"), addr: None }));
+    assert!(Load::parse("monitor.rom").is_err());
+    assert!(Load::parse("monitor.rom@zz").is_err());
+    assert!(Load::parse("test.hex@0100").is_err());
+}
+
+#[test]
+fn test_flatten() {
+    assert_eq!(flatten(&[(0x0102, vec![3]), (0x0100, vec![1])]), (0x0100, vec![1, 0, 3]));
+    assert_eq!(flatten(&[]), (0, vec![]));
+}
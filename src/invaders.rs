@@ -0,0 +1,712 @@
+use std::thread;
+use time;
+
+use cpu::{ConditionCodes, Cpu, Registers};
+use machine::Machine;
+use memory::{Access, Memory};
+use script::Script;
+
+/// Cycles of the 2MHz CPU in each 60Hz frame.
+pub const CYCLES_PER_FRAME: u32 = 2_000_000 / 60;
+
+/// When the beam is half way down the screen, at
+/// scanline 96, and the video hardware makes RST 1.
+/// It makes RST 2 at the end of the frame as vblank
+/// starts at scanline 224.
+const MID_SCREEN: u32 = CYCLES_PER_FRAME / 2;
+
+/// Nanoseconds of real time each frame takes.
+const FRAME_NS: u64 = 1_000_000_000 / 60;
+
+/// The screen as it's seen, rotated upright.
+pub const WIDTH: u32 = 224;
+pub const HEIGHT: u32 = 256;
+
+/// Start of the 1bpp bitmap, 32 bytes per scanline.
+const VIDEO_RAM: u16 = 0x2400;
+
+/// The samples the sound hardware plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDie,
+    InvaderDie,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundEvent {
+    Play(Sound),
+    /// Only the UFO's sound is stopped, it loops
+    /// for as long as the UFO is on screen.
+    Stop(Sound),
+}
+
+/// The cabinet's buttons and switches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Coin,
+    P1Start,
+    P1Shoot,
+    P1Left,
+    P1Right,
+    P2Start,
+    P2Shoot,
+    P2Left,
+    P2Right,
+    Tilt,
+}
+
+impl Button {
+    /// The input port and bit the button is read from.
+    pub fn port_bit(self) -> (usize, u8) {
+        match self {
+            Button::Coin => (1, 0x01),
+            Button::P2Start => (1, 0x02),
+            Button::P1Start => (1, 0x04),
+            Button::P1Shoot => (1, 0x10),
+            Button::P1Left => (1, 0x20),
+            Button::P1Right => (1, 0x40),
+            Button::Tilt => (2, 0x04),
+            Button::P2Shoot => (2, 0x10),
+            Button::P2Left => (2, 0x20),
+            Button::P2Right => (2, 0x40),
+        }
+    }
+}
+
+/// The state of the hardware, other than memory,
+/// that stepping the CPU can change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    registers: Registers,
+    ports: [u8; 8],
+    cycles: u32,
+    frames: u64,
+    frame: bool,
+    shift0: u8,
+    shift1: u8,
+    shift_offset: u8,
+    last_out_port3: u8,
+    last_out_port5: u8,
+    script: usize, // Script changes made
+}
+
+/// The Space Invaders hardware on its own: the CPU,
+/// shift register, ports, interrupts and video RAM,
+/// with nothing to show or play it through.
+///
+/// See `SpaceInvaders` for the memory map.
+pub struct SpaceInvadersCore {
+    pub cpu: Cpu,
+    cycles: u32, // Cycles run in the current frame
+    frames: u64, // Frames run since the machine started
+    frame: bool, // Has a frame finished since last asked?
+    shift0: u8,
+    shift1: u8,
+    shift_offset: u8,
+    last_out_port3: u8,
+    last_out_port5: u8,
+    sounds: Vec<SoundEvent>,
+}
+
+impl SpaceInvadersCore {
+    pub fn new(data: &[u8]) -> Self {
+        SpaceInvadersCore {
+            cpu: Cpu::new(Memory::with_rom(data)),
+            cycles: 0,
+            frames: 0,
+            frame: false,
+            shift0: 0,
+            shift1: 0,
+            shift_offset: 0,
+            last_out_port3: 0,
+            last_out_port5: 0,
+            sounds: Vec::new(),
+        }
+    }
+
+    /// Step through a single instruction, interrupting
+    /// as the video hardware does. Returns the number of
+    /// clock cycles the instruction took.
+    ///
+    /// Interrupts come from counting cycles rather than
+    /// real time, so every run is the same. One that
+    /// comes while interrupts are disabled is lost.
+    #[inline(always)]
+    pub fn step(&mut self) -> u32 {
+        let cycles = self.execute();
+
+        let before = self.cycles;
+        self.cycles += cycles;
+        if before < MID_SCREEN && self.cycles >= MID_SCREEN {
+            self.cpu.interrupt(1);
+        }
+        if self.cycles >= CYCLES_PER_FRAME {
+            // VBlank interrupt
+            self.cycles -= CYCLES_PER_FRAME;
+            self.frames += 1;
+            self.frame = true;
+            self.cpu.interrupt(2);
+        }
+        cycles
+    }
+
+    /// Frames run since the machine started.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Has a frame finished since last asked?
+    pub fn take_frame(&mut self) -> bool {
+        let frame = self.frame;
+        self.frame = false;
+        frame
+    }
+
+    /// Is the pixel lit, counting from the
+    /// top left of the upright screen?
+    #[inline(always)]
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        // Screen is 256x224 pixels but the screen is rotated
+        // 90 degrees counter-clockwise in the machine
+        // so the visible screen is actually 224x256 pixels.
+        // http://computerarcheology.com/Arcade/SpaceInvaders/Hardware.html
+        let y = HEIGHT - 1 - y;
+        let offset = (x * (HEIGHT / 8)) + y / 8;
+        let byte = self.cpu.mem.read(VIDEO_RAM + offset as u16);
+        byte & (1 << (y % 8)) != 0
+    }
+
+    /// The upright screen as a binary PPM image,
+    /// white on black.
+    pub fn screenshot(&self) -> Vec<u8> {
+        let mut image = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let value = if self.pixel(x, y) { 0xff } else { 0x00 };
+                image.extend_from_slice(&[value, value, value]);
+            }
+        }
+        image
+    }
+
+    /// Sounds started and stopped since last asked.
+    pub fn take_sounds(&mut self) -> Vec<SoundEvent> {
+        self.sounds.drain(..).collect()
+    }
+
+    /// Press or release a button.
+    pub fn press(&mut self, button: Button, down: bool) {
+        let (port, bit) = button.port_bit();
+        self.set_input_bits(port, bit, down);
+    }
+
+    /// Set or clear bits of an input port.
+    pub fn set_input_bits(&mut self, port: usize, bits: u8, down: bool) {
+        if down {
+            self.cpu.ports[port] |= bits;
+        } else {
+            self.cpu.ports[port] &= !bits;
+        }
+    }
+
+    /// The buttons and switches read from an input port.
+    pub fn input(&self, port: usize) -> u8 {
+        self.cpu.ports[port]
+    }
+
+    #[inline(always)]
+    fn handle_sound(&mut self, port: u8, value: u8) {
+        let (last, sounds) = match port {
+            3 => (self.last_out_port3, [Some(Sound::Ufo), Some(Sound::Shot), Some(Sound::PlayerDie),
+                                        Some(Sound::InvaderDie), None]),
+            5 => (self.last_out_port5, [Some(Sound::Fleet1), Some(Sound::Fleet2), Some(Sound::Fleet3),
+                                        Some(Sound::Fleet4), Some(Sound::UfoHit)]),
+            _ => panic!("Not a valid sound port: {}", port)
+        };
+        for (i, sound) in sounds.iter().enumerate() {
+            let bit = 1 << i;
+            match *sound {
+                // Played as the bit is set
+                Some(sound) if value & bit != 0 && last & bit == 0 => self.sounds.push(SoundEvent::Play(sound)),
+                Some(Sound::Ufo) if value & bit == 0 && last & bit != 0 => self.sounds.push(SoundEvent::Stop(Sound::Ufo)),
+                _ => {},
+            }
+        }
+        if port == 3 {
+            self.last_out_port3 = value;
+        } else {
+            self.last_out_port5 = value;
+        }
+    }
+
+    /// Execute a single instruction, handling
+    /// the IN and OUT ports of the cabinet.
+    #[inline(always)]
+    fn execute(&mut self) -> u32 {
+        if self.cpu.halted {
+            return self.cpu.step();
+        }
+        let op = self.cpu.mem.read(self.cpu.pc);
+        match op {
+            0xd3 => { // OUT D8
+                let port = self.cpu.mem.read(self.cpu.pc + 1);
+                let value = self.cpu.a;
+                match port {
+                    2 => self.shift_offset = value & 0x7,
+                    3 | 5 => self.handle_sound(port, value),
+                    4 => {
+                        self.shift0 = self.shift1;
+                        self.shift1 = value;
+                    },
+                    _ => {}
+                }
+                self.cpu.pc += 2;
+                10
+            },
+            0xdb => { // IN D8
+                let port = self.cpu.mem.read(self.cpu.pc + 1);
+                self.cpu.a = match port {
+                    0 => 1,
+                    1 => self.cpu.ports[1],
+                    3 => {
+                        let value = (self.shift1 as u16) << 8 | self.shift0 as u16;
+                        ((value >> (8 - self.shift_offset)) & 0xff) as u8
+                    },
+                    _ => self.cpu.ports[port as usize],
+                };
+                self.cpu.pc += 2;
+                10
+            },
+            _ => self.cpu.step()
+        }
+    }
+}
+
+/// What the game is shown, heard and played through.
+pub trait Frontend {
+    /// Show the screen, play any sounds and read the
+    /// buttons. Called once a frame and while the
+    /// machine is paused.
+    fn idle(&mut self, core: &mut SpaceInvadersCore);
+
+    /// Has the player quit, such as by closing the window?
+    fn closed(&self) -> bool;
+}
+
+/// No screen, sound or buttons, for tests and
+/// running without a display.
+pub struct Headless;
+
+impl Frontend for Headless {
+    fn idle(&mut self, core: &mut SpaceInvadersCore) {
+        core.take_sounds();
+    }
+
+    fn closed(&self) -> bool {
+        false
+    }
+}
+
+/// Space Invaders, (C) Taito 1978, Midway 1979
+///
+/// CPU: Intel 8080 @ 2MHz (CPU similar to the (newer) Zilog Z80)
+///
+/// Interrupts: $cf (RST 1) at mid-screen (scanline 96), $d7 (RST 2) at the start of vblank (scanline 224).
+///
+/// Video: 256(x)*224(y) @ 60Hz, vertical monitor. Colours are simulated with a
+/// plastic transparent overlay and a background picture.
+/// Video hardware is very simple: 7168 bytes 1bpp bitmap (32 bytes per scanline).
+///
+/// Sound: SN76477 and samples.
+///
+/// Memory map:
+/// ROM
+/// $0000-$07ff:    invaders.h
+/// $0800-$0fff:    invaders.g
+/// $1000-$17ff:    invaders.f
+/// $1800-$1fff:    invaders.e
+///
+/// RAM
+/// $2000-$23ff:    work RAM
+/// $2400-$3fff:    video RAM
+///
+/// $4000-:     RAM mirror
+pub struct SpaceInvaders<F: Frontend> {
+    pub core: SpaceInvadersCore,
+    pub frontend: F,
+    script: Script,
+    /// Input changes made since recording started
+    recording: Option<Script>,
+    /// Ports 1 and 2 as last recorded
+    inputs: [u8; 2],
+    /// Is the script a replay, which the frontend's
+    /// buttons mustn't change?
+    replaying: bool,
+}
+
+impl<F: Frontend> SpaceInvaders<F> {
+    pub fn new(data: &[u8], frontend: F) -> Self {
+        SpaceInvaders {
+            core: SpaceInvadersCore::new(data),
+            frontend: frontend,
+            script: Script::new(),
+            recording: None,
+            inputs: [0; 2],
+            replaying: false,
+        }
+    }
+
+    /// Press and release buttons as the script says,
+    /// alongside any the frontend reads.
+    pub fn set_script(&mut self, script: Script) {
+        self.script = script;
+        self.script.play(&mut self.core);
+    }
+
+    /// Play back a recording. The script alone presses
+    /// and releases buttons, any the frontend reads are
+    /// ignored so the game runs exactly as it was recorded.
+    pub fn replay(&mut self, script: Script) {
+        self.replaying = true;
+        self.set_script(script);
+    }
+
+    /// Record every change to the inputs from now on, from
+    /// the frontend and any script, to be played back later.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Script::new());
+        self.inputs = [0; 2];
+        self.record();
+    }
+
+    /// Stop recording and give the changes recorded.
+    pub fn take_recording(&mut self) -> Option<Script> {
+        self.recording.take()
+    }
+
+    fn record(&mut self) {
+        if let Some(ref mut recording) = self.recording {
+            for port in 1..3 {
+                let input = self.core.input(port);
+                if input != self.inputs[port - 1] {
+                    recording.record(self.core.frames(), port, self.inputs[port - 1], input);
+                    self.inputs[port - 1] = input;
+                }
+            }
+        }
+    }
+
+    /// Step until the end of the current frame,
+    /// when the frontend is shown it.
+    pub fn run_frame(&mut self) {
+        let frames = self.core.frames();
+        while self.core.frames() == frames {
+            self.step();
+        }
+    }
+
+    /// Run a frame at a time, waiting after each so the
+    /// game runs at 60 frames a second, until closed.
+    pub fn run(&mut self) {
+        let mut next_frame_time = time::precise_time_ns();
+        while !self.stopped() {
+            self.run_frame();
+
+            next_frame_time += FRAME_NS;
+            let current_real_time = time::precise_time_ns();
+            if next_frame_time > current_real_time {
+                thread::sleep(::std::time::Duration::new(0, (next_frame_time - current_real_time) as u32));
+            } else {
+                // Too far behind to catch up
+                next_frame_time = current_real_time;
+            }
+        }
+    }
+}
+
+impl<F: Frontend> Machine for SpaceInvaders<F> {
+    /// Step through a single instruction.
+    /// Returns the number of clock cycles
+    /// required for the instruction processed.
+    #[inline(always)]
+    fn step(&mut self) -> u32 {
+        let cycles = self.core.step();
+        if self.core.take_frame() {
+            self.script.play(&mut self.core);
+            // Draw and poll events once a frame
+            self.idle();
+            self.record();
+        }
+        cycles
+    }
+
+    #[inline(always)]
+    fn interrupt(&mut self, int: usize) {
+        // TODO: interrupt code should be an enum?
+        // This is identical to an `RST int` instruction
+        self.core.cpu.interrupt(int as u8);
+    }
+
+    #[inline(always)]
+    fn get_pc(&self) -> u16 {
+        self.core.cpu.pc
+    }
+
+    #[inline(always)]
+    fn set_pc(&mut self, value: u16) { self.core.cpu.pc = value; }
+
+    #[inline(always)]
+    fn get_sp(&self) -> u16 { self.core.cpu.sp }
+    #[inline(always)]
+    fn set_sp(&mut self, value: u16) { self.core.cpu.sp = value; }
+
+    #[inline(always)]
+    fn get_flags(&self) -> u8 { self.core.cpu.cc.to_byte() }
+    #[inline(always)]
+    fn set_flags(&mut self, value: u8) { self.core.cpu.cc = ConditionCodes::from_byte(value); }
+
+    #[inline(always)]
+    fn get_interrupt_enabled(&self) -> bool { self.core.cpu.interrupt_enabled }
+
+    #[inline(always)]
+    fn get_a(&self) -> u8 { self.core.cpu.a }
+    #[inline(always)]
+    fn set_a(&mut self, value: u8) { self.core.cpu.a = value; }
+
+    #[inline(always)]
+    fn get_b(&self) -> u8 { self.core.cpu.b }
+    #[inline(always)]
+    fn set_b(&mut self, value: u8) { self.core.cpu.b = value; }
+
+    #[inline(always)]
+    fn get_c(&self) -> u8 { self.core.cpu.c }
+    #[inline(always)]
+    fn set_c(&mut self, value: u8) { self.core.cpu.c = value; }
+
+    #[inline(always)]
+    fn get_d(&self) -> u8 { self.core.cpu.d }
+    #[inline(always)]
+    fn set_d(&mut self, value: u8) { self.core.cpu.d = value; }
+
+    #[inline(always)]
+    fn get_e(&self) -> u8 { self.core.cpu.e }
+    #[inline(always)]
+    fn set_e(&mut self, value: u8) { self.core.cpu.e = value; }
+
+    #[inline(always)]
+    fn get_h(&self) -> u8 { self.core.cpu.h }
+    #[inline(always)]
+    fn set_h(&mut self, value: u8) { self.core.cpu.h = value; }
+
+    #[inline(always)]
+    fn get_l(&self) -> u8 { self.core.cpu.l }
+    #[inline(always)]
+    fn set_l(&mut self, value: u8) { self.core.cpu.l = value; }
+
+    #[inline(always)]
+    fn read(&self, addr: u16) -> u8 {
+        self.core.cpu.mem.read(addr)
+    }
+
+    #[inline(always)]
+    fn write(&mut self, addr: u16, value: u8) {
+        self.core.cpu.mem.load(addr, &[value]);
+    }
+
+    fn set_watches(&mut self, addrs: &[u16]) {
+        self.core.cpu.mem.set_watches(addrs);
+    }
+
+    fn take_accesses(&mut self) -> Vec<(u16, Access)> {
+        self.core.cpu.mem.take_accesses()
+    }
+
+    fn registers(&self) -> Registers {
+        self.core.cpu.registers()
+    }
+
+    fn set_registers(&mut self, registers: &Registers) {
+        self.core.cpu.set_registers(registers);
+    }
+
+    type Snapshot = Snapshot;
+
+    fn snapshot(&self) -> Snapshot {
+        let core = &self.core;
+        Snapshot {
+            registers: core.cpu.registers(),
+            ports: core.cpu.ports,
+            cycles: core.cycles,
+            frames: core.frames,
+            frame: core.frame,
+            shift0: core.shift0,
+            shift1: core.shift1,
+            shift_offset: core.shift_offset,
+            last_out_port3: core.last_out_port3,
+            last_out_port5: core.last_out_port5,
+            script: self.script.played(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        let core = &mut self.core;
+        core.cpu.set_registers(&snapshot.registers);
+        core.cpu.ports = snapshot.ports;
+        core.cycles = snapshot.cycles;
+        core.frames = snapshot.frames;
+        core.frame = snapshot.frame;
+        core.shift0 = snapshot.shift0;
+        core.shift1 = snapshot.shift1;
+        core.shift_offset = snapshot.shift_offset;
+        core.last_out_port3 = snapshot.last_out_port3;
+        core.last_out_port5 = snapshot.last_out_port5;
+        self.script.set_played(snapshot.script);
+    }
+
+    fn set_journal(&mut self, on: bool) {
+        self.core.cpu.mem.set_journal(on);
+    }
+
+    fn take_journal(&mut self) -> Vec<(u16, u8)> {
+        self.core.cpu.mem.take_journal()
+    }
+
+    fn idle(&mut self) {
+        let inputs = [self.core.input(1), self.core.input(2)];
+        self.frontend.idle(&mut self.core);
+        if self.replaying {
+            for port in 1..3 {
+                self.core.set_input_bits(port, 0xff, false);
+                self.core.set_input_bits(port, inputs[port - 1], true);
+            }
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.frontend.closed()
+    }
+}
+
+#[test]
+fn test_ports() {
+    // MVI A,12h; OUT 4; MVI A,34h; OUT 4; MVI A,4; OUT 2; IN 3; MOV B,A
+    // MVI A,3; OUT 3; MVI A,2; OUT 3; IN 1; HLT
+    let rom = [0x3e, 0x12, 0xd3, 0x04, 0x3e, 0x34, 0xd3, 0x04, 0x3e, 0x04, 0xd3, 0x02, 0xdb, 0x03, 0x47,
+               0x3e, 0x03, 0xd3, 0x03, 0x3e, 0x02, 0xd3, 0x03, 0xdb, 0x01, 0x76];
+    let mut core = SpaceInvadersCore::new(&rom);
+    core.press(Button::Coin, true);
+    core.press(Button::P1Left, true);
+    core.press(Button::P1Left, false);
+    core.press(Button::P2Shoot, true);
+    assert_eq!(core.input(1), 0x01);
+    assert_eq!(core.input(2), 0x10);
+    while !core.cpu.halted {
+        core.step();
+    }
+    // 0x3412 shifted left by 4, high byte
+    assert_eq!(core.cpu.b, 0x41);
+    assert_eq!(core.take_sounds(), vec![SoundEvent::Play(Sound::Ufo), SoundEvent::Play(Sound::Shot),
+                                        SoundEvent::Stop(Sound::Ufo)]);
+    assert!(core.take_sounds().is_empty());
+    assert_eq!(core.cpu.a, 0x01);
+}
+
+#[test]
+fn test_pixel() {
+    let mut core = SpaceInvadersCore::new(&[]);
+    // The bottom left pixel, then the top right
+    core.cpu.mem.write(0x2400, 0x01);
+    core.cpu.mem.write(0x3fff, 0x80);
+    assert!(core.pixel(0, HEIGHT - 1));
+    assert!(core.pixel(WIDTH - 1, 0));
+    assert!(!core.pixel(0, 0));
+    assert!(!core.pixel(1, HEIGHT - 1));
+
+    let image = core.screenshot();
+    let header = b"P6\n224 256\n255\n";
+    assert_eq!(&image[..header.len()], &header[..]);
+    assert_eq!(image.len(), header.len() + 3 * (WIDTH * HEIGHT) as usize);
+    // Top right, then bottom left
+    let pixel = |x: u32, y: u32| image[header.len() + 3 * (y * WIDTH + x) as usize];
+    assert_eq!(pixel(WIDTH - 1, 0), 0xff);
+    assert_eq!(pixel(0, HEIGHT - 1), 0xff);
+    assert_eq!(pixel(0, 0), 0x00);
+}
+
+#[test]
+fn test_interrupts() {
+    // EI; loop: JMP loop, then at RST 1 and RST 2: EI; RET
+    let mut rom = vec![0; 0x20];
+    rom[0..4].copy_from_slice(&[0xfb, 0xc3, 0x01, 0x00]);
+    rom[0x08..0x0a].copy_from_slice(&[0xfb, 0xc9]);
+    rom[0x10..0x12].copy_from_slice(&[0xfb, 0xc9]);
+    let mut core = SpaceInvadersCore::new(&rom);
+    core.cpu.sp = 0x2400;
+    let mut interrupts = Vec::new();
+    let mut cycles = 0u64;
+    while core.frames() < 2 {
+        cycles += core.step() as u64;
+        if core.cpu.pc == 0x08 || core.cpu.pc == 0x10 {
+            interrupts.push((core.cpu.pc, cycles));
+        }
+    }
+    assert!(core.take_frame());
+    assert!(!core.take_frame());
+    // Each comes on the first instruction boundary at or after its cycle
+    let expected = [(0x08, MID_SCREEN), (0x10, CYCLES_PER_FRAME),
+                    (0x08, CYCLES_PER_FRAME + MID_SCREEN), (0x10, 2 * CYCLES_PER_FRAME)];
+    assert_eq!(interrupts.len(), expected.len());
+    for (&(pc, at), &(expected_pc, expected_at)) in interrupts.iter().zip(expected.iter()) {
+        assert_eq!(pc, expected_pc);
+        assert!(at >= expected_at as u64 && at < expected_at as u64 + 10);
+    }
+}
+
+#[test]
+fn test_replay() {
+    // Loop: IN 1; MOV B,A; LDA 2000h; ADD B; STA 2000h; JMP Loop
+    let rom = [0xdb, 0x01, 0x47, 0x3a, 0x00, 0x20, 0x80, 0x32, 0x00, 0x20, 0xc3, 0x00, 0x00];
+    let mut machine = SpaceInvaders::new(&rom, Headless);
+    machine.set_script(Script::parse("frame 0: coin down; frame 3: coin up, left down; frame 7: left up").unwrap());
+    machine.start_recording();
+    for _ in 0..10 {
+        machine.run_frame();
+    }
+    let recording = machine.take_recording().unwrap();
+    assert_eq!(format!("{}", recording), "frame 0: coin down\n\
+                                          frame 3: coin up\n\
+                                          frame 3: left down\n\
+                                          frame 7: left up\n");
+
+    // A player mashing every button doesn't change the replay
+    struct Mashing;
+
+    impl Frontend for Mashing {
+        fn idle(&mut self, core: &mut SpaceInvadersCore) {
+            core.set_input_bits(1, 0xff, true);
+            core.set_input_bits(2, 0xff, true);
+        }
+
+        fn closed(&self) -> bool {
+            false
+        }
+    }
+
+    let mut replay = SpaceInvaders::new(&rom, Mashing);
+    replay.replay(recording);
+    for _ in 0..10 {
+        replay.run_frame();
+        replay.idle();
+    }
+    assert_eq!(replay.core.frames(), 10);
+    assert_eq!(replay.core.cpu.registers(), machine.core.cpu.registers());
+    assert!((0x2000..0x4000).all(|addr| replay.read(addr) == machine.read(addr)));
+    assert!(machine.read(0x2000) != 0);
+}
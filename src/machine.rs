@@ -1,16 +1,53 @@
+use std::fmt;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+use cpu::Registers;
+use memory::Access;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum RegPair {
     BC,
     DE,
     HL,
+    SP,
+    /// A and the flags, only used by PUSH and POP.
+    PSW,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Reg {
     A, B, C, D, E, H, L
 }
 
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reg::A => "A",
+            Reg::B => "B",
+            Reg::C => "C",
+            Reg::D => "D",
+            Reg::E => "E",
+            Reg::H => "H",
+            Reg::L => "L",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Register pairs are named after their first
+/// register, as in 8080 assembly language.
+impl fmt::Display for RegPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            RegPair::BC => "B",
+            RegPair::DE => "D",
+            RegPair::HL => "H",
+            RegPair::SP => "SP",
+            RegPair::PSW => "PSW",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 /// A trait representing the abstract function of
 /// a machine running an 8080 processor.
 pub trait Machine {
@@ -21,6 +58,17 @@ pub trait Machine {
     fn interrupt(&mut self, code: usize);
 
     fn get_pc(&self) -> u16;
+    fn set_pc(&mut self, value: u16);
+
+    fn get_sp(&self) -> u16;
+    fn set_sp(&mut self, value: u16);
+
+    /// The flags as the byte `PUSH PSW` stores.
+    fn get_flags(&self) -> u8;
+    /// Set the flags from a byte as `POP PSW` loads them.
+    fn set_flags(&mut self, value: u8);
+
+    fn get_interrupt_enabled(&self) -> bool;
 
     fn get_a(&self) -> u8;
     fn set_a(&mut self, value: u8);
@@ -45,6 +93,36 @@ pub trait Machine {
 
     /// Read a byte from memory.
     fn read(&self, addr: u16) -> u8;
-    /// Write a byte to memory.
+    /// Write a byte to memory, even to ROM.
     fn write(&mut self, addr: u16, value: u8);
+
+    /// Watch these addresses for the CPU using them.
+    fn set_watches(&mut self, addrs: &[u16]);
+    /// Accesses to watched addresses since last asked.
+    fn take_accesses(&mut self) -> Vec<(u16, Access)>;
+
+    /// The CPU's registers, flags and interrupt state.
+    fn registers(&self) -> Registers;
+    fn set_registers(&mut self, registers: &Registers);
+
+    /// Everything other than memory a step can change:
+    /// the CPU's registers and any other hardware state.
+    type Snapshot;
+    /// Take a snapshot to undo steps with.
+    fn snapshot(&self) -> Self::Snapshot;
+    fn restore(&mut self, snapshot: &Self::Snapshot);
+
+    /// Keep the old values of bytes written, or stop.
+    fn set_journal(&mut self, on: bool);
+    /// Bytes written since last asked with their old values.
+    fn take_journal(&mut self) -> Vec<(u16, u8)>;
+
+    /// Called while the machine is paused, such as at
+    /// the debugger's prompt, so machines with a window
+    /// can keep it drawn and responding.
+    fn idle(&mut self) {}
+
+    /// Has the machine finished running, such as a CP/M
+    /// program exiting or the game window being closed?
+    fn stopped(&self) -> bool { false }
 }
\ No newline at end of file
@@ -1,49 +1,111 @@
 extern crate clap;
+#[cfg(feature = "window")]
 extern crate ears;
+#[cfg(feature = "window")]
 #[macro_use]
 extern crate glium;
 #[macro_use]
 extern crate nom;
 extern crate time;
 
+mod assemble;
+mod bare;
+mod bdos;
+mod bios;
+mod breakpoint;
+mod console;
 mod cpm;
 mod cpu;
 mod debug;
+mod disk;
 mod disassemble;
+mod gdb;
+mod ihex;
+mod image;
+mod invaders;
 mod machine;
 mod memory;
+mod movie;
+mod script;
+#[cfg(feature = "window")]
 mod spaceinvaders;
+mod symbols;
 
+use std::fs;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::process;
 use std::u8;
 
 use clap::{Arg, App, SubCommand};
+#[cfg(feature = "window")]
 use ears::{Sound, AudioController};
 
+use bare::Bare;
 use cpu::Cpu;
-use disassemble::disassemble;
-use spaceinvaders::SpaceInvadersMachine;
+use debug::Debugger;
+use disassemble::{default_entries, disassemble, disassemble_from, Listing};
+use disk::Disk;
+use image::Load;
+use invaders::{Frontend, Headless, SpaceInvaders};
+use machine::Machine;
+use movie::{crc32, Movie};
+use script::Script;
+#[cfg(feature = "window")]
+use spaceinvaders::{GliumFrontend, SpaceInvadersMachine};
+use symbols::Symbols;
 
 enum MachineType {
     SpaceInvaders,
     Cpm,
+    Bare,
 }
 
 enum Options {
     SpaceInvaders {
         filename: String,
+        headless: bool,
+        frames: Option<u64>,
+        screenshot_every: Option<u64>,
+        dir: String,
+        script: Option<String>,
+        record: Option<String>,
+        replay: Option<String>,
     },
     Cpm {
-        filename: String,
+        filename: Option<String>,
+        args: Vec<String>,
+        dir: String,
+        disks: Vec<String>,
+        loads: Vec<String>,
+        start: Option<u16>,
     },
     Disassemble {
         filename: String,
-        offset: usize,
+        offset: u16,
+        follow: bool,
+        entries: Vec<u16>,
+        asm: bool,
+        symbols: Option<String>,
+    },
+    Assemble {
+        filename: String,
+        output: Option<String>,
+        hex: bool,
+        listing: Option<String>,
+        symbols: Option<String>,
     },
     Debug {
         filename: String,
+        machine: MachineType,
+        args: Vec<String>,
+        dir: String,
+        offset: u16,
+        loads: Vec<String>,
+        start: Option<u16>,
+        symbols: Option<String>,
+        gdb: Option<String>,
     },
 }
 
@@ -52,48 +114,237 @@ fn get_opts() -> Options {
         .version("0.1")
         .subcommand(SubCommand::with_name("cpm")
             .arg(Arg::with_name("FILENAME")
-                .required(true)))
+                .help("Program to run, boots from the first disk if not given"))
+            .arg(Arg::with_name("DIR")
+                .short("d")
+                .long("dir")
+                .takes_value(true)
+                .help("Host directory used as drive A"))
+            .arg(Arg::with_name("ARGS")
+                .multiple(true)
+                .help("Command tail passed to the program"))
+            .arg(Arg::with_name("DISK")
+                .long("disk")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("8\" SSSD disk image for the next BIOS drive, starting at A"))
+            .arg(Arg::with_name("LOAD")
+                .long("load")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("FILE@ADDR raw binary or Intel HEX FILE to load into memory after the program"))
+            .arg(Arg::with_name("START")
+                .long("start")
+                .takes_value(true)
+                .help("Hex address to start running at instead of the program's entry point")))
         .subcommand(SubCommand::with_name("dis")
             .arg(Arg::with_name("FILENAME")
                 .required(true))
             .arg(Arg::with_name("OFFSET")
                 .short("o")
                 .long("offset")
-                .takes_value(true)))
+                .takes_value(true)
+                .help("Decimal address the binary is loaded at, defaults to 0"))
+            .arg(Arg::with_name("FOLLOW")
+                .short("f")
+                .long("follow")
+                .help("Follow jumps and calls from the entry points, treating unreached bytes as data"))
+            .arg(Arg::with_name("ENTRY")
+                .short("e")
+                .long("entry")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Hex address to follow from, defaults to 0 and the RST vectors"))
+            .arg(Arg::with_name("ASM")
+                .short("a")
+                .long("asm")
+                .help("Write assembly language source that assembles back to the binary"))
+            .arg(Arg::with_name("SYMBOLS")
+                .short("s")
+                .long("symbols")
+                .takes_value(true)
+                .help("File of names and comments for addresses")))
+        .subcommand(SubCommand::with_name("asm")
+            .arg(Arg::with_name("FILENAME")
+                .required(true))
+            .arg(Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("File to write, defaults to FILENAME with a .bin or .hex extension"))
+            .arg(Arg::with_name("HEX")
+                .long("hex")
+                .help("Write Intel HEX instead of a flat binary"))
+            .arg(Arg::with_name("LISTING")
+                .short("l")
+                .long("listing")
+                .takes_value(true)
+                .help("File to write a listing of addresses, bytes and source lines to"))
+            .arg(Arg::with_name("SYMBOLS")
+                .short("s")
+                .long("symbols")
+                .takes_value(true)
+                .help("File to write labels and EQUs to, in the format dis and debug read")))
         .subcommand(SubCommand::with_name("spaceinvaders")
             .arg(Arg::with_name("FILENAME")
-                .required(true)))
+                .required(true))
+            .arg(Arg::with_name("DIR")
+                .help("Directory to write headless screenshots to, defaults to the current one"))
+            .arg(Arg::with_name("HEADLESS")
+                .long("headless")
+                .requires("FRAMES")
+                .help("Run without a window or sound, writing screenshots"))
+            .arg(Arg::with_name("FRAMES")
+                .long("frames")
+                .takes_value(true)
+                .requires("HEADLESS")
+                .help("Number of frames to run headless, at 60 a second"))
+            .arg(Arg::with_name("SCREENSHOT_EVERY")
+                .long("screenshot-every")
+                .takes_value(true)
+                .requires("HEADLESS")
+                .help("Write a screenshot every this many frames instead of only after the last"))
+            .arg(Arg::with_name("SCRIPT")
+                .long("script")
+                .takes_value(true)
+                .help("File of buttons to press and release at set frames"))
+            .arg(Arg::with_name("RECORD")
+                .long("record")
+                .takes_value(true)
+                .help("Movie file to record every change to the buttons to"))
+            .arg(Arg::with_name("REPLAY")
+                .long("replay")
+                .takes_value(true)
+                .conflicts_with("SCRIPT")
+                .help("Movie file to play back, recorded on the same ROM")))
         .subcommand(SubCommand::with_name("debug")
             .arg(Arg::with_name("FILENAME")
-                .required(true)))
+                .required(true))
+            .arg(Arg::with_name("ARGS")
+                .multiple(true)
+                .help("Command tail passed to a CP/M program"))
+            .arg(Arg::with_name("MACHINE")
+                .short("m")
+                .long("machine")
+                .takes_value(true)
+                .possible_values(&["cpm", "spaceinvaders", "bare"])
+                .help("Machine to run the program on, defaults to cpm"))
+            .arg(Arg::with_name("DIR")
+                .short("d")
+                .long("dir")
+                .takes_value(true)
+                .help("Host directory used as drive A by a CP/M program"))
+            .arg(Arg::with_name("OFFSET")
+                .short("o")
+                .long("offset")
+                .takes_value(true)
+                .help("Hex address to load a raw binary at on the bare machine, defaults to 0000"))
+            .arg(Arg::with_name("LOAD")
+                .long("load")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("FILE@ADDR raw binary or Intel HEX FILE to load into memory after the program"))
+            .arg(Arg::with_name("START")
+                .long("start")
+                .takes_value(true)
+                .help("Hex address to start running at instead of the program's entry point"))
+            .arg(Arg::with_name("SYMBOLS")
+                .short("s")
+                .long("symbols")
+                .takes_value(true)
+                .help("File of names and comments for addresses"))
+            .arg(Arg::with_name("GDB")
+                .long("gdb")
+                .takes_value(true)
+                .help("HOST:PORT to wait for gdb on instead of starting the prompt")))
         .get_matches();
 
     if let Some(sub_matches) = matches.subcommand_matches("spaceinvaders") {
         Options::SpaceInvaders {
             filename: String::from(sub_matches.value_of("FILENAME").unwrap()),
+            headless: sub_matches.is_present("HEADLESS"),
+            frames: sub_matches.value_of("FRAMES")
+                .map(|frames| frames.parse::<u64>().ok().expect("--frames is not a valid number")),
+            screenshot_every: sub_matches.value_of("SCREENSHOT_EVERY")
+                .map(|every| every.parse::<u64>().ok().expect("--screenshot-every is not a valid number")),
+            dir: String::from(sub_matches.value_of("DIR").unwrap_or(".")),
+            script: sub_matches.value_of("SCRIPT").map(String::from),
+            record: sub_matches.value_of("RECORD").map(String::from),
+            replay: sub_matches.value_of("REPLAY").map(String::from),
         }
     } else {
         if let Some(sub_matches) = matches.subcommand_matches("cpm") {
             Options::Cpm {
-                filename: String::from(sub_matches.value_of("FILENAME").unwrap()),
+                filename: sub_matches.value_of("FILENAME").map(String::from),
+                args: sub_matches.values_of("ARGS")
+                    .map(|args| args.map(String::from).collect())
+                    .unwrap_or(Vec::new()),
+                dir: String::from(sub_matches.value_of("DIR").unwrap_or(".")),
+                disks: sub_matches.values_of("DISK")
+                    .map(|disks| disks.map(String::from).collect())
+                    .unwrap_or(Vec::new()),
+                loads: sub_matches.values_of("LOAD")
+                    .map(|loads| loads.map(String::from).collect())
+                    .unwrap_or(Vec::new()),
+                start: sub_matches.value_of("START").map(parse_addr),
             }
         } else {
             if let Some(sub_matches) = matches.subcommand_matches("dis") {
-                let offset = sub_matches.value_of("OFFSET").unwrap_or("0").parse::<usize>().ok().expect("--offset is not valid usize");
+                let offset = sub_matches.value_of("OFFSET").unwrap_or("0").parse::<u16>().ok().expect("--offset is not a valid address");
                 Options::Disassemble {
                     filename: String::from(sub_matches.value_of("FILENAME").unwrap()),
                     offset: offset,
+                    follow: sub_matches.is_present("FOLLOW"),
+                    entries: sub_matches.values_of("ENTRY")
+                        .map(|entries| entries.map(parse_addr).collect())
+                        .unwrap_or(Vec::new()),
+                    asm: sub_matches.is_present("ASM"),
+                    symbols: sub_matches.value_of("SYMBOLS").map(String::from),
+                }
+            } else if let Some(sub_matches) = matches.subcommand_matches("asm") {
+                Options::Assemble {
+                    filename: String::from(sub_matches.value_of("FILENAME").unwrap()),
+                    output: sub_matches.value_of("OUTPUT").map(String::from),
+                    hex: sub_matches.is_present("HEX"),
+                    listing: sub_matches.value_of("LISTING").map(String::from),
+                    symbols: sub_matches.value_of("SYMBOLS").map(String::from),
                 }
             } else {
                 let sub_matches = matches.subcommand_matches("debug").unwrap();
                 Options::Debug {
                     filename: String::from(sub_matches.value_of("FILENAME").unwrap()),
+                    machine: match sub_matches.value_of("MACHINE").unwrap_or("cpm") {
+                        "spaceinvaders" => MachineType::SpaceInvaders,
+                        "bare" => MachineType::Bare,
+                        _ => MachineType::Cpm,
+                    },
+                    args: sub_matches.values_of("ARGS")
+                        .map(|args| args.map(String::from).collect())
+                        .unwrap_or(Vec::new()),
+                    dir: String::from(sub_matches.value_of("DIR").unwrap_or(".")),
+                    offset: parse_addr(sub_matches.value_of("OFFSET").unwrap_or("0")),
+                    loads: sub_matches.values_of("LOAD")
+                        .map(|loads| loads.map(String::from).collect())
+                        .unwrap_or(Vec::new()),
+                    start: sub_matches.value_of("START").map(parse_addr),
+                    symbols: sub_matches.value_of("SYMBOLS").map(String::from),
+                    gdb: sub_matches.value_of("GDB").map(String::from),
                 }
             }
         }
     }
 }
 
+/// Parse a hex address, with or without a leading 0x.
+fn parse_addr(s: &str) -> u16 {
+    let digits = if s.starts_with("0x") { &s[2..] } else { s };
+    u16::from_str_radix(digits, 16).ok().expect("Address is not valid hex")
+}
+
 fn read_file(filename: &str) -> Vec<u8> {
     let mut file = File::open(filename).unwrap();
     let mut buf = Vec::new();
@@ -101,50 +352,334 @@ fn read_file(filename: &str) -> Vec<u8> {
     buf
 }
 
+fn write_file(filename: &Path, bytes: &[u8]) {
+    let mut file = File::create(filename).expect("Could not create file.");
+    file.write_all(bytes).expect("Could not write file.");
+}
+
+/// Read the bytes of `FILE@ADDR` specs, each
+/// with the address they're to be loaded at.
+fn read_loads(specs: &[String]) -> Result<Vec<(u16, Vec<u8>)>, String> {
+    let mut chunks = Vec::new();
+    for spec in specs {
+        chunks.extend(Load::parse(spec)?.read()?);
+    }
+    Ok(chunks)
+}
+
+/// Create a Space Invaders machine for the ROM in `filename`,
+/// with the sounds from the same directory.
+#[cfg(feature = "window")]
+fn spaceinvaders(filename: &str) -> SpaceInvadersMachine {
+    let buf = read_file(filename);
+    let path = PathBuf::from(filename);
+    let dir = path.parent().expect("Given path has no parent directory.");
+
+    let mut sound0 = Sound::new(dir.join("0.wav").to_str().unwrap())
+        .expect("Could not load sound from `0.wav`.");
+    sound0.set_looping(true);
+    let sound1 = Sound::new(dir.join("1.wav").to_str().unwrap())
+        .expect("Could not load sound from `1.wav`.");
+    let sound2 = Sound::new(dir.join("2.wav").to_str().unwrap())
+        .expect("Could not load sound from `2.wav`.");
+    let sound3 = Sound::new(dir.join("3.wav").to_str().unwrap())
+        .expect("Could not load sound from `3.wav`.");
+    let sound4 = Sound::new(dir.join("4.wav").to_str().unwrap())
+        .expect("Could not load sound from `4.wav`.");
+    let sound5 = Sound::new(dir.join("5.wav").to_str().unwrap())
+        .expect("Could not load sound from `5.wav`.");
+    let sound6 = Sound::new(dir.join("6.wav").to_str().unwrap())
+        .expect("Could not load sound from `6.wav`.");
+    let sound7 = Sound::new(dir.join("7.wav").to_str().unwrap())
+        .expect("Could not load sound from `7.wav`.");
+    let sound8 = Sound::new(dir.join("8.wav").to_str().unwrap())
+        .expect("Could not load sound from `8.wav`.");
+
+    SpaceInvadersMachine::new(&buf, GliumFrontend::new(
+        sound0, sound1, sound2, sound3, sound4,
+        sound5, sound6, sound7, sound8))
+}
+
+/// Read an input script for Space Invaders.
+fn read_script(filename: &str) -> Script {
+    Script::load(Path::new(filename)).expect("Could not read input script.")
+}
+
+/// Read a movie and check it was recorded on `rom`.
+fn read_movie(filename: &str, rom: &[u8]) -> Result<Movie, String> {
+    let movie = Movie::load(Path::new(filename)).map_err(|e| format!("{}: {}", filename, e))?;
+    movie.check_rom(rom).map_err(|e| format!("{}: {}", filename, e))?;
+    Ok(movie)
+}
+
+/// Play `script`, or replay it, and record if
+/// `record` is given, as the machine runs.
+fn start<F: Frontend>(machine: &mut SpaceInvaders<F>, script: Option<Script>, replaying: bool, record: bool) {
+    match script {
+        Some(script) if replaying => machine.replay(script),
+        Some(script) => machine.set_script(script),
+        None => {},
+    }
+    if record {
+        machine.start_recording();
+    }
+}
+
+/// Play Space Invaders in a window until it's closed.
+#[cfg(feature = "window")]
+fn windowed(filename: &str, rom: &[u8], script: Option<Script>, replaying: bool, record: Option<String>) {
+    let mut machine = spaceinvaders(filename);
+    start(&mut machine, script, replaying, record.is_some());
+    machine.run();
+    if let Some(record) = record {
+        write_movie(&mut machine, rom, &record);
+    }
+}
+
+#[cfg(not(feature = "window"))]
+fn windowed(_: &str, _: &[u8], _: Option<Script>, _: bool, _: Option<String>) {
+    eprintln!("Built without the window feature, only --headless runs are possible.");
+    process::exit(1);
+}
+
+/// Save what's been recorded as a movie of `rom`.
+fn write_movie<F: Frontend>(machine: &mut SpaceInvaders<F>, rom: &[u8], filename: &str) {
+    if let Some(script) = machine.take_recording() {
+        let movie = Movie { rom: crc32(rom), script: script };
+        write_file(Path::new(filename), format!("{}", movie).as_bytes());
+    }
+}
+
+/// Run Space Invaders without a window for `frames` frames,
+/// writing a screenshot into `dir` every `every` frames, or
+/// only after the last, named by frame number. Prints the
+/// CRC-32 of RAM at the end so runs can be compared.
+fn headless(machine: &mut SpaceInvaders<Headless>, frames: u64, every: Option<u64>, dir: &str) {
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir).expect("Could not create screenshot directory.");
+    for _ in 0..frames {
+        machine.run_frame();
+        let frame = machine.core.frames();
+        if every.map_or(frame == frames, |every| every > 0 && frame % every == 0) {
+            let path = dir.join(format!("frame{:06}.ppm", frame));
+            write_file(&path, &machine.core.screenshot());
+        }
+    }
+    let ram: Vec<u8> = (0x2000..0x4000).map(|addr| machine.read(addr)).collect();
+    println!("RAM CRC-32 after {} frames: {:08x}", frames, crc32(&ram));
+}
+
+/// Run the interactive debugger, naming addresses from the
+/// `symbols` file if given, or serve gdb on the `gdb` address.
+fn debug<T: Machine>(machine: T, symbols: Option<String>, gdb: Option<String>) {
+    if let Some(addr) = gdb {
+        if let Err(e) = gdb::serve(machine, &addr) {
+            eprintln!("gdb: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let mut debugger = Debugger::new(machine);
+    if let Some(symbols) = symbols {
+        debugger.set_symbols(Symbols::load(Path::new(&symbols)).expect("Could not read symbol file."));
+    }
+    debugger.run();
+}
+
 fn main() {
     let options = get_opts();
 
     match options {
-        Options::SpaceInvaders { filename } => {
-            let buf = read_file(&filename);
-            let path = PathBuf::from(filename);
-            let dir = path.parent().expect("Given path has no parent directory.");
-
-            let mut sound0 = Sound::new(dir.join("0.wav").to_str().unwrap())
-                .expect("Could not load sound from `0.wav`.");
-            sound0.set_looping(true);
-            let sound1 = Sound::new(dir.join("1.wav").to_str().unwrap())
-                .expect("Could not load sound from `1.wav`.");
-            let sound2 = Sound::new(dir.join("2.wav").to_str().unwrap())
-                .expect("Could not load sound from `2.wav`.");
-            let sound3 = Sound::new(dir.join("3.wav").to_str().unwrap())
-                .expect("Could not load sound from `3.wav`.");
-            let sound4 = Sound::new(dir.join("4.wav").to_str().unwrap())
-                .expect("Could not load sound from `4.wav`.");
-            let sound5 = Sound::new(dir.join("5.wav").to_str().unwrap())
-                .expect("Could not load sound from `5.wav`.");
-            let sound6 = Sound::new(dir.join("6.wav").to_str().unwrap())
-                .expect("Could not load sound from `6.wav`.");
-            let sound7 = Sound::new(dir.join("7.wav").to_str().unwrap())
-                .expect("Could not load sound from `7.wav`.");
-            let sound8 = Sound::new(dir.join("8.wav").to_str().unwrap())
-                .expect("Could not load sound from `8.wav`.");
-
-            let mut machine = SpaceInvadersMachine::new(&buf,
-                sound0, sound1, sound2, sound3, sound4,
-                sound5, sound6, sound7, sound8);
-            machine.run();
+        Options::SpaceInvaders { filename, headless: is_headless, frames, screenshot_every, dir, script, record, replay } => {
+            let rom = read_file(&filename);
+            let replaying = replay.is_some();
+            let script = match (script, replay) {
+                (Some(script), _) => Some(read_script(&script)),
+                (None, Some(replay)) => match read_movie(&replay, &rom) {
+                    Ok(movie) => Some(movie.script),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    },
+                },
+                (None, None) => None,
+            };
+            if is_headless {
+                let mut machine = SpaceInvaders::new(&rom, Headless);
+                start(&mut machine, script, replaying, record.is_some());
+                headless(&mut machine, frames.unwrap(), screenshot_every, &dir);
+                if let Some(record) = record {
+                    write_movie(&mut machine, &rom, &record);
+                }
+            } else {
+                windowed(&filename, &rom, script, replaying, record);
+            }
         }
-        Options::Cpm { filename } => {
-            let mut buf = read_file(&filename);
-            let mut machine = cpm::Cpm::new(&buf);
-            machine.cpu.pc = 0x100;
+        Options::Cpm { filename, args, dir, disks, mut loads, start } => {
+            let disks: Vec<Disk> = disks.iter()
+                .map(|path| Disk::open(Path::new(path)).expect("Could not open disk image."))
+                .collect();
+            let mut machine = match filename {
+                Some(filename) => {
+                    // Intel HEX programs are loaded at their
+                    // own addresses rather than in the TPA
+                    let buf = if image::is_hex(Path::new(&filename)) {
+                        loads.insert(0, filename);
+                        Vec::new()
+                    } else {
+                        read_file(&filename)
+                    };
+                    let mut machine = cpm::Cpm::new(&buf, &args, Path::new(&dir));
+                    for (drive, disk) in disks.into_iter().enumerate() {
+                        if let Err(e) = machine.mount(drive, disk) {
+                            eprintln!("{}", e);
+                            process::exit(1);
+                        }
+                    }
+                    machine
+                },
+                None => {
+                    if disks.is_empty() {
+                        eprintln!("A program to run or a --disk to boot from is required.");
+                        process::exit(1);
+                    }
+                    match cpm::Cpm::boot(disks) {
+                        Ok(machine) => machine,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            process::exit(1);
+                        },
+                    }
+                },
+            };
+            match read_loads(&loads) {
+                Ok(chunks) => {
+                    for (addr, bytes) in chunks {
+                        machine.load(addr, &bytes);
+                    }
+                },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                },
+            }
+            if let Some(start) = start {
+                machine.cpu.pc = start;
+            }
             machine.run();
         },
-        Options::Disassemble { filename, offset } => {
-            let buf = read_file(&filename);
-            disassemble(&buf, offset);
+        Options::Disassemble { filename, offset, follow, entries, asm, symbols } => {
+            let (offset, buf) = if image::is_hex(Path::new(&filename)) {
+                match read_loads(&[filename]) {
+                    Ok(chunks) => image::flatten(&chunks),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    },
+                }
+            } else {
+                (offset, read_file(&filename))
+            };
+            let mut listing = if follow {
+                let entries = if entries.is_empty() {
+                    default_entries(buf.len(), offset)
+                } else {
+                    entries
+                };
+                disassemble_from(&buf, offset, &entries)
+            } else if asm {
+                Listing::linear(&buf, offset)
+            } else {
+                Listing::new(disassemble(&buf, offset).collect())
+            };
+            if let Some(symbols) = symbols {
+                listing.symbols = Symbols::load(Path::new(&symbols)).expect("Could not read symbol file.");
+            }
+            if asm {
+                print!("{}", listing.to_asm());
+            } else {
+                print!("{}", listing);
+            }
+        },
+        Options::Assemble { filename, output, hex, listing, symbols } => {
+            let assembly = match assemble::assemble_file(Path::new(&filename)) {
+                Ok(assembly) => assembly,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                },
+            };
+            let output = match output {
+                Some(output) => PathBuf::from(output),
+                None => Path::new(&filename).with_extension(if hex { "hex" } else { "bin" }),
+            };
+            if hex {
+                write_file(&output, assembly.hex().as_bytes());
+            } else {
+                write_file(&output, &assembly.binary().1);
+            }
+            if let Some(listing) = listing {
+                write_file(Path::new(&listing), assembly.listing.as_bytes());
+            }
+            if let Some(symbols) = symbols {
+                write_file(Path::new(&symbols), format!("{}", assembly.symbols).as_bytes());
+            }
+        },
+        Options::Debug { filename, machine, args, dir, offset, mut loads, start, symbols, gdb } => {
+            // Intel HEX programs are loaded at their own addresses
+            let buf = if image::is_hex(Path::new(&filename)) {
+                loads.insert(0, filename.clone());
+                Vec::new()
+            } else {
+                read_file(&filename)
+            };
+            let chunks = match read_loads(&loads) {
+                Ok(chunks) => chunks,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                },
+            };
+            match machine {
+                MachineType::Cpm => {
+                    let mut machine = cpm::Cpm::new(&buf, &args, Path::new(&dir));
+                    for (addr, bytes) in chunks {
+                        machine.load(addr, &bytes);
+                    }
+                    if let Some(start) = start {
+                        machine.cpu.pc = start;
+                    }
+                    debug(machine, symbols, gdb);
+                },
+                MachineType::SpaceInvaders => {
+                    if !chunks.is_empty() || start.is_some() {
+                        eprintln!("The Space Invaders ROM is a raw binary loaded at 0000.");
+                        process::exit(1);
+                    }
+                    // Without a window there's no screen or sound
+                    #[cfg(feature = "window")]
+                    debug(spaceinvaders(&filename), symbols, gdb);
+                    #[cfg(not(feature = "window"))]
+                    debug(SpaceInvaders::new(&read_file(&filename), Headless), symbols, gdb);
+                },
+                MachineType::Bare => {
+                    if offset as usize + buf.len() > 0x10000 {
+                        eprintln!("{} doesn't fit in memory at {:04x}", filename, offset);
+                        process::exit(1);
+                    }
+                    // Start at the lowest address of an Intel HEX program
+                    let entry = match chunks.first() {
+                        Some(&(addr, _)) if buf.is_empty() => addr,
+                        _ => offset,
+                    };
+                    let mut machine = Bare::new(&buf, offset);
+                    for (addr, bytes) in chunks {
+                        machine.load(addr, &bytes);
+                    }
+                    machine.cpu.pc = start.unwrap_or(entry);
+                    debug(machine, symbols, gdb);
+                },
+            }
         },
-        Options::Debug { filename } => {},
     }
 }
@@ -1,6 +1,22 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::mem;
+
+/// How a watched address was used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Memory(Vec<u8>);
+pub struct Memory {
+    mem: Vec<u8>,
+    rom_size: usize, // Writes below this address are refused
+    watches: HashSet<u16>,
+    accesses: RefCell<Vec<(u16, Access)>>, // Made to watched addresses
+    journal: Option<Vec<(u16, u8)>>, // Old values of bytes written
+}
 
 impl Memory {
     pub fn with_data(data: &[u8]) -> Self {
@@ -13,21 +29,105 @@ impl Memory {
         for (i, x) in data.iter().enumerate() {
             mem[i + offset] = x.clone();
         }
-        Memory(mem)
+        Memory {
+            mem: mem,
+            rom_size: 0,
+            watches: HashSet::new(),
+            accesses: RefCell::new(Vec::new()),
+            journal: None,
+        }
+    }
+
+    /// Load `data` at address 0 and treat it
+    /// as read-only memory.
+    pub fn with_rom(data: &[u8]) -> Self {
+        let mut memory = Self::with_data(data);
+        memory.rom_size = data.len();
+        memory
+    }
+
+    /// Copy `data` in at `addr`, even over ROM.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        let start = addr as usize;
+        self.mem[start..start + data.len()].copy_from_slice(data);
+    }
+
+    /// Record reads and writes of these addresses,
+    /// including instruction fetches, from now on.
+    pub fn set_watches(&mut self, addrs: &[u16]) {
+        self.watches = addrs.iter().cloned().collect();
+    }
+
+    /// The accesses made to watched addresses
+    /// since this was last called, in order.
+    pub fn take_accesses(&self) -> Vec<(u16, Access)> {
+        mem::replace(&mut *self.accesses.borrow_mut(), Vec::new())
+    }
+
+    /// Keep the old value of every byte written from now
+    /// on, so writes can be undone, or stop keeping them.
+    pub fn set_journal(&mut self, on: bool) {
+        self.journal = if on { Some(Vec::new()) } else { None };
+    }
+
+    /// The addresses written since this was last
+    /// called with the values they had, oldest first.
+    pub fn take_journal(&mut self) -> Vec<(u16, u8)> {
+        match self.journal {
+            Some(ref mut journal) => mem::replace(journal, Vec::new()),
+            None => Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    fn access(&self, addr: u16, access: Access) {
+        if !self.watches.is_empty() && self.watches.contains(&addr) {
+            self.accesses.borrow_mut().push((addr, access));
+        }
     }
 
     #[inline(always)]
     pub fn read(&self, addr: u16) -> u8 {
-        let Memory(ref mem) = *self;
-        mem[addr as usize]
+        self.access(addr, Access::Read);
+        self.mem[addr as usize]
     }
 
     #[inline(always)]
     pub fn write(&mut self, addr: u16, d: u8) {
-        if addr < 0x2000 {
+        if (addr as usize) < self.rom_size {
             panic!("Trying to write {:>0padd$x} to ROM at: {:>0pada$x}", d, addr, padd=2, pada=4);
         }
-        let Memory(ref mut mem) = *self;
-        mem[addr as usize] = d;
+        self.access(addr, Access::Write);
+        if let Some(ref mut journal) = self.journal {
+            journal.push((addr, self.mem[addr as usize]));
+        }
+        self.mem[addr as usize] = d;
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_watches() {
+    let mut memory = Memory::with_data(&[1, 2, 3]);
+    memory.read(0);
+    memory.set_watches(&[1, 2]);
+    memory.read(0);
+    memory.read(1);
+    memory.write(2, 4);
+    memory.load(1, &[5]);
+    assert_eq!(memory.take_accesses(), vec![(1, Access::Read), (2, Access::Write)]);
+    assert_eq!(memory.take_accesses(), vec![]);
+}
+
+#[test]
+fn test_journal() {
+    let mut memory = Memory::with_data(&[1, 2, 3]);
+    memory.write(0, 4);
+    memory.set_journal(true);
+    memory.write(1, 5);
+    memory.write(1, 6);
+    assert_eq!(memory.take_journal(), vec![(1, 2), (1, 5)]);
+    assert_eq!(memory.take_journal(), vec![]);
+    memory.set_journal(false);
+    memory.write(2, 7);
+    assert_eq!(memory.take_journal(), vec![]);
+}
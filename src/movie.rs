@@ -0,0 +1,112 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use script::Script;
+
+/// The CRC-32 of some bytes, as zip and MAME use
+/// to tell ROMs apart.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// A game as it was played: every change to the
+/// buttons with the frame it was made at, and the
+/// ROM it was played on.
+///
+/// Movies are input scripts with a `rom` line first
+/// giving the ROM's CRC-32 in hex:
+///
+/// ```text
+/// rom 1a2b3c4d
+/// frame 120: coin down
+/// frame 125: coin up
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom: u32,
+    pub script: Script,
+}
+
+impl Movie {
+    /// Read a movie file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        Movie::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Parse the text of a movie.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rom = None;
+        // The rest is the script, blank lines keep its line numbers
+        let mut script = String::new();
+        for (n, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+            if rom.is_none() && !words.is_empty() {
+                if words.len() != 2 || words[0] != "rom" {
+                    return Err(format!("line {}: expected rom CRC32: {}", n + 1, line.trim()));
+                }
+                let crc = u32::from_str_radix(words[1], 16)
+                    .map_err(|_| format!("line {}: invalid CRC32: {}", n + 1, words[1]))?;
+                rom = Some(crc);
+                script.push('\n');
+                continue;
+            }
+            script.push_str(line);
+            script.push('\n');
+        }
+        match rom {
+            Some(rom) => Ok(Movie { rom: rom, script: Script::parse(&script)? }),
+            None => Err(String::from("expected rom CRC32")),
+        }
+    }
+
+    /// Is this movie of the game in `rom`?
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), String> {
+        let crc = crc32(rom);
+        if crc != self.rom {
+            return Err(format!("movie was recorded on ROM {:08x}, not {:08x}", self.rom, crc));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rom {:08x}", self.rom)?;
+        write!(f, "{}", self.script)
+    }
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(&[]), 0);
+}
+
+#[test]
+fn test_parse_movie() {
+    let movie = Movie::parse("# Dies on the first wave\nrom cbf43926\nframe 120: coin down\n").unwrap();
+    assert_eq!(movie.rom, 0xcbf4_3926);
+    assert_eq!(movie.script, Script::parse("frame 120: coin down").unwrap());
+    assert!(movie.check_rom(b"123456789").is_ok());
+    assert!(movie.check_rom(b"12345678").is_err());
+    assert_eq!(Movie::parse(&format!("{}", movie)), Ok(movie));
+
+    assert!(Movie::parse("frame 120: coin down").is_err());
+    assert!(Movie::parse("rom xyz").is_err());
+    assert!(Movie::parse("").is_err());
+    assert_eq!(Movie::parse("rom 0\n\nframe 1: fire down").unwrap_err(),
+               "line 3: unknown button: frame 1: fire down");
+}
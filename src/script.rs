@@ -0,0 +1,238 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use invaders::{Button, SpaceInvadersCore};
+
+/// A change to input port bits at the start of a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub frame: u64,
+    pub port: usize,
+    pub bits: u8,
+    pub down: bool,
+}
+
+/// Buttons pressed and released at set frames, to
+/// play the game the same way every time.
+///
+/// Scripts have one change per line or separated by
+/// semicolons, each a frame number then buttons and
+/// whether they go down or up, with comments after
+/// a `#`:
+///
+/// ```text
+/// # Start a one player game
+/// frame 120: coin down; frame 125: coin up
+/// frame 200: start down
+/// frame 205: start up
+/// frame 300: left down, shoot down
+/// ```
+///
+/// Buttons are `coin`, `start`, `shoot`, `left` and
+/// `right` for player 1, the same with a `p2` in front
+/// for player 2 and `tilt`. Any other bit of port 1 or
+/// 2, such as the DIP switches, is given as `port2.3`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script {
+    events: Vec<Event>,
+    next: usize, // The next event to play
+}
+
+const BUTTONS: [(&'static str, Button); 10] = [
+    ("coin", Button::Coin),
+    ("start", Button::P1Start),
+    ("shoot", Button::P1Shoot),
+    ("left", Button::P1Left),
+    ("right", Button::P1Right),
+    ("p2start", Button::P2Start),
+    ("p2shoot", Button::P2Shoot),
+    ("p2left", Button::P2Left),
+    ("p2right", Button::P2Right),
+    ("tilt", Button::Tilt),
+];
+
+/// The port and bits of an input by name.
+fn input(name: &str) -> Option<(usize, u8)> {
+    // Player 1's buttons can be given with a p1 too
+    let name = if name.starts_with("p1") { &name[2..] } else { name };
+    if let Some(&(_, button)) = BUTTONS.iter().find(|&&(button_name, _)| button_name == name) {
+        return Some(button.port_bit());
+    }
+    if !name.starts_with("port") {
+        return None;
+    }
+    let mut parts = name[4..].splitn(2, '.');
+    let port = parts.next().and_then(|port| port.parse::<usize>().ok());
+    let bit = parts.next().and_then(|bit| bit.parse::<u8>().ok());
+    match (port, bit) {
+        (Some(port), Some(bit)) if (1..3).contains(&port) && bit < 8 => Some((port, 1 << bit)),
+        _ => None,
+    }
+}
+
+/// The name of a single bit of an input port.
+fn input_name(port: usize, bit: u8) -> String {
+    match BUTTONS.iter().find(|&&(_, button)| button.port_bit() == (port, bit)) {
+        Some(&(name, _)) => String::from(name),
+        None => format!("port{}.{}", port, bit.trailing_zeros()),
+    }
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Script::default()
+    }
+
+    /// Read a script file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        Script::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Parse the text of a script.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(i) => &line[..i],
+                None => line,
+            };
+            for statement in line.split(';') {
+                let statement = statement.trim();
+                if statement.is_empty() {
+                    continue;
+                }
+                let error = |e: &str| format!("line {}: {}: {}", n + 1, e, statement);
+                let (frame, changes) = match statement.find(':') {
+                    Some(i) => (statement[..i].trim(), &statement[i + 1..]),
+                    None => return Err(error("expected frame N: BUTTON down|up")),
+                };
+                if !frame.starts_with("frame") {
+                    return Err(error("expected frame N: BUTTON down|up"));
+                }
+                let frame = frame[5..].trim().parse::<u64>().map_err(|_| error("invalid frame number"))?;
+                for change in changes.split(',') {
+                    let words: Vec<&str> = change.split_whitespace().collect();
+                    if words.len() != 2 {
+                        return Err(error("expected BUTTON down|up"));
+                    }
+                    let (port, bits) = input(&words[0].to_lowercase()).ok_or_else(|| error("unknown button"))?;
+                    let down = match words[1] {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(error("expected down or up")),
+                    };
+                    events.push(Event { frame: frame, port: port, bits: bits, down: down });
+                }
+            }
+        }
+        // In frame order, keeping the order given within a frame
+        events.sort_by_key(|event| event.frame);
+        Ok(Script { events: events, next: 0 })
+    }
+
+    /// Add the changes from `old` to `new` in an input port,
+    /// a bit at a time, to be made at the start of `frame`.
+    pub fn record(&mut self, frame: u64, port: usize, old: u8, new: u8) {
+        for bit in 0..8 {
+            let bits = 1 << bit;
+            if (old ^ new) & bits != 0 {
+                self.events.push(Event { frame: frame, port: port, bits: bits, down: new & bits != 0 });
+            }
+        }
+    }
+
+    /// How many of the changes have been made.
+    pub fn played(&self) -> usize {
+        self.next
+    }
+
+    /// Go back to when only the first `played`
+    /// changes had been made, or skip forward.
+    pub fn set_played(&mut self, played: usize) {
+        self.next = played;
+    }
+
+    /// Make the changes due by the frame the core is on.
+    pub fn play(&mut self, core: &mut SpaceInvadersCore) {
+        while let Some(&event) = self.events.get(self.next) {
+            if event.frame > core.frames() {
+                break;
+            }
+            core.set_input_bits(event.port, event.bits, event.down);
+            self.next += 1;
+        }
+    }
+}
+
+/// Written back out one change per line.
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for event in &self.events {
+            for bit in 0..8 {
+                if event.bits & (1 << bit) != 0 {
+                    let state = if event.down { "down" } else { "up" };
+                    writeln!(f, "frame {}: {} {}", event.frame, input_name(event.port, 1 << bit), state)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse() {
+    let script = Script::parse("# Start a game\n\
+                                frame 125: coin up; frame 120: Coin down\n\
+                                \n\
+                                frame 300: left down, p2shoot down, port2.3 down # Extra ship at 1000").unwrap();
+    assert_eq!(script.events, vec![
+        Event { frame: 120, port: 1, bits: 0x01, down: true },
+        Event { frame: 125, port: 1, bits: 0x01, down: false },
+        Event { frame: 300, port: 1, bits: 0x20, down: true },
+        Event { frame: 300, port: 2, bits: 0x10, down: true },
+        Event { frame: 300, port: 2, bits: 0x08, down: true },
+    ]);
+    assert!(Script::parse("frame 10: fire down").is_err());
+    assert!(Script::parse("frame ten: coin down").is_err());
+    assert!(Script::parse("frame 10: coin").is_err());
+    assert!(Script::parse("10: coin down").is_err());
+    assert!(Script::parse("frame 10: port3.0 down").is_err());
+    assert_eq!(Script::parse("\nframe 1 coin down").unwrap_err(),
+               "line 2: expected frame N: BUTTON down|up: frame 1 coin down");
+}
+
+#[test]
+fn test_play() {
+    // Loop: JMP Loop
+    let mut core = SpaceInvadersCore::new(&[0xc3, 0x00, 0x00]);
+    let mut script = Script::parse("frame 0: coin down; frame 1: coin up, start down").unwrap();
+    script.play(&mut core);
+    assert_eq!(core.input(1), 0x01);
+    while core.frames() < 1 {
+        core.step();
+    }
+    script.play(&mut core);
+    assert_eq!(core.input(1), 0x04);
+}
+
+#[test]
+fn test_record() {
+    let mut script = Script::new();
+    script.record(0, 1, 0x00, 0x01);
+    script.record(5, 1, 0x01, 0x24);
+    script.record(9, 2, 0x00, 0x88);
+    let text = format!("{}", script);
+    assert_eq!(text, "frame 0: coin down\n\
+                      frame 5: coin up\n\
+                      frame 5: start down\n\
+                      frame 5: left down\n\
+                      frame 9: port2.3 down\n\
+                      frame 9: port2.7 down\n");
+    assert_eq!(Script::parse(&text), Ok(script));
+}
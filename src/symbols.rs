@@ -0,0 +1,179 @@
+use std::collections::{btree_map, BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+/// Names and comments for addresses in a binary.
+///
+/// Symbol files have one address per line, in hex,
+/// followed by an optional name and an optional
+/// comment starting with a semicolon:
+///
+/// ```text
+/// ; Space Invaders
+/// 0000 Reset
+/// 1439 DrawSprite ; Draw the sprite at DE to the screen at HL
+/// 20c0 IsrDelay
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    names: BTreeMap<u16, String>,
+    addrs: HashMap<String, u16>,
+    comments: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols::default()
+    }
+
+    /// Read a symbol file.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        Symbols::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Parse the text of a symbol file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Symbols::new();
+        for (n, line) in text.lines().enumerate() {
+            let (line, comment) = match line.find(';') {
+                Some(i) => (&line[..i], Some(line[i + 1..].trim())),
+                None => (line, None),
+            };
+            let mut words = line.split_whitespace();
+            let addr = match words.next() {
+                Some(word) => {
+                    let digits = if word.starts_with("0x") { &word[2..] } else { word };
+                    match u16::from_str_radix(digits, 16) {
+                        Ok(addr) => addr,
+                        Err(_) => return Err(format!("line {}: invalid address {}", n + 1, word)),
+                    }
+                },
+                // Blank or only a comment
+                None => continue,
+            };
+            if let Some(name) = words.next() {
+                symbols.insert(addr, name);
+            }
+            if words.next().is_some() {
+                return Err(format!("line {}: expected an address and a name", n + 1));
+            }
+            match comment {
+                Some(comment) if !comment.is_empty() => {
+                    symbols.comments.insert(addr, String::from(comment));
+                },
+                _ => {},
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Give `addr` another name. It's shown by
+    /// the first name it was given.
+    pub fn insert(&mut self, addr: u16, name: &str) {
+        if let Some(old) = self.addrs.insert(String::from(name), addr) {
+            if self.names.get(&old).map(|old| old.as_str()) == Some(name) {
+                // Show the old address by another of its names
+                self.names.remove(&old);
+                let alias = self.addrs.iter().find(|&(_, addr)| *addr == old).map(|(alias, _)| alias.clone());
+                if let Some(alias) = alias {
+                    self.names.insert(old, alias);
+                }
+            }
+        }
+        if !self.names.contains_key(&addr) {
+            self.names.insert(addr, String::from(name));
+        }
+    }
+
+    /// The name of `addr`, if it has one.
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(|name| name.as_str())
+    }
+
+    /// The address called `name`, if any.
+    pub fn addr(&self, name: &str) -> Option<u16> {
+        self.addrs.get(name).cloned()
+    }
+
+    /// The comment on `addr`, if it has one.
+    pub fn comment(&self, addr: u16) -> Option<&str> {
+        self.comments.get(&addr).map(|comment| comment.as_str())
+    }
+
+    /// All named addresses in order,
+    /// by the name they're shown with.
+    pub fn iter<'a>(&'a self) -> btree_map::Iter<'a, u16, String> {
+        self.names.iter()
+    }
+}
+
+#[test]
+fn test_parse() {
+    let symbols = Symbols::parse("\
+; Space Invaders
+0000 Reset
+0x1439 DrawSprite ; Draw a sprite
+
+20c0 ; Counts down every interrupt
+").unwrap();
+    assert_eq!(symbols.name(0x0000), Some("Reset"));
+    assert_eq!(symbols.addr("DrawSprite"), Some(0x1439));
+    assert_eq!(symbols.comment(0x1439), Some("Draw a sprite"));
+    assert_eq!(symbols.name(0x20c0), None);
+    assert_eq!(symbols.comment(0x20c0), Some("Counts down every interrupt"));
+    assert_eq!(symbols.iter().count(), 2);
+
+    assert!(Symbols::parse("zzzz Nowhere").is_err());
+    assert!(Symbols::parse("0000 Two Names").is_err());
+}
+
+/// Writes the symbol file format.
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<(u16, &str)> = self.addrs.iter()
+            .map(|(name, addr)| (*addr, name.as_str()))
+            .collect();
+        names.sort();
+        for (addr, name) in names {
+            match self.comment(addr) {
+                Some(comment) if self.name(addr) == Some(name) => {
+                    writeln!(f, "{:04x} {} ; {}", addr, name, comment)?;
+                },
+                _ => writeln!(f, "{:04x} {}", addr, name)?,
+            }
+        }
+        for (addr, comment) in self.comments.iter() {
+            if self.name(*addr).is_none() {
+                writeln!(f, "{:04x} ; {}", addr, comment)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_aliases() {
+    let mut symbols = Symbols::new();
+    symbols.insert(0x100, "Start");
+    symbols.insert(0x100, "Main");
+    assert_eq!(symbols.name(0x100), Some("Start"));
+    assert_eq!(symbols.addr("Main"), Some(0x100));
+    // Moving a name
+    symbols.insert(0x200, "Start");
+    assert_eq!(symbols.name(0x100), Some("Main"));
+    assert_eq!(symbols.addr("Start"), Some(0x200));
+}
+
+#[test]
+fn test_write() {
+    let text = "0000 Reset\n1439 DrawSprite ; Draw a sprite\n20c0 ; Counts down\n";
+    let symbols = Symbols::parse(text).unwrap();
+    assert_eq!(format!("{}", symbols), text);
+    assert_eq!(Symbols::parse(&format!("{}", symbols)).unwrap(), symbols);
+}